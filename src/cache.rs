@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use clap::crate_name;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// File under `$XDG_CACHE_HOME/twm/` with the workspaces found in each search path on previous runs.
+const CACHE_FILE_NAME: &str = "workspaces";
+
+/// A workspace found in a search path on a previous run.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CachedWorkspace {
+    pub path: String,
+    pub workspace_type: Option<String>,
+    /// Modification time of the workspace directory when it was found, in seconds since the Unix epoch.
+    pub modified: Option<u64>,
+}
+
+impl CachedWorkspace {
+    pub fn new(path: &str, workspace_type: Option<&str>) -> Self {
+        Self {
+            path: path.to_owned(),
+            workspace_type: workspace_type.map(String::from),
+            modified: modified_secs(Path::new(path)),
+        }
+    }
+
+    /// Whether the workspace directory was modified since it was found, in which case it may not be the same type of
+    /// workspace anymore.
+    pub fn is_modified(&self) -> bool {
+        modified_secs(Path::new(&self.path)) != self.modified
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CachedSearchPath {
+    /// When the search path was searched, in seconds since the Unix epoch.
+    searched_at: u64,
+    workspaces: Vec<CachedWorkspace>,
+}
+
+/// The workspaces found in each search path on previous runs, listed right away on the next run while the search
+/// paths are searched again, if `cache_ttl` is set.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct WorkspaceCache {
+    search_paths: HashMap<String, CachedSearchPath>,
+}
+
+impl WorkspaceCache {
+    /// Reads the cache, which is empty if it was never written or can't be read.
+    pub fn load() -> Self {
+        xdg::BaseDirectories::with_prefix(crate_name!())
+            .ok()
+            .and_then(|xdg_dirs| xdg_dirs.find_cache_file(CACHE_FILE_NAME))
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let xdg_dirs = xdg::BaseDirectories::with_prefix(crate_name!())?;
+        let cache_path = xdg_dirs
+            .place_cache_file(CACHE_FILE_NAME)
+            .context("Failed to create the twm cache directory")?;
+        std::fs::write(&cache_path, serde_json::to_string(self)?)
+            .with_context(|| format!("Failed to write {}", cache_path.display()))
+    }
+
+    /// Gets the workspaces cached for `search_path`, unless it was last searched more than `ttl` before `now`.
+    pub fn get(
+        &self,
+        search_path: &str,
+        ttl: Duration,
+        now: SystemTime,
+    ) -> Option<&[CachedWorkspace]> {
+        let cached = self.search_paths.get(search_path)?;
+        let searched_at = UNIX_EPOCH + Duration::from_secs(cached.searched_at);
+        let age = now.duration_since(searched_at).unwrap_or_default();
+        (age <= ttl).then_some(cached.workspaces.as_slice())
+    }
+
+    /// Replaces the workspaces cached for `search_path` with the ones found searching it at `now`.
+    pub fn insert(&mut self, search_path: &str, workspaces: Vec<CachedWorkspace>, now: SystemTime) {
+        let searched_at = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.search_paths.insert(
+            search_path.to_owned(),
+            CachedSearchPath {
+                searched_at,
+                workspaces,
+            },
+        );
+    }
+}
+
+fn modified_secs(path: &Path) -> Option<u64> {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cached_search_paths_expire() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let ttl = Duration::from_secs(60);
+        let mut cache = WorkspaceCache::default();
+        let workspace = CachedWorkspace {
+            path: "/src/foo".into(),
+            workspace_type: Some("rust".into()),
+            modified: None,
+        };
+        cache.insert("/src", vec![workspace.clone()], start);
+        assert_eq!(
+            cache.get("/src", ttl, start + ttl),
+            Some([workspace].as_slice())
+        );
+        assert_eq!(
+            cache.get("/src", ttl, start + ttl + Duration::from_secs(1)),
+            None
+        );
+        assert_eq!(cache.get("/other", ttl, start), None);
+    }
+}
@@ -1,58 +1,286 @@
 use crate::{
+    config::TwmGlobal,
+    error::Error,
     handler::{
-        handle_existing_session_selection, handle_group_session_selection,
-        handle_make_default_config, handle_make_default_layout_config,
-        handle_print_bash_completions, handle_print_config_schema, handle_print_fish_completions,
-        handle_print_layout_config_schema, handle_print_man, handle_print_zsh_completions,
-        handle_workspace_selection,
+        handle_attach, handle_check_config, handle_clone, handle_complete, handle_count,
+        handle_daemon, handle_existing_session_selection, handle_first_workspace,
+        handle_group_cycle, handle_group_session_selection, handle_kill_session_selection,
+        handle_last_session, handle_list_sessions, handle_make_default_config,
+        handle_make_default_layout_config, handle_print_bash_completions,
+        handle_print_config_schema, handle_print_fish_completions,
+        handle_print_layout_config_schema, handle_print_man, handle_print_workspace_type,
+        handle_print_zsh_completions, handle_reload_layout, handle_scratch, handle_sessions_file,
+        handle_workspace_query, handle_workspace_selection, WorkspaceQuery,
     },
     ui::Tui,
 };
 use anyhow::Result;
+use std::process::ExitCode;
 
-use clap::Parser;
+use clap::{ArgGroup, Parser, ValueEnum};
 
-#[derive(Parser, Default, Debug)]
-#[clap(author = "Vinny Meller", version)]
+/// Setting this environment variable to `0` or `1` overrides the `follow_links` configuration option.
+const FOLLOW_LINKS_ENV_VAR: &str = "TWM_FOLLOW_LINKS";
+
+/// Values that the shell completion scripts ask twm for at completion time.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum CompletionKind {
+    /// Names of the globally-defined layouts.
+    Layouts,
+    /// Names of the existing tmux sessions.
+    Sessions,
+    /// Names of the configured profiles.
+    Profiles,
+}
+
+/// Common invocations listed at the end of `--help`.
+const EXAMPLES: &str = "\
+Examples:
+  twm                        Pick a workspace to open
+  twm api                    Open the workspace best matching `api`, or pick among the matches
+  twm -l                     Pick a workspace, then pick a layout to open it with
+  twm -p ~/dev/twm --layout=rust
+                             Open a directory as a workspace with the `rust` layout
+  twm -e                     Pick an existing session to attach to
+  twm -g                     Pick an existing session to start a new session in its group
+  twm --kill                 Pick existing sessions to kill
+  twm --last                 Jump back to the session twm attached to before this one
+  twm --first api -d         Open the best match for `api` in the background without attaching
+  twm api -c 'git pull' --command-window
+                             Open the best match for `api` and run `git pull` in a new window of it
+  twm --dry-run -l           Pick a workspace and layout, printing the tmux commands instead of running them
+  twm --clone https://github.com/vinnymeller/twm
+                             Clone a repository into the first search path and open it
+
+Press `?` in the picker to show its keybindings.";
+
+#[derive(Parser, Default, Debug, Clone)]
+#[clap(author = "Vinny Meller", version, after_long_help = EXAMPLES)]
+#[clap(group(
+    ArgGroup::new("json_output")
+        .args(["print_workspace_type", "list_sessions"])
+        .multiple(true)
+))]
 /// twm (tmux workspace manager) is a customizable tool for managing workspaces in tmux sessions.
 ///
 /// Workspaces are defined as a directory matching any workspace pattern from your configuration. If no configuration is set, any directory containing a `.git` file/folder or a `.twm.yaml` file is considered a workspace.
 pub struct Arguments {
+    /// Fuzzy search query for the workspace to open.
+    ///
+    /// If a single workspace clearly matches the query best, it is opened right away without showing the picker.
+    /// Otherwise the picker is opened with the query already filled in.
+    pub query: Option<String>,
+
+    #[clap(long)]
+    /// Open the workspace the picker would list first for the search query right away, without showing the picker.
+    ///
+    /// Without a query, the first workspace the picker would list is opened, favorites first and then by `sort_by`. Exits with an error if no workspace matches.
+    pub first: bool,
+
+    #[clap(long)]
+    /// Always show the picker, even if the search query only clearly matches one workspace.
+    pub force_picker: bool,
+
     #[clap(short, long)]
     /// Prompt user to select an existing tmux session to attach to.
     ///
     /// This shouldn't be used with other options.
     pub existing: bool,
 
+    #[clap(long)]
+    /// Prompt user to select existing tmux sessions to kill.
+    ///
+    /// Several sessions can be marked with Tab to kill them all at once. Nothing is attached to afterwards.
+    pub kill: bool,
+
+    #[clap(long, requires = "kill")]
+    /// Kill sessions selected with `--kill` even if the `pre_kill` hook fails for them.
+    pub force: bool,
+
     #[clap(short, long)]
     /// Prompt user to start a new session in the same group as an existing session.
     ///
     /// Setting this option will cause `-l/--layout` and `-p/--path` to be ignored.
     pub group: bool,
 
+    #[clap(long, value_name = "NAME")]
+    /// Attach to the existing session with the given name without showing a picker.
+    ///
+    /// The name must match the session name exactly. Unlike names passed to `-n/--name`, it isn't sanitized.
+    /// Exits with an error if no such session exists.
+    pub attach: Option<String>,
+
+    #[clap(long)]
+    /// Attach to the session twm attached to most recently without showing a picker.
+    ///
+    /// Inside tmux, this is the session twm attached to before the current one, so running it again bounces back.
+    /// Falls back to the workspace picker if there is no such session anymore.
+    pub last: bool,
+
+    #[clap(long, value_name = "GROUP")]
+    /// Switch to the next session in the given session group, wrapping around after the last one.
+    ///
+    /// Sessions opened with twm are in a group named after the session, so this cycles through the sessions created for a workspace with `-g/--group`.
+    /// If the current session isn't in the group, the first session in the group is used.
+    pub group_cycle: Option<String>,
+
+    #[clap(long)]
+    /// Re-read the local layout of the current workspace and run its commands in the current session.
+    ///
+    /// Must be run inside a session opened with twm, whose `TWM_ROOT` is used to find the `.twm.yaml` or `.twm-layout` file.
+    /// Useful for trying out changes to a local layout without opening the workspace again.
+    pub reload_layout: bool,
+
     #[clap(short, long)]
     /// Don't attach to the workspace session after opening it.
     pub dont_attach: bool,
 
-    #[clap(short, long)]
+    #[clap(long)]
+    /// Detach every other client from the session when attaching to it, like `tmux attach -d`.
+    ///
+    /// Avoids tmux resizing the session's windows back and forth between terminals of different sizes. Sessions twm just created have no other clients, so this only matters when attaching to an existing one.
+    pub detach_others: bool,
+
+    #[clap(short, long, value_name = "NAME", num_args = 0..=1, require_equals = true)]
     /// Prompt user to select a globally-defined layout to open the workspace with.
     ///
+    /// Several layouts can be marked with Tab in the prompt to combine them for this session. Their commands are run in the order the layouts were marked,
+    /// and commands in more than one of them run once for each.
+    ///
+    /// A layout can also be given directly with `--layout=<NAME>` to skip the prompt.
     /// Using this option will override any other layout definitions that would otherwise automatically be used when opening the workspace.
-    pub layout: bool,
+    pub layout: Option<Option<String>>,
+
+    #[clap(short, long, value_name = "COMMAND")]
+    /// Run the given command in the workspace's session after opening it.
+    ///
+    /// The command is typed into the active pane of the session's current window after the layout's commands, whether or not a layout is applied. With `layout_command_timeout` set, it waits for them like they wait for each other.
+    /// It's also run when the session already exists, in which case whatever runs in that pane gets it as input. Ignored with `-g/--group`.
+    pub command: Option<String>,
+
+    #[clap(long, requires = "command")]
+    /// Run the command given with `-c/--command` in a new window of the session instead, leaving the layout's windows as they are.
+    ///
+    /// The new window starts in the workspace directory and becomes the session's current window.
+    pub command_window: bool,
 
     #[clap(short, long)]
     /// Open the given path as a workspace.
     ///
     /// Using this option does not require that the path be a valid workspace according to your configuration.
+    /// The path can start with a `<shorthand>:` prefix defined in the `project_roots` configuration option, e.g. `work:service-a`.
     pub path: Option<String>,
 
+    #[clap(long, value_name = "SESSION")]
+    /// Open the workspace in a new window of the given existing session instead of in its own session.
+    ///
+    /// The workspace's layout is built in the new window, which becomes the session's current window, and its `TWM_*` environment variables are set for that window only.
+    /// The session itself is left as it was, so twm still opens the workspace in its own session later. Unlike `-g/--group`, the windows aren't shared with any other session.
+    pub into: Option<String>,
+
+    #[clap(long)]
+    /// Open a new session in a fresh temporary directory.
+    ///
+    /// The directory is created in the `scratch_dir` configuration option, or the system temporary directory if unset, and isn't removed when the session ends.
+    /// The session is named `scratch-<timestamp>` unless `-n/--name` is given, and opened with the `scratch_layout` configuration option unless `-l/--layout` is given.
+    pub scratch: bool,
+
+    #[clap(long = "clone", value_name = "URL")]
+    /// Clone the git repository at the given URL and open it as a workspace.
+    ///
+    /// The repository is cloned with `git clone` into the `clone_root` configuration option, or the first search path if unset, in a directory named after the last part of the URL.
+    /// If that directory already exists, it's opened without cloning anything.
+    pub clone_url: Option<String>,
+
+    #[clap(long, value_name = "PATH")]
+    /// Prompt user to select one of the sessions listed in the given file to open.
+    ///
+    /// The file is either a twm sessions file, with a `sessions` list of entries with a `path` and an optional `name` and `layout`,
+    /// or a tmuxinator project file, of which only `name` and `root` are used. A `name` or `layout` in the file takes precedence over `-n/--name` and `-l/--layout`.
+    pub sessions_file: Option<String>,
+
+    #[clap(long, requires = "sessions_file")]
+    /// Open every session in the file given with `--sessions-file` instead of prompting for one.
+    ///
+    /// twm attaches to the first session in the file afterwards, unless `-d/--dont-attach` is given.
+    pub all: bool,
+
+    #[clap(long, value_name = "SCOPE")]
+    /// Only search for workspaces in one of the configured search paths.
+    ///
+    /// The search path can be given either by its index in `search_paths`, starting from 0, or by a prefix that matches exactly one search path.
+    pub scope: Option<String>,
+
+    #[clap(long)]
+    /// Don't follow symlinks when searching for workspaces, regardless of the `follow_links` configuration option.
+    ///
+    /// Useful for avoiding a symlink loop in a single run. Setting the `TWM_FOLLOW_LINKS` environment variable to `0` or `1` also overrides the configuration option.
+    pub no_follow_links: bool,
+
+    #[clap(long)]
+    /// Don't use or update the workspace cache, regardless of the `cache_ttl` configuration option.
+    ///
+    /// Every search path is searched before its workspaces are listed, as without a cache.
+    pub no_cache: bool,
+
+    #[clap(long)]
+    /// Print the selected workspace's path to stdout instead of opening it.
+    ///
+    /// Nothing is done in tmux, so this can be used to pick a workspace directory for other tools, e.g. `cd "$(twm --print-path)"`.
+    pub print_path: bool,
+
+    #[clap(long)]
+    /// Print the commands of the layout the selected workspace would be opened with to stdout instead of opening it.
+    ///
+    /// The layout is chosen the same way as when opening the workspace, and inherited commands are included in the order they'd be run.
+    /// Nothing is done in tmux, so this can be used to debug layouts, e.g. `twm --show-layout -p ~/projects/foo`.
+    pub show_layout: bool,
+
+    #[clap(long)]
+    /// Print the tmux commands that would be run to stdout instead of running them.
+    ///
+    /// The directories `--scratch` and `--clone` would create aren't created either, and the commands creating them are printed instead.
+    ///
+    /// Pickers are still shown and tmux is still asked which sessions exist, so the commands are the ones twm would run right now.
+    /// Useful for checking what a layout or workspace definition does before trying it, e.g. `twm --dry-run -p ~/projects/foo`.
+    pub dry_run: bool,
+
+    #[clap(long)]
+    /// Print the number of workspaces found to stdout instead of showing a picker.
+    ///
+    /// Only workspaces matching `QUERY` are counted if one is given. Combined with `--scope`, this answers how many workspaces a single search path has,
+    /// e.g. `twm --count --scope ~/work`.
+    pub count: bool,
+
     #[clap(short, long)]
     /// Force the workspace to be opened with the given name.
     ///
+    /// The name can contain `{basename}` and `{parent}`, which are replaced with the last two components of the workspace path, `{type}`, which is replaced with the workspace type, and `{git_branch}`, which is replaced with the checked out git branch.
+    /// e.g. `twm -p ~/projects/foo -n "review-{basename}"` opens a session named `review-foo`. Placeholders aren't expanded with `-g/--group`.
+    ///
     /// When setting this option, you should be aware that twm will not "see" this session when performing other automatic actions.
     /// For example, if you have a workspace at ~/foobar and run `twm -n jimbob -p ~/foobar`, and then run `twm` and select `~/foobar` from the picker, a new session `foobar` will be created. If you then run `twm -g` and select `foobar`, `foobar-1` will be created in the `foobar` group.
     pub name: Option<String>,
 
+    #[clap(long, value_name = "NAME")]
+    /// Apply the configuration profile with the given name from the `profiles` configuration option.
+    ///
+    /// Options set in the profile replace the ones set in the rest of the configuration file.
+    pub profile: Option<String>,
+
+    #[clap(long)]
+    /// Run in the background, keeping an index of your workspaces up to date as files change.
+    ///
+    /// While the daemon is running, twm gets workspaces from its index instead of searching the search paths every time, which is much faster for large directory trees.
+    /// twm falls back to searching normally if the daemon isn't running, or if it was started with a different configuration, e.g. another `--profile`. Restart the daemon after changing your configuration.
+    pub daemon: bool,
+
+    #[clap(long)]
+    /// Exit successfully when a picker is closed without selecting anything.
+    ///
+    /// By default, closing a picker without a selection exits with code 130 and no error message, so it can be told apart from actual errors.
+    pub exit_zero_on_abort: bool,
+
     #[clap(long)]
     /// Make default configuration file.
     ///
@@ -68,6 +296,17 @@ pub struct Arguments {
     /// You can use `-p/--path <PATH>` to specify a different directory to write the file to.
     pub make_default_layout_config: bool,
 
+    #[clap(long)]
+    /// Check the configuration file for problems without running anything.
+    ///
+    /// Checks the configuration file found as normal, or the file given with `--config-file`. Unlike normal runs, a missing configuration file is an error rather than falling back to the defaults.
+    /// Every problem found is printed, including undefined or cyclic layout references and problems in any profile, and twm exits with an error if there are any.
+    pub check_config: bool,
+
+    #[clap(long, value_name = "FILE", requires = "check_config")]
+    /// Check this configuration file with `--check-config` instead of the one found as normal.
+    pub config_file: Option<String>,
+
     #[clap(long)]
     /// Print the configuration file (twm.yaml) schema.
     ///
@@ -80,6 +319,33 @@ pub struct Arguments {
     /// This can be used with tools (e.g. language servers) to provide autocompletion and validation when editing your configuration.
     pub print_layout_config_schema: bool,
 
+    #[clap(long, value_name = "PATH")]
+    /// Print the workspace type matching the given path to stdout.
+    ///
+    /// Exits with an error if the path doesn't match any workspace definition. Useful for shell prompts and scripts.
+    pub print_workspace_type: Option<String>,
+
+    #[clap(long)]
+    /// Print the names of all tmux sessions to stdout, one per line, in the order they're shown in the session picker.
+    pub list_sessions: bool,
+
+    #[clap(long, requires = "list_sessions")]
+    /// Only list sessions created by twm with `--list-sessions`, leaving out manually created ones.
+    ///
+    /// Sessions created by twm are the ones with the `TWM` environment variable set, so this can be used to e.g. only clean up twm sessions in a script.
+    pub twm_only: bool,
+
+    #[clap(long, requires = "json_output")]
+    /// Print output as JSON.
+    ///
+    /// Used with `--print-workspace-type` to also print the workspace definition that matched, including its conditions.
+    /// Used with `--list-sessions` to also print whether each session was created by twm, and its workspace path and type if so.
+    pub json: bool,
+
+    #[clap(long, value_name = "KIND", hide = true)]
+    /// Print the current values of the given kind, one per line, for dynamic shell completions.
+    pub complete: Option<CompletionKind>,
+
     #[clap(long)]
     /// Print bash completions to stdout
     pub print_bash_completion: bool,
@@ -97,11 +363,31 @@ pub struct Arguments {
     pub print_man: bool,
 }
 
+/// Exit code used when a picker is closed without selecting anything, like a shell command interrupted by Ctrl+C.
+const ABORT_EXIT_CODE: u8 = 130;
+
 /// Parses the command line arguments and runs the program. Called from `main.rs`.
 /// Since not every command needs a TUI, we start one up as necessary in each handler that needs one.
-pub fn parse() -> Result<()> {
+pub fn parse() -> Result<ExitCode> {
     let args = Arguments::parse();
+    let exit_zero_on_abort = args.exit_zero_on_abort;
+    match run(args) {
+        Ok(()) => Ok(ExitCode::SUCCESS),
+        Err(Error::NoSelection(_)) => match exit_zero_on_abort {
+            true => Ok(ExitCode::SUCCESS),
+            false => Ok(ExitCode::from(ABORT_EXIT_CODE)),
+        },
+        Err(e) => Err(e.into_anyhow()),
+    }
+}
+
+/// Runs twm with already parsed arguments, like `parse` does with the command line's. Failures are sorted into the
+/// variants of `twm::Error` here, so everything below can just use `anyhow`.
+pub fn run(args: Arguments) -> crate::Result<()> {
+    Ok(dispatch(args)?)
+}
 
+fn dispatch(args: Arguments) -> Result<()> {
     // This kind of matching couuld be avoided by using subcommands but I just generally like flags better.
     // Who's going to try running `twm --group --print-man --print-config-schema` anyways? grow up
     match args {
@@ -113,6 +399,9 @@ pub fn parse() -> Result<()> {
             make_default_layout_config: true,
             ..
         } => handle_make_default_layout_config(&args),
+        Arguments {
+            check_config: true, ..
+        } => handle_check_config(&args),
         Arguments {
             print_config_schema: true,
             ..
@@ -121,6 +410,31 @@ pub fn parse() -> Result<()> {
             print_layout_config_schema: true,
             ..
         } => handle_print_layout_config_schema(),
+        Arguments {
+            attach: Some(ref session_name),
+            ..
+        } => handle_attach(session_name, &args),
+        Arguments {
+            group_cycle: Some(ref group),
+            ..
+        } => handle_group_cycle(group, &args),
+        Arguments {
+            reload_layout: true,
+            ..
+        } => handle_reload_layout(&args),
+        Arguments {
+            list_sessions: true,
+            ..
+        } => handle_list_sessions(&args),
+        Arguments {
+            print_workspace_type: Some(ref path),
+            ..
+        } => handle_print_workspace_type(path, &args),
+        Arguments { daemon: true, .. } => handle_daemon(&args),
+        Arguments {
+            complete: Some(kind),
+            ..
+        } => handle_complete(kind, &args),
         Arguments {
             print_bash_completion: true,
             ..
@@ -137,15 +451,64 @@ pub fn parse() -> Result<()> {
             print_man: true, ..
         } => handle_print_man(),
         _ => {
-            let mut tui = Tui::start()?;
-            let res = if args.existing {
-                handle_existing_session_selection(&mut tui)
+            let mut config = TwmGlobal::load(args.profile.as_deref())?;
+            if let Some(scope) = &args.scope {
+                config.restrict_search_paths(scope)?;
+            }
+            // the flag wins over the environment variable, which wins over the config file
+            match std::env::var(FOLLOW_LINKS_ENV_VAR).as_deref() {
+                Ok("0") => config.follow_links = false,
+                Ok("1") => config.follow_links = true,
+                _ => {}
+            }
+            if args.no_follow_links {
+                config.follow_links = false;
+            }
+            if args.no_cache {
+                config.cache_ttl = None;
+            }
+            config.detach_others = args.detach_others;
+            config.dry_run = args.dry_run;
+            if args.count {
+                return handle_count(&config, args.query.as_deref());
+            }
+            if args.last && handle_last_session(&config)? {
+                return Ok(());
+            }
+            let mut args = args;
+            if args.scratch {
+                handle_scratch(&config, &mut args)?;
+            }
+            if let Some(url) = args.clone_url.clone() {
+                handle_clone(&config, &mut args, &url)?;
+            }
+            // the workspaces found to match the query are listed in the picker if there's no clear match
+            let mut found_workspaces = None;
+            if args.first && !args.existing && !args.group && args.path.is_none() {
+                args.path = Some(handle_first_workspace(&config, args.query.as_deref())?);
+            } else if let Some(query) = &args.query {
+                if !args.existing && !args.group && !args.force_picker && args.path.is_none() {
+                    match handle_workspace_query(&config, query)? {
+                        WorkspaceQuery::Match(path) => args.path = Some(path),
+                        WorkspaceQuery::Ambiguous(workspaces) => {
+                            found_workspaces = Some(workspaces)
+                        }
+                    }
+                }
+            }
+            let mut tui = Tui::new(&config);
+            let res = if let Some(path) = &args.sessions_file {
+                handle_sessions_file(path, &config, &args, &mut tui)
+            } else if args.kill {
+                handle_kill_session_selection(&config, &args, &mut tui)
+            } else if args.existing {
+                handle_existing_session_selection(&config, &mut tui)
             } else if args.group {
-                handle_group_session_selection(&args, &mut tui)
+                handle_group_session_selection(&config, &args, &mut tui)
             } else {
-                handle_workspace_selection(&args, &mut tui)
+                handle_workspace_selection(&config, &args, found_workspaces, &mut tui)
             };
-            tui.exit()?;
+            tui.suspend()?;
             res
         }
     }
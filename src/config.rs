@@ -1,17 +1,29 @@
-use crate::layout::LayoutDefinition;
+use crate::layout::{
+    check_layout_inheritance, check_layout_names, get_layout_by_name, LayoutCommand,
+    LayoutDefinition,
+};
+use crate::tmux::{validate_attach_command_template, validate_group_name_template};
 use crate::workspace::{
-    HasAnyFileCondition, MissingAllFilesCondition, MissingAnyFileCondition, NullCondition,
-    WorkspaceConditionEnum, WorkspaceDefinition,
+    ExecCondition, GitBranchCondition, HasAnyFileCondition, MaxEntriesCondition,
+    MissingAllFilesCondition, MissingAnyFileCondition, NullCondition, RegexCondition,
+    ScriptCondition, WorkspaceConditionEnum, WorkspaceDefinition,
 };
 use anyhow::{Context, Result};
 use schemars::{schema_for, JsonSchema};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
+
+/// Setting this environment variable overrides the `tmux_config_file` configuration option.
+const TMUX_CONFIG_FILE_ENV_VAR: &str = "TWM_TMUX_CONF";
 
-#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct WorkspaceDefinitionConfig {
     /// Name for the workspace type defined by the list item.
@@ -51,18 +63,99 @@ struct WorkspaceDefinitionConfig {
     /// cannot match this workspace definition.
     pub missing_all_files: Option<Vec<String>>,
 
+    /// Regular expression the currently checked out git branch must match for a directory to be considered a
+    /// workspace of this type.
+    ///
+    /// If unset, this constraint is simply ignored.
+    ///
+    /// Directories that aren't git repositories, or that have a detached `HEAD`, cannot match this workspace
+    /// definition. For example, `^release/` only matches repositories currently on a release branch.
+    pub git_branch: Option<String>,
+
+    /// Maximum number of entries a directory can contain to be considered a workspace of this type.
+    ///
+    /// If unset, this constraint is simply ignored.
+    ///
+    /// Only the directory's immediate children are counted, not the contents of its subdirectories. This is useful
+    /// to keep huge vendored or data directories that happen to contain a marker file from being treated as workspaces.
+    pub max_entries: Option<usize>,
+
+    /// List of regular expressions of which at least one must match the name of an entry in a directory for it to be
+    /// considered a workspace of this type.
+    ///
+    /// If unset, this constraint is simply ignored.
+    ///
+    /// Only the directory's immediate children are checked. For example, `["\\.sln$"]` matches directories containing a
+    /// Visual Studio solution, whatever it's called. The patterns match anywhere in the name unless anchored with `^`
+    /// and `$`.
+    pub has_any_file_matching: Option<Vec<String>>,
+
+    /// Path to a script that must exit successfully for a directory to be considered a workspace of this type.
+    ///
+    /// If unset, this constraint is simply ignored.
+    ///
+    /// The script is run in the directory being checked, which is also passed to it as its only argument. Scripts
+    /// running longer than a second are killed and treated as not matching. This starts a process for every
+    /// directory searched that passes the other conditions of this definition, so it can make searching large
+    /// directory trees **very slow**. Pair it with cheaper conditions to narrow down the directories it runs in.
+    /// Shell expansion is supported.
+    pub script: Option<String>,
+
+    /// List of shell commands that must all exit successfully for a directory to be considered a workspace of this
+    /// type.
+    ///
+    /// If unset, this constraint is simply ignored.
+    ///
+    /// The commands are run with `$SHELL -c` in the directory being checked, e.g.
+    /// `["git remote get-url origin"]` only matches git repositories with an `origin` remote. Like `script`, commands
+    /// running longer than a second are killed and treated as not matching, and a process is started for every
+    /// directory searched that passes the other conditions of this definition, which can make searching large
+    /// directory trees **very slow**.
+    pub has_command_succeed: Option<Vec<String>>,
+
     /// The name of the layout to apply to a session during initialization.
     ///
-    /// If unset, no layout will be applied by default.
+    /// If unset, the global `fallback_layout` will be applied, if any.
     ///
     /// This option can be overridden either by using the `-l/--layout` command line flag, which will prompt you to select
     /// a layout from the list of configured layouts, or by the presence of a `.twm.yaml` local layout configuration file
-    /// in the workspace directory.
+    /// or a `.twm-layout` file naming a layout in the workspace directory.
     pub default_layout: Option<String>,
+
+    /// Whether to explicitly apply no layout to sessions of this workspace type, even if a global `fallback_layout`
+    /// is configured.
+    /// If unset, defaults to false.
+    ///
+    /// Can't be used together with `default_layout`. Layouts chosen with `-l/--layout` and local `.twm.yaml` layouts
+    /// are still applied.
+    #[serde(default)]
+    pub no_default_layout: bool,
+
+    /// Command to start in the first pane of new sessions of this workspace type instead of the default shell.
+    ///
+    /// The shell started by tmux is replaced with the command as soon as the session is created, so the program
+    /// doesn't have to be typed into a shell prompt. When the command exits, its pane is closed. Layout `commands` are typed into the first pane as
+    /// if it were a shell, so they are sent to the program instead.
+    pub initial_command: Option<String>,
+
+    /// Name of the first window in new sessions of this workspace type.
+    ///
+    /// If unset, the window is named after the layout the session is opened with, or if there is none, tmux names it
+    /// as usual, after the program running in it.
+    pub window_name: Option<String>,
 }
 
-impl From<WorkspaceDefinitionConfig> for WorkspaceDefinition {
-    fn from(config: WorkspaceDefinitionConfig) -> Self {
+impl TryFrom<WorkspaceDefinitionConfig> for WorkspaceDefinition {
+    type Error = anyhow::Error;
+
+    fn try_from(config: WorkspaceDefinitionConfig) -> Result<Self> {
+        if config.no_default_layout && config.default_layout.is_some() {
+            anyhow::bail!(
+                "Workspace definition `{}` can't set both `default_layout` and `no_default_layout`.",
+                config.name
+            );
+        }
+
         let mut conditions = Vec::<WorkspaceConditionEnum>::new();
 
         if let Some(has_any_file) = config.has_any_file {
@@ -101,21 +194,192 @@ impl From<WorkspaceDefinitionConfig> for WorkspaceDefinition {
             }
         }
 
+        if let Some(git_branch) = config.git_branch {
+            let condition = GitBranchCondition::new(git_branch).with_context(|| {
+                format!(
+                    "Invalid `git_branch` pattern in workspace definition `{}`.",
+                    config.name
+                )
+            })?;
+            conditions.push(condition.into());
+        }
+
+        if let Some(max_entries) = config.max_entries {
+            let condition = MaxEntriesCondition { max: max_entries };
+            conditions.push(condition.into());
+        }
+
+        if let Some(has_any_file_matching) = config.has_any_file_matching {
+            if !has_any_file_matching.is_empty() {
+                let condition = RegexCondition::new(has_any_file_matching).with_context(|| {
+                    format!(
+                        "Invalid `has_any_file_matching` pattern in workspace definition `{}`.",
+                        config.name
+                    )
+                })?;
+                conditions.push(condition.into());
+            }
+        }
+
+        // scripts and commands are the most expensive conditions by far, so they go last to only be run if everything
+        // else matched
+        if let Some(script) = config.script {
+            let condition = ScriptCondition {
+                path: shellexpand::tilde(&script).to_string(),
+            };
+            conditions.push(condition.into());
+        }
+
+        if let Some(commands) = config.has_command_succeed {
+            if !commands.is_empty() {
+                let condition = ExecCondition { commands };
+                conditions.push(condition.into());
+            }
+        }
+
         if conditions.is_empty() {
             let condition = NullCondition {};
             conditions.push(condition.into());
         }
 
-        WorkspaceDefinition {
+        Ok(WorkspaceDefinition {
             name: config.name,
             conditions,
             default_layout: config.default_layout,
+            initial_command: config.initial_command,
+            window_name: config.window_name,
+        })
+    }
+}
+
+/// How to name a session when the name twm would normally use is already taken by another session.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionStrategy {
+    /// Add more components of the workspace path to the name, e.g. `foo` becomes `projects/foo`.
+    #[default]
+    PathComponents,
+    /// Keep the name and append an incrementing suffix, e.g. `foo` becomes `foo-2`.
+    NumericSuffix,
+}
+
+/// Where the prompt and the best matches are shown in the picker.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PickerLayout {
+    /// Show the prompt at the bottom, with the list growing upwards from it.
+    #[default]
+    Bottom,
+    /// Show the prompt at the top, with the list growing downwards from it.
+    Top,
+}
+
+/// A criterion the picker sorts matches by, see the `tiebreak` configuration option.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Tiebreak {
+    /// Better matches for the query first.
+    Score,
+    /// Shorter items first.
+    Length,
+    /// Items in alphabetical order.
+    Path,
+}
+
+/// How existing sessions are displayed in the picker.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionDisplay {
+    /// Display the tmux session name.
+    #[default]
+    Name,
+    /// Display the workspace path the session was opened for, falling back to the session name for sessions not
+    /// created by twm.
+    Path,
+}
+
+/// What modified Enter in the workspace picker does when the workspace has no twm session to group with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupFallback {
+    /// Open the workspace in a new session as if it was selected with Enter, explaining why it wasn't grouped.
+    #[default]
+    Open,
+    /// Show the picker from `-g/--group` to choose a session to group with instead.
+    Pick,
+    /// Exit with an error without opening anything.
+    Error,
+}
+
+/// How existing sessions are sorted in the picker.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionSort {
+    /// Sort alphabetically by session name.
+    #[default]
+    Name,
+    /// List the sessions with the most recent activity first.
+    Activity,
+    /// List the most recently created sessions first.
+    Created,
+}
+
+/// How the workspace picker orders workspaces while nothing is typed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortBy {
+    /// Keep the order the workspaces were found in.
+    #[default]
+    None,
+    /// List the workspaces opened most often and most recently first.
+    Frecency,
+    /// Sort alphabetically by path.
+    Path,
+}
+
+/// A directory to search for workspaces, either just its path or its path along with options for it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(untagged)]
+pub enum SearchPathConfig {
+    Path(String),
+    Options(SearchPathOptions),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SearchPathOptions {
+    /// The directory to search. Shell expansion is supported.
+    pub path: String,
+
+    /// Maximum depth to search for workspaces inside this directory.
+    /// If unset, the global `max_search_depth` is used.
+    pub max_depth: Option<usize>,
+}
+
+impl SearchPathConfig {
+    fn path(&self) -> &str {
+        match self {
+            SearchPathConfig::Path(path) => path,
+            SearchPathConfig::Options(options) => &options.path,
+        }
+    }
+
+    fn max_depth(&self) -> Option<usize> {
+        match self {
+            SearchPathConfig::Path(_) => None,
+            SearchPathConfig::Options(options) => options.max_depth,
         }
     }
 }
 
-fn default_search_paths() -> Vec<String> {
-    vec!["~".into()]
+/// Canonicalizes `path` if it exists, so paths written differently that lead to the same directory are equal. Paths
+/// that don't exist are only compared by their components, which ignores trailing and repeated slashes.
+fn canonicalize_or_keep(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn default_search_paths() -> Vec<SearchPathConfig> {
+    vec![SearchPathConfig::Path("~".into())]
 }
 
 fn default_workspace_definitions() -> Vec<WorkspaceDefinitionConfig> {
@@ -126,6 +390,14 @@ fn default_workspace_definitions() -> Vec<WorkspaceDefinitionConfig> {
         has_all_files: None,
         missing_any_file: None,
         missing_all_files: None,
+        git_branch: None,
+        max_entries: None,
+        has_any_file_matching: None,
+        script: None,
+        has_command_succeed: None,
+        no_default_layout: false,
+        initial_command: None,
+        window_name: None,
     }]
 }
 
@@ -133,10 +405,30 @@ const fn default_max_search_depth() -> usize {
     3
 }
 
+/// Search depth used when there's no config file, since searching all of `~` 3 levels deep can take a while on a
+/// fresh machine.
+const NO_CONFIG_MAX_SEARCH_DEPTH: usize = 2;
+
+const fn default_max_injected() -> usize {
+    100_000
+}
+
 const fn default_session_name_path_components() -> usize {
     2
 }
 
+fn default_env_prefix() -> String {
+    "TWM".into()
+}
+
+fn default_dotenv_file() -> String {
+    ".env".into()
+}
+
+fn default_session_name_type_separator() -> String {
+    "@".into()
+}
+
 fn default_exclude_path_components() -> Vec<String> {
     vec![
         ".cache".into(),
@@ -153,27 +445,64 @@ fn default_layout_definitions() -> Vec<LayoutDefinition> {
     vec![LayoutDefinition {
         name: "default".into(),
         inherits: None,
-        commands: Some(vec![String::from("echo \"Created $TWM_TYPE session\"")]),
+        windows: None,
+        commands: Some(vec![LayoutCommand::from(
+            "echo \"Created $TWM_TYPE session\"",
+        )]),
+        commands_file: None,
+        selectable: true,
+        zoom_pane: None,
     }]
 }
 
+fn default_tiebreak() -> Vec<Tiebreak> {
+    vec![Tiebreak::Score, Tiebreak::Length]
+}
+
 fn default_follow_links() -> bool {
     true
 }
 
-#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+fn default_highlight_matches() -> bool {
+    true
+}
+
+fn default_show_workspace_type() -> bool {
+    true
+}
+
+fn default_preview_command() -> String {
+    "ls -A {}".into()
+}
+
+fn default_mouse_capture() -> bool {
+    true
+}
+
+fn default_search_hidden() -> bool {
+    true
+}
+
+fn default_include_hidden_workspaces() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct RawTwmGlobal {
     /// List of directories to have twm search for workspaces.
     ///
     /// If unset, defaults to `~` (shell expansion is supported).
     ///
+    /// Each entry is either a path, or an object with a `path` and a `max_depth` to search that directory to a
+    /// different depth than `max_search_depth`, e.g. `{ path: ~/experiments, max_depth: 1 }`.
+    ///
     /// Be careful to not make your search paths overlap, e.g. if you include `~/projects` and `~/projects/foo/bar`
     /// with `max_search_depth: 3`, `~/projects/foo/bar` will be searched twice and results will be displayed twice
     /// in the picker. Generally it's easiest to just include the parent directory and increase `max_search_depth`
     /// if needed.
     #[serde(default = "default_search_paths")]
-    search_paths: Vec<String>,
+    search_paths: Vec<SearchPathConfig>,
 
     /// List of configurations for workspaces.
     ///
@@ -191,6 +520,32 @@ pub struct RawTwmGlobal {
     #[serde(default = "default_max_search_depth")]
     max_search_depth: usize,
 
+    /// Maximum number of workspaces to add to the workspace picker.
+    /// If unset, defaults to 100000.
+    ///
+    /// Searching stops once this many workspaces have been found and the picker shows that the list was truncated.
+    /// This keeps the picker usable when a search path accidentally contains a huge directory tree.
+    #[serde(default = "default_max_injected")]
+    max_injected: usize,
+
+    /// Only list workspaces modified within this many days in the workspace picker.
+    /// If unset, workspaces are listed regardless of when they were last modified.
+    ///
+    /// A workspace's age is taken from the most recent modification time of its directory and, for git repositories,
+    /// the `HEAD` file in its git directory, which changes whenever a branch is checked out or committed to. Files
+    /// further inside the workspace aren't checked, so editing a file in a subdirectory doesn't count. This costs
+    /// one or two extra `stat` calls per matched workspace.
+    max_age_days: Option<u64>,
+
+    /// Number of seconds the workspaces found in a search path are cached for.
+    /// If unset, workspaces aren't cached.
+    ///
+    /// Cached workspaces are listed in the workspace picker right away, while the search paths are searched again in
+    /// the background to add any new workspaces and update the cache. Cached workspaces that were deleted since are
+    /// left out, but ones that no longer match any workspace definition may still be listed until the next run. The
+    /// cache is kept in `$XDG_CACHE_HOME/twm/` and isn't used for search paths indexed by `twm --daemon`.
+    cache_ttl: Option<u64>,
+
     /// Default number of components of the workspace directory to use for the created session name.
     /// If unset, defaults to 1.
     ///
@@ -199,6 +554,30 @@ pub struct RawTwmGlobal {
     #[serde(default = "default_session_name_path_components")]
     session_name_path_components: usize,
 
+    /// Whether to append the workspace type to the names of sessions twm creates, e.g. `bar@rust`.
+    /// If unset, defaults to false.
+    ///
+    /// The type is appended after `session_name_type_separator`. Workspaces that don't match any workspace
+    /// definition don't get a suffix. Names given with `-n/--name` are used as-is.
+    #[serde(default)]
+    session_name_include_type: bool,
+
+    /// Separator between the session name and the workspace type when `session_name_include_type` is set.
+    /// If unset, defaults to `@`.
+    #[serde(default = "default_session_name_type_separator")]
+    session_name_type_separator: String,
+
+    /// Template for the names of sessions twm creates, used instead of the last `session_name_path_components`
+    /// components of the workspace path.
+    /// If unset, sessions are named after the workspace path.
+    ///
+    /// `{basename}` and `{parent}` are replaced with the last two components of the workspace path, `{type}` with
+    /// the workspace type and `{git_branch}` with the checked out git branch, e.g. `{basename}@{git_branch}` names
+    /// sessions like `twm@main`. Placeholders that don't apply to a workspace are replaced with nothing, and if the
+    /// whole name ends up empty the path is used as if the template wasn't set. When the name is already taken by a
+    /// session for a different workspace, a numeric suffix is appended regardless of `collision_strategy`.
+    session_name_template: Option<String>,
+
     /// List of path components which will *exclude* a directory from being considered a workspace.
     /// If unset, defaults to an empty list.
     ///
@@ -215,9 +594,344 @@ pub struct RawTwmGlobal {
     layouts: Vec<LayoutDefinition>,
 
     /// Whether to follow symbolic links when searching for workspaces.
+    /// Links pointing back to one of their own parent directories are skipped with a warning.
     /// If unset, defaults to true.
     #[serde(default = "default_follow_links")]
     follow_links: bool,
+
+    /// Whether to highlight the characters matching the current search query in the picker.
+    /// If unset, defaults to true.
+    #[serde(default = "default_highlight_matches")]
+    highlight_matches: bool,
+
+    /// Whether the picker should capture the mouse.
+    /// If unset, defaults to true.
+    ///
+    /// Disabling this lets you use your terminal's native text selection (e.g. to copy a path) while the picker is open.
+    #[serde(default = "default_mouse_capture")]
+    mouse_capture: bool,
+
+    /// Where the prompt is shown in the picker.
+    /// If unset, defaults to `bottom`.
+    ///
+    /// With `bottom`, the prompt is at the bottom of the screen and the best match right above it. With `top`, the
+    /// prompt is at the top and the best match right below it. Either way, the best match is next to the prompt.
+    #[serde(default)]
+    picker_layout: PickerLayout,
+
+    /// How the picker orders matches, by each criterion in turn until one tells two matches apart.
+    /// If unset, defaults to `[score, length]`.
+    ///
+    /// `score` puts better matches for the query first, `length` shorter items and `path` items in alphabetical
+    /// order. Matches tied on every criterion keep the order they were found in.
+    #[serde(default = "default_tiebreak")]
+    tiebreak: Vec<Tiebreak>,
+
+    /// How the workspace picker orders workspaces while nothing is typed. Once a query is typed, matches are ordered
+    /// by `tiebreak` as usual.
+    /// If unset, defaults to `none`.
+    ///
+    /// `none` keeps the order workspaces are found in, `path` sorts them alphabetically and `frecency` lists the
+    /// workspaces opened most often and most recently first. Workspaces are only recorded in
+    /// `$XDG_STATE_HOME/twm/history` while `frecency` is set. Favorites are always listed first.
+    #[serde(default)]
+    sort_by: SortBy,
+
+    /// Whether to group workspaces in the picker by their workspace type, with a header above each group.
+    /// If unset, defaults to false.
+    ///
+    /// Groups are ordered by their best match for the current search query, and groups without any matches are hidden.
+    #[serde(default)]
+    grouped: bool,
+
+    /// Whether to group workspaces in the picker by the search path they were found in, instead of by workspace type.
+    /// If unset, defaults to false.
+    ///
+    /// Turns on grouping by itself, so `grouped` doesn't need to be set too. Groups can be collapsed and expanded with
+    /// Ctrl+T, and collapsed groups still show how many of their workspaces match the current search query.
+    #[serde(default)]
+    group_by_search_path: bool,
+
+    /// Whether to show each workspace's type after its path in the picker, e.g. `~/code/foo  [rust]`.
+    /// If unset, defaults to true.
+    ///
+    /// The type is matched along with the path, so typing a workspace type narrows the list to workspaces of that type.
+    #[serde(default = "default_show_workspace_type")]
+    show_workspace_type: bool,
+
+    /// Whether the workspace picker starts out with the preview pane shown. It can always be toggled with Ctrl+/.
+    /// If unset, defaults to false.
+    #[serde(default)]
+    show_preview: bool,
+
+    /// Command whose output is shown in the workspace picker's preview pane for the highlighted workspace, with `{}`
+    /// in place of its path.
+    /// If unset, defaults to `ls -A {}`.
+    ///
+    /// The command is run with your shell in the workspace directory in the background, so a slow command doesn't
+    /// hold up the picker. Its output is kept, so it only runs once per workspace while the picker is open. When
+    /// scrolling past several workspaces, it only runs for the one highlighted last. It's killed if it runs for more
+    /// than 5 seconds.
+    #[serde(default = "default_preview_command")]
+    preview_command: String,
+
+    /// Whether modified Enter (Ctrl/Shift/Alt+Enter) should perform its action and keep the picker open.
+    /// If unset, defaults to false.
+    ///
+    /// Currently this applies to `-g/--group`, where modified Enter creates a new session in the highlighted
+    /// session's group without attaching to it, so several sessions can be created in a row.
+    #[serde(default)]
+    keep_open_on_modified_enter: bool,
+
+    /// Number of threads to use when searching for workspaces.
+    /// If unset, twm picks a number based on how many CPUs are available.
+    ///
+    /// Searching in parallel is much faster on local disks, but on network filesystems lots of concurrent
+    /// directory reads can hammer the mount and end up slower than reading one directory at a time. Setting this
+    /// to 1 makes the search fully sequential. Must be greater than 0.
+    discovery_threads: Option<usize>,
+
+    /// Whether to automatically open the workspace if the search finds exactly one.
+    /// If unset, defaults to false.
+    ///
+    /// The workspace is only opened automatically once the search is completely finished, and only if you haven't
+    /// started typing in the picker.
+    #[serde(default)]
+    auto_select_single: bool,
+
+    /// Whether to leave the workspace of the current twm session out of the picker when running twm inside it.
+    /// If unset, defaults to false.
+    ///
+    /// The current workspace is read from the `TWM_ROOT` environment variable that twm sets in its sessions.
+    /// Selecting it would just attach to the session you're already in.
+    #[serde(default)]
+    exclude_current_workspace: bool,
+
+    /// How to name a new session when its name is already taken by a session for a different workspace.
+    /// If unset, defaults to `path_components`.
+    ///
+    /// With `path_components`, more components of the workspace path are used until the name is unique, e.g.
+    /// `foo` becomes `projects/foo`. With `numeric_suffix`, an incrementing number is appended to the name
+    /// instead, e.g. `foo` becomes `foo-2`.
+    #[serde(default)]
+    collision_strategy: CollisionStrategy,
+
+    /// Map of shorthands to base directories that can be used as a prefix with `-p/--path`.
+    /// If unset, defaults to an empty map.
+    ///
+    /// E.g. with `work: ~/work`, `twm -p work:service-a` opens `~/work/service-a`. Paths with a prefix that isn't
+    /// in this map are used as-is. Shell expansion is supported.
+    #[serde(default)]
+    project_roots: HashMap<String, String>,
+
+    /// Whether `exclude_path_components` should be matched case-insensitively.
+    /// If unset, defaults to false.
+    ///
+    /// Useful on case-insensitive filesystems (e.g. the macOS default), where `Node_Modules` and `node_modules` are
+    /// the same directory.
+    #[serde(default)]
+    case_insensitive_excludes: bool,
+
+    /// Command used to attach to a session when twm is run outside of tmux, with `{session}` in place of the
+    /// session name.
+    /// If unset, twm runs `tmux attach -t {session}`.
+    ///
+    /// The command is run with `$SHELL -c`. This can be used to e.g. attach in a new terminal window or tab instead
+    /// of the current terminal.
+    attach_command_template: Option<String>,
+
+    /// Directory to create scratch workspaces opened with `--scratch` in.
+    /// If unset, the system temporary directory (usually `$TMPDIR` or `/tmp`) is used.
+    ///
+    /// Scratch directories aren't removed when their session ends. Shell expansion is supported.
+    scratch_dir: Option<String>,
+
+    /// Name of the layout to open scratch workspaces opened with `--scratch` with.
+    /// If unset, scratch workspaces are opened without a layout.
+    ///
+    /// `-l/--layout` overrides this option.
+    scratch_layout: Option<String>,
+
+    /// Directory to clone repositories into with `--clone`.
+    /// If unset, the first search path is used.
+    ///
+    /// Shell expansion is supported.
+    clone_root: Option<String>,
+
+    /// Whether to load the environment variables in a workspace's dotenv file into sessions opened for it.
+    /// If unset, defaults to false.
+    ///
+    /// The file is read once when the session is created. Only `KEY=VALUE` lines are supported, optionally prefixed
+    /// with `export` and with the value in quotes. Blank lines and comments are ignored, and other lines are skipped
+    /// with a warning.
+    #[serde(default)]
+    load_dotenv: bool,
+
+    /// Name of the dotenv file loaded from the workspace directory when `load_dotenv` is set.
+    /// If unset, defaults to `.env`.
+    #[serde(default = "default_dotenv_file")]
+    dotenv_file: String,
+
+    /// Path to the tmux config file to start the tmux server with when twm starts it.
+    /// If unset, tmux finds its config file as usual.
+    ///
+    /// The `TWM_TMUX_CONF` environment variable overrides this option. tmux only reads its config file when the
+    /// server starts, so this only has an effect when twm opens a workspace while no tmux server is running.
+    /// Shell expansion is supported.
+    tmux_config_file: Option<String>,
+
+    /// Prefix of the environment variables twm sets in its sessions, e.g. `MY_TWM` for `MY_TWM_ROOT`.
+    /// If unset, defaults to `TWM`.
+    ///
+    /// twm also reads these variables to recognize its own sessions, so sessions created with a different prefix
+    /// are treated like sessions not created by twm. Must only contain ASCII letters, digits and underscores, and
+    /// can't start with a digit. Environment variables that configure twm itself, like `TWM_CONFIG_FILE`, keep
+    /// their names.
+    #[serde(default = "default_env_prefix")]
+    env_prefix: String,
+
+    /// Command whose output lists workspaces to add to the picker, e.g. `ghq list --full-path`.
+    ///
+    /// The command is run with `$SHELL -c` and should print one absolute path per line. Shell expansion is supported
+    /// in the paths. The workspaces are listed in addition to the ones found in `search_paths`, so set
+    /// `search_paths` to an empty list to only use this command. Paths are listed even if they don't match any
+    /// workspace definition. If the command fails, its output is ignored.
+    workspace_command: Option<String>,
+
+    /// Command run with `$SHELL -c` before killing a session with `--kill`, e.g. to commit work in progress.
+    ///
+    /// The command runs with the session's environment as read with `tmux showenv`, so `TWM_ROOT` is the session's
+    /// workspace, and with `TWM_SESSION` set to the session's name. It runs in the workspace directory if the session
+    /// has one. If the command exits unsuccessfully, the session isn't killed unless `--force` is given.
+    pre_kill: Option<String>,
+
+    /// Command run with `$SHELL -c` after killing a session with `--kill`, e.g. to send a notification.
+    ///
+    /// The command runs with the same environment as `pre_kill`.
+    post_kill: Option<String>,
+
+    /// How existing sessions are sorted when selecting a session with `-e/--existing` or `-g/--group`.
+    /// If unset, defaults to `name`.
+    ///
+    /// With `name`, sessions are sorted alphabetically. With `activity`, the sessions with the most recent activity
+    /// are listed first, closest to the prompt. With `created`, the most recently created sessions are listed first.
+    #[serde(default)]
+    session_sort: SessionSort,
+
+    /// Template for the names of sessions created in an existing session's group with `-g/--group`, with `{base}`
+    /// in place of the existing session's name and `{n}` in place of a counter.
+    /// If unset, defaults to `{base}-{n}`.
+    ///
+    /// The counter starts at 1 and is incremented until the name isn't taken, so the template must contain `{n}`.
+    /// Characters tmux doesn't allow in session names are replaced the same way as in other session names.
+    group_name_template: Option<String>,
+
+    /// How existing sessions are displayed when selecting a session with `-e/--existing` or `-g/--group`.
+    /// If unset, defaults to `name`.
+    ///
+    /// With `name`, sessions are displayed by their tmux session name. With `path`, sessions created by twm are
+    /// displayed by their workspace path, the same way workspaces are displayed in the workspace picker. Sessions
+    /// not created by twm are still displayed by name.
+    #[serde(default)]
+    session_display: SessionDisplay,
+
+    /// What to do when a workspace selected with modified Enter doesn't have a twm session to group with.
+    /// If unset, defaults to `open`.
+    ///
+    /// With `open`, the workspace is opened in a new session like a normal selection and a message says why it
+    /// wasn't grouped. With `pick`, the session picker from `-g/--group` is shown to choose a session to group with
+    /// instead. With `error`, twm exits with an error.
+    #[serde(default)]
+    group_fallback: GroupFallback,
+
+    /// The name of the layout to apply to a session when its workspace type doesn't set a `default_layout`.
+    /// If unset, no layout is applied to those sessions.
+    ///
+    /// This also applies to workspaces that don't match any workspace definition, e.g. ones opened with
+    /// `-p/--path`. Workspace definitions can opt out of it with `no_default_layout`.
+    fallback_layout: Option<String>,
+
+    /// Maximum number of seconds to wait for each layout command to finish before sending the next one.
+    /// If unset, layout commands are sent one after another without waiting.
+    ///
+    /// Waiting lets commands depend on the ones before them, e.g. activating a virtualenv before starting a server,
+    /// but makes opening sessions slower. Each command is followed by `; tmux wait-for -S <channel>` to tell twm it's
+    /// done, except the last one and ones run in the background with `&`. Commands that keep running, like editors,
+    /// hold up the rest of the layout until the timeout is reached.
+    layout_command_timeout: Option<u64>,
+
+    /// Whether to name the first window of a new session after the layout it's opened with.
+    /// If unset, defaults to false.
+    ///
+    /// A workspace definition's `window_name` is used either way, and the layout's first window can still rename it
+    /// if it has a `name` of its own.
+    #[serde(default)]
+    name_window_after_layout: bool,
+
+    /// Whether to descend into hidden directories (ones whose names start with a `.`) when searching for
+    /// workspaces.
+    /// If unset, defaults to true.
+    ///
+    /// This only controls whether workspaces *inside* hidden directories are found. Whether a hidden directory can
+    /// be a workspace itself is controlled by `include_hidden_workspaces`.
+    #[serde(default = "default_search_hidden")]
+    search_hidden: bool,
+
+    /// Whether hidden directories (ones whose names start with a `.`) can themselves be matched as workspaces, e.g.
+    /// a `~/.dotfiles` git repository.
+    /// If unset, defaults to true.
+    ///
+    /// This is independent of `search_hidden`, so e.g. `~/.dotfiles` can still be found when searching inside
+    /// hidden directories is disabled. Search paths are always matchable, even if they're hidden.
+    #[serde(default = "default_include_hidden_workspaces")]
+    include_hidden_workspaces: bool,
+
+    /// Whether workspaces inside a git repository should be collapsed into the repository's root directory.
+    /// If unset, defaults to false.
+    ///
+    /// When a directory matches a workspace definition, it is replaced in the picker by the nearest directory
+    /// containing a `.git` file or directory among itself and its parents, so monorepo subdirectories show up once
+    /// as their repository. Submodules are repositories of their own, so they're still listed separately. Parents
+    /// above the search path are never considered.
+    #[serde(default)]
+    collapse_to_git_root: bool,
+
+    /// Whether to skip directories ignored by `.gitignore` files when searching for workspaces.
+    /// If unset, defaults to false.
+    ///
+    /// Only `.gitignore` files inside the search paths are read, with the one closest to a directory deciding whether
+    /// it's ignored. Ignored directories are neither searched nor listed as workspaces. A `.twmignore` file with the
+    /// same syntax directly inside a search path is always respected, whether or not this is set.
+    #[serde(default)]
+    respect_gitignore: bool,
+
+    /// Map of workspace paths to lists of tags that can be searched for in the picker along with the path.
+    /// If unset, defaults to an empty map.
+    ///
+    /// E.g. with `~/work/api: [backend, urgent]`, searching for `urgent` finds `~/work/api`. Tags are displayed after
+    /// the path, and a query that matches the path scores the same whether or not the workspace has tags. Paths are
+    /// compared after resolving symlinks and `..`, and shell expansion is supported in them.
+    #[serde(default)]
+    workspace_tags: HashMap<String, Vec<String>>,
+
+    /// List of workspace paths to pin to the top of the picker, in the order they're listed.
+    /// If unset, defaults to an empty list.
+    ///
+    /// Favorites are listed first and displayed in bold until something is typed, after which they're matched like
+    /// any other workspace. Only favorites that searching the `search_paths` would find are listed, so ones that don't
+    /// match a workspace definition or are excluded from the search are ignored. Shell expansion is supported in the
+    /// paths.
+    #[serde(default)]
+    favorites: Vec<String>,
+
+    /// Named sets of configuration options that can be applied on top of the rest of the config with `--profile`.
+    /// If unset, defaults to an empty map.
+    ///
+    /// Each profile can set any other top-level option. Options set in the profile replace the value from the
+    /// rest of the config entirely, so e.g. a profile's `search_paths` list replaces the base list instead of being
+    /// appended to it. Options the profile doesn't set keep their base value.
+    #[serde(default)]
+    profiles: HashMap<String, serde_json::Map<String, serde_json::Value>>,
 }
 
 impl Default for RawTwmGlobal {
@@ -231,17 +945,102 @@ impl RawTwmGlobal {
     pub fn schema() -> Result<String> {
         Ok(serde_json::to_string_pretty(&schema_for!(Self))?)
     }
+
+    /// Whether the workspace definitions are the built-in ones. They use the built-in `default` layout, which is fine
+    /// to leave out when defining your own layouts.
+    fn uses_builtin_definitions(&self) -> bool {
+        self.workspace_definitions == default_workspace_definitions()
+    }
+
+    /// Overlays the options set in the profile named `profile` onto the rest of the config.
+    fn apply_profile(self, profile: &str) -> Result<Self> {
+        let Some(overlay) = self.profiles.get(profile) else {
+            let mut names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            anyhow::bail!(
+                "No profile named `{profile}` is defined. Available profiles: [{}]",
+                names.join(", ")
+            );
+        };
+        if overlay.contains_key("profiles") {
+            anyhow::bail!("Profile `{profile}` can't define `profiles` itself.");
+        }
+        let overlay = overlay.clone();
+        let serde_json::Value::Object(mut merged) = serde_json::to_value(self)? else {
+            unreachable!("config always serializes to a map");
+        };
+        merged.extend(overlay);
+        serde_json::from_value(serde_json::Value::Object(merged))
+            .with_context(|| format!("Failed to apply profile `{profile}`."))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TwmGlobal {
     pub search_paths: Vec<String>,
+    /// Search depths of the search paths that set their own `max_depth`, keyed by the expanded search path.
+    pub search_path_depths: HashMap<String, usize>,
     pub exclude_path_components: Vec<String>,
     pub workspace_definitions: Vec<WorkspaceDefinition>,
     pub session_name_path_components: usize,
+    pub session_name_include_type: bool,
+    pub session_name_type_separator: String,
+    pub session_name_template: Option<String>,
     pub layouts: Vec<LayoutDefinition>,
     pub max_search_depth: usize,
+    pub max_injected: usize,
+    pub max_age: Option<Duration>,
+    pub cache_ttl: Option<Duration>,
     pub follow_links: bool,
+    pub highlight_matches: bool,
+    pub mouse_capture: bool,
+    pub picker_layout: PickerLayout,
+    pub tiebreak: Vec<Tiebreak>,
+    pub sort_by: SortBy,
+    pub grouped: bool,
+    pub group_by_search_path: bool,
+    pub show_workspace_type: bool,
+    pub show_preview: bool,
+    pub preview_command: String,
+    pub keep_open_on_modified_enter: bool,
+    pub discovery_threads: Option<usize>,
+    pub auto_select_single: bool,
+    pub exclude_current_workspace: bool,
+    pub collision_strategy: CollisionStrategy,
+    pub project_roots: HashMap<String, String>,
+    pub case_insensitive_excludes: bool,
+    pub attach_command_template: Option<String>,
+    pub session_display: SessionDisplay,
+    pub group_fallback: GroupFallback,
+    pub fallback_layout: Option<String>,
+    pub search_hidden: bool,
+    pub include_hidden_workspaces: bool,
+    pub profile_names: Vec<String>,
+    pub workspace_tags: HashMap<PathBuf, Vec<String>>,
+    pub favorites: Vec<String>,
+    pub collapse_to_git_root: bool,
+    pub respect_gitignore: bool,
+    pub group_name_template: Option<String>,
+    pub session_sort: SessionSort,
+    pub workspace_command: Option<String>,
+    pub pre_kill: Option<String>,
+    pub post_kill: Option<String>,
+    pub tmux_config_file: Option<String>,
+    pub env_prefix: String,
+    pub scratch_dir: Option<String>,
+    pub scratch_layout: Option<String>,
+    pub clone_root: Option<String>,
+    pub load_dotenv: bool,
+    pub dotenv_file: String,
+    pub layout_command_timeout: Option<Duration>,
+    pub name_window_after_layout: bool,
+    /// Whether no config file was found, so the built-in defaults are in use.
+    pub using_defaults: bool,
+    /// Whether tmux commands are printed instead of run, set with `--dry-run` rather than in the config file.
+    pub dry_run: bool,
+    /// Whether attaching to a session detaches every other client from it, set with `--detach-others` rather than in
+    /// the config file.
+    pub detach_others: bool,
 }
 
 #[derive(Debug, Deserialize, Clone, JsonSchema)]
@@ -258,32 +1057,174 @@ impl TwmLayout {
     }
 }
 
-impl From<RawTwmGlobal> for TwmGlobal {
-    fn from(raw_config: RawTwmGlobal) -> Self {
+impl TryFrom<RawTwmGlobal> for TwmGlobal {
+    type Error = anyhow::Error;
+
+    fn try_from(raw_config: RawTwmGlobal) -> Result<Self> {
         // search paths are the only place we need to worry about shell expansion
         let search_paths: Vec<String> = raw_config
             .search_paths
             .iter()
-            .map(|path| shellexpand::tilde(path).to_string())
+            .map(|search_path| shellexpand::tilde(search_path.path()).to_string())
+            .collect();
+        let search_path_depths = raw_config
+            .search_paths
+            .iter()
+            .zip(&search_paths)
+            .filter_map(|(search_path, path)| Some((path.clone(), search_path.max_depth()?)))
             .collect();
 
-        let exclude_path_components = raw_config.exclude_path_components;
+        let exclude_path_components = if raw_config.case_insensitive_excludes {
+            raw_config
+                .exclude_path_components
+                .iter()
+                .map(|c| c.to_lowercase())
+                .collect()
+        } else {
+            raw_config.exclude_path_components
+        };
+
+        if raw_config.discovery_threads == Some(0) {
+            anyhow::bail!("`discovery_threads` must be greater than 0.");
+        }
+
+        let env_prefix_is_valid = raw_config
+            .env_prefix
+            .starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && raw_config
+                .env_prefix
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !env_prefix_is_valid {
+            anyhow::bail!(
+                "`env_prefix` must be a valid environment variable name, got `{}`.",
+                raw_config.env_prefix
+            );
+        }
+
+        // no path components would give every session an empty name, which tmux rejects
+        if raw_config.session_name_path_components == 0 {
+            anyhow::bail!("`session_name_path_components` must be greater than 0.");
+        }
+
+        if let Some(template) = &raw_config.attach_command_template {
+            validate_attach_command_template(template)?;
+        }
+
+        if let Some(template) = &raw_config.group_name_template {
+            validate_group_name_template(template)?;
+        }
+
+        let mut profile_names: Vec<String> = raw_config.profiles.keys().cloned().collect();
+        profile_names.sort_unstable();
 
         let workspace_definitions = raw_config
             .workspace_definitions
             .into_iter()
-            .map(WorkspaceDefinition::from)
-            .collect();
+            .map(|mut definition| {
+                // an explicit `no_default_layout` opts out of the fallback, otherwise unset layouts fall back
+                if definition.default_layout.is_none() && !definition.no_default_layout {
+                    definition.default_layout = raw_config.fallback_layout.clone();
+                }
+                WorkspaceDefinition::try_from(definition)
+            })
+            .collect::<Result<_>>()?;
 
-        Self {
+        Ok(Self {
             search_paths,
+            search_path_depths,
             exclude_path_components,
             workspace_definitions,
             layouts: raw_config.layouts,
             max_search_depth: raw_config.max_search_depth,
+            max_injected: raw_config.max_injected,
+            max_age: raw_config
+                .max_age_days
+                .map(|days| Duration::from_secs(days.saturating_mul(SECONDS_PER_DAY))),
+            cache_ttl: raw_config.cache_ttl.map(Duration::from_secs),
             session_name_path_components: raw_config.session_name_path_components,
+            session_name_include_type: raw_config.session_name_include_type,
+            session_name_type_separator: raw_config.session_name_type_separator,
+            session_name_template: raw_config.session_name_template,
             follow_links: raw_config.follow_links,
-        }
+            highlight_matches: raw_config.highlight_matches,
+            mouse_capture: raw_config.mouse_capture,
+            picker_layout: raw_config.picker_layout,
+            tiebreak: raw_config.tiebreak,
+            sort_by: raw_config.sort_by,
+            grouped: raw_config.grouped,
+            group_by_search_path: raw_config.group_by_search_path,
+            show_workspace_type: raw_config.show_workspace_type,
+            show_preview: raw_config.show_preview,
+            preview_command: raw_config.preview_command,
+            keep_open_on_modified_enter: raw_config.keep_open_on_modified_enter,
+            discovery_threads: raw_config.discovery_threads,
+            auto_select_single: raw_config.auto_select_single,
+            exclude_current_workspace: raw_config.exclude_current_workspace,
+            collision_strategy: raw_config.collision_strategy,
+            project_roots: raw_config
+                .project_roots
+                .into_iter()
+                .map(|(name, root)| (name, shellexpand::tilde(&root).to_string()))
+                .collect(),
+            case_insensitive_excludes: raw_config.case_insensitive_excludes,
+            attach_command_template: raw_config.attach_command_template,
+            session_display: raw_config.session_display,
+            group_fallback: raw_config.group_fallback,
+            fallback_layout: raw_config.fallback_layout,
+            search_hidden: raw_config.search_hidden,
+            include_hidden_workspaces: raw_config.include_hidden_workspaces,
+            profile_names,
+            collapse_to_git_root: raw_config.collapse_to_git_root,
+            respect_gitignore: raw_config.respect_gitignore,
+            group_name_template: raw_config.group_name_template,
+            session_sort: raw_config.session_sort,
+            workspace_command: raw_config.workspace_command,
+            pre_kill: raw_config.pre_kill,
+            post_kill: raw_config.post_kill,
+            scratch_dir: raw_config
+                .scratch_dir
+                .map(|dir| shellexpand::tilde(&dir).into_owned()),
+            scratch_layout: raw_config.scratch_layout,
+            clone_root: raw_config
+                .clone_root
+                .map(|dir| shellexpand::tilde(&dir).into_owned()),
+            load_dotenv: raw_config.load_dotenv,
+            dotenv_file: raw_config.dotenv_file,
+            layout_command_timeout: raw_config.layout_command_timeout.map(Duration::from_secs),
+            name_window_after_layout: raw_config.name_window_after_layout,
+            using_defaults: false,
+            dry_run: false,
+            detach_others: false,
+            tmux_config_file: std::env::var(TMUX_CONFIG_FILE_ENV_VAR)
+                .ok()
+                .filter(|file| !file.is_empty())
+                .or(raw_config.tmux_config_file)
+                .map(|file| shellexpand::tilde(&file).into_owned()),
+            env_prefix: raw_config.env_prefix,
+            workspace_tags: raw_config
+                .workspace_tags
+                .into_iter()
+                .map(|(path, tags)| {
+                    (
+                        canonicalize_or_keep(Path::new(shellexpand::tilde(&path).as_ref())),
+                        tags,
+                    )
+                })
+                .collect(),
+            favorites: raw_config
+                .favorites
+                .iter()
+                // found workspaces never have a trailing slash, so favorites wouldn't match them with one
+                .map(|path| {
+                    let path = shellexpand::tilde(path);
+                    match path.trim_end_matches('/') {
+                        "" => "/".to_owned(),
+                        trimmed => trimmed.to_owned(),
+                    }
+                })
+                .collect(),
+        })
     }
 }
 
@@ -316,7 +1257,12 @@ impl FromStr for RawTwmGlobal {
 }
 
 impl TwmGlobal {
-    fn get_config_path() -> Result<Option<PathBuf>> {
+    /// Gets the name of the environment variable twm sets in its sessions for `name`, e.g. `TWM_ROOT` for `ROOT`.
+    pub fn env_var(&self, name: &str) -> String {
+        format!("{}_{name}", self.env_prefix)
+    }
+
+    pub fn get_config_path() -> Result<Option<PathBuf>> {
         let config_file_name = format!("{}.yaml", clap::crate_name!());
         match std::env::var_os("TWM_CONFIG_FILE") {
             // if TWM_CONFIG_FILE is not set, search xdg dirs for config file as normal
@@ -340,14 +1286,196 @@ impl TwmGlobal {
         }
     }
 
-    pub fn load() -> Result<Self> {
-        let raw_config = match TwmGlobal::get_config_path()? {
-            Some(path) => RawTwmGlobal::try_from(&path)?,
-            None => RawTwmGlobal::default(),
+    /// Gets the tags configured for the workspace at `path`, however the path is written in the config.
+    pub fn tags_for_workspace(&self, path: &Path) -> &[String] {
+        if self.workspace_tags.is_empty() {
+            return &[];
+        }
+        // the keys were canonicalized when loading the config, so most found paths already match them as they are
+        self.workspace_tags
+            .get(path)
+            .or_else(|| self.workspace_tags.get(&canonicalize_or_keep(path)))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Expands a `<shorthand>:<path>` prefix from `project_roots` in the given path.
+    ///
+    /// Paths without a known shorthand prefix are returned unchanged.
+    pub fn expand_project_root(&self, path: &str) -> String {
+        if let Some((shorthand, rest)) = path.split_once(':') {
+            if let Some(root) = self.project_roots.get(shorthand) {
+                return Path::new(root).join(rest).to_string_lossy().into_owned();
+            }
+        }
+        path.to_owned()
+    }
+
+    /// Gets how deep to search for workspaces inside the search path `search_path`, which is its own `max_depth` if
+    /// it set one and `max_search_depth` otherwise.
+    pub fn max_search_depth_for(&self, search_path: &str) -> usize {
+        self.search_path_depths
+            .get(search_path)
+            .copied()
+            .unwrap_or(self.max_search_depth)
+    }
+
+    /// Restricts `search_paths` to the single search path selected by `scope`.
+    ///
+    /// `scope` can either be the index of the search path, starting from 0, or a prefix of exactly one search path.
+    /// Shell expansion is supported in the prefix.
+    pub fn restrict_search_paths(&mut self, scope: &str) -> Result<()> {
+        let search_path = if let Ok(index) = scope.parse::<usize>() {
+            self.search_paths.get(index).with_context(|| {
+                format!(
+                    "Scope `{scope}` is out of range, only {} search paths are configured.",
+                    self.search_paths.len()
+                )
+            })?
+        } else {
+            let prefix = shellexpand::tilde(scope);
+            let mut matches = self.search_paths.iter().filter(|p| p.starts_with(&*prefix));
+            match (matches.next(), matches.next()) {
+                (Some(search_path), None) => search_path,
+                (None, _) => anyhow::bail!("Scope `{scope}` doesn't match any search path."),
+                (Some(_), Some(_)) => {
+                    // an exact match is fine even if it's also a prefix of another search path
+                    self.search_paths
+                        .iter()
+                        .find(|p| **p == prefix)
+                        .with_context(|| {
+                            format!("Scope `{scope}` matches multiple search paths.")
+                        })?
+                }
+            }
         };
-        let config = TwmGlobal::from(raw_config);
+        self.search_paths = vec![search_path.clone()];
+        Ok(())
+    }
+
+    /// Loads the config file, applying the profile with the given name if one is given.
+    pub fn load(profile: Option<&str>) -> crate::Result<Self> {
+        Self::load_file(profile).map_err(crate::Error::Config)
+    }
+
+    fn load_file(profile: Option<&str>) -> Result<Self> {
+        let config_path = TwmGlobal::get_config_path()?;
+        let mut raw_config = match &config_path {
+            Some(path) => RawTwmGlobal::try_from(path)?,
+            None => RawTwmGlobal {
+                max_search_depth: NO_CONFIG_MAX_SEARCH_DEPTH,
+                ..RawTwmGlobal::default()
+            },
+        };
+        if let Some(profile) = profile {
+            raw_config = raw_config.apply_profile(profile)?;
+        }
+        let builtin_definitions = raw_config.uses_builtin_definitions();
+        let mut config = TwmGlobal::try_from(raw_config)?;
+        config.using_defaults = config_path.is_none();
+        // a layout that isn't defined would otherwise just leave its commands out of the session
+        let config_dir = config_path.as_deref().and_then(Path::parent);
+        if let Some(error) = config
+            .check_layouts(config_dir, !builtin_definitions)
+            .into_iter()
+            .next()
+        {
+            return Err(error);
+        }
         Ok(config)
     }
+
+    /// Checks the config file at `path` without falling back to defaults, returning every problem found.
+    ///
+    /// On top of everything checked when loading the config, layout names are checked for duplicates, and every
+    /// profile is checked by applying it.
+    pub fn check(path: &PathBuf) -> Vec<anyhow::Error> {
+        let raw_config = match RawTwmGlobal::try_from(path) {
+            Ok(raw_config) => raw_config,
+            Err(e) => return vec![e],
+        };
+        let mut profile_names: Vec<String> = raw_config.profiles.keys().cloned().collect();
+        profile_names.sort_unstable();
+        let mut errors = Vec::new();
+        let mut reported = HashSet::new();
+        for profile in std::iter::once(None).chain(profile_names.iter().map(Some)) {
+            let raw_config = match profile {
+                Some(profile) => raw_config.clone().apply_profile(profile),
+                None => Ok(raw_config.clone()),
+            };
+            let problems = match raw_config.and_then(|raw_config| {
+                let builtin_definitions = raw_config.uses_builtin_definitions();
+                Ok((TwmGlobal::try_from(raw_config)?, builtin_definitions))
+            }) {
+                Ok((mut config, builtin_definitions)) => {
+                    let mut problems = check_layout_names(&config.layouts);
+                    problems.extend(config.check_layouts(path.parent(), !builtin_definitions));
+                    problems
+                }
+                Err(e) => vec![e],
+            };
+            for problem in problems {
+                // profiles only override some options, so only report the problems they introduce themselves
+                if !reported.insert(format!("{problem:#}")) {
+                    continue;
+                }
+                errors.push(match profile {
+                    Some(profile) => problem.context(format!("In profile `{profile}`")),
+                    None => problem,
+                });
+            }
+        }
+        errors
+    }
+
+    /// Checks that every layout referenced in the config is defined and can be loaded.
+    ///
+    /// The layouts of workspace definitions are only checked if `check_definitions` is set.
+    fn check_layouts(
+        &mut self,
+        config_dir: Option<&Path>,
+        check_definitions: bool,
+    ) -> Vec<anyhow::Error> {
+        let mut errors = check_layout_inheritance(&self.layouts);
+        if let Some(config_dir) = config_dir {
+            for layout in &mut self.layouts {
+                if let Err(e) = layout.load_commands_file(config_dir) {
+                    errors.push(e);
+                }
+            }
+        }
+        for (option, layout) in [
+            ("fallback_layout", &self.fallback_layout),
+            ("scratch_layout", &self.scratch_layout),
+        ] {
+            if let Some(layout) = layout {
+                if get_layout_by_name(layout, &self.layouts).is_none() {
+                    errors.push(anyhow::anyhow!(
+                        "`{option}` is set to layout `{layout}`, which isn't defined."
+                    ));
+                }
+            }
+        }
+        for definition in self
+            .workspace_definitions
+            .iter()
+            .filter(|_| check_definitions)
+        {
+            let Some(layout) = &definition.default_layout else {
+                continue;
+            };
+            // the fallback layout was already applied to the definitions, so it would be reported again here
+            if Some(layout) != self.fallback_layout.as_ref()
+                && get_layout_by_name(layout, &self.layouts).is_none()
+            {
+                errors.push(anyhow::anyhow!(
+                    "Workspace definition `{}` uses layout `{layout}`, which isn't defined.",
+                    definition.name
+                ));
+            }
+        }
+        errors
+    }
 }
 
 impl FromStr for TwmLayout {
@@ -368,6 +1496,99 @@ impl FromStr for TwmLayout {
     }
 }
 
+/// A session to open, listed in a file given with `--sessions-file`.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SessionEntry {
+    /// Path of the workspace to open. Relative paths are relative to the directory of the sessions file.
+    pub path: String,
+    /// Name of the session, generated from the path as usual if unset.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Name of the layout to open the session with, chosen as usual if unset.
+    #[serde(default)]
+    pub layout: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SessionsFile {
+    Twm(TwmSessionsFile),
+    /// Only the parts of a tmuxinator project that map onto a twm session, the windows and panes are ignored.
+    Tmuxinator {
+        name: String,
+        root: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TwmSessionsFile {
+    sessions: Vec<SessionEntry>,
+}
+
+impl SessionEntry {
+    /// Loads the sessions listed in a twm sessions file or a tmuxinator project file.
+    pub fn load_all(path: &Path) -> Result<Vec<Self>> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read sessions file: {path:#?}"))?;
+        let mut sessions = Self::parse_all(&contents)
+            .with_context(|| format!("Failed to parse sessions file: {path:#?}"))?;
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        for session in &mut sessions {
+            let expanded = shellexpand::tilde(&session.path);
+            session.path = base_dir
+                .join(expanded.as_ref())
+                .to_string_lossy()
+                .into_owned();
+        }
+        Ok(sessions)
+    }
+
+    fn parse_all(contents: &str) -> Result<Vec<Self>> {
+        Ok(match serde_yaml::from_str(contents)? {
+            SessionsFile::Twm(file) => file.sessions,
+            SessionsFile::Tmuxinator { name, root } => vec![Self {
+                path: root,
+                name: Some(name),
+                layout: None,
+            }],
+        })
+    }
+}
+
+/// A layout configured for a workspace from inside the workspace directory or one of its parents.
+#[derive(Debug, Clone)]
+pub enum LocalLayout {
+    /// A full layout definition from a `.twm.yaml` file.
+    Config(TwmLayout),
+    /// The name of a globally defined layout from a `.twm-layout` file.
+    Named(String),
+}
+
+impl LocalLayout {
+    /// Attempts to load a local layout from the given path.
+    /// A `.twm.yaml` file takes precedence over a `.twm-layout` file in the same directory.
+    /// Will return Ok(None) if neither file is found.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        const LAYOUT_POINTER_FILE_NAME: &str = ".twm-layout";
+        if let Some(config) = TwmLayout::load(path)? {
+            return Ok(Some(Self::Config(config)));
+        }
+        let pointer_path = path.join(LAYOUT_POINTER_FILE_NAME);
+        if !pointer_path.exists() {
+            return Ok(None);
+        }
+        let name = fs::read_to_string(&pointer_path)
+            .with_context(|| format!("Failed to read layout name from path: {pointer_path:#?}"))?;
+        let name = name.trim();
+        if name.is_empty() {
+            anyhow::bail!("Layout file {pointer_path:#?} doesn't contain a layout name.");
+        }
+        Ok(Some(Self::Named(name.to_owned())))
+    }
+}
+
 impl TwmLayout {
     /// Attemps to load a local config file from the given path.
     /// Will return Ok(None) if no config file is found.
@@ -378,7 +1599,9 @@ impl TwmLayout {
         if config_path.exists() {
             let config = fs::read_to_string(&config_path)
                 .with_context(|| format!("Failed to read config from path: {config_path:#?}"))?;
-            Ok(Some(TwmLayout::from_str(&config)?))
+            let mut local_config = TwmLayout::from_str(&config)?;
+            local_config.layout.load_commands_file(path)?;
+            Ok(Some(local_config))
         } else {
             Ok(None)
         }
@@ -389,14 +1612,16 @@ impl TwmLayout {
 mod tests {
 
     use crate::handler::DEFAULT_LAYOUT_CONFIG_TEMPLATE;
+    use crate::layout::ResolvedLayout;
 
     use super::*;
+    use crate::test_utils::{config_from_yaml, TestDir};
     use serial_test::serial;
 
     #[test]
     fn test_empty_config_is_valid() {
         let raw_config = RawTwmGlobal::from_str("").unwrap();
-        let _ = TwmGlobal::from(raw_config);
+        let _ = TwmGlobal::try_from(raw_config).unwrap();
     }
 
     #[test]
@@ -405,6 +1630,448 @@ mod tests {
         assert!(raw_config.is_err());
     }
 
+    #[test]
+    fn test_invalid_git_branch_pattern_is_error() {
+        let raw_config = RawTwmGlobal::from_str(
+            r#"
+workspace_definitions:
+  - name: release
+    git_branch: "release/("
+"#,
+        )
+        .unwrap();
+        assert!(TwmGlobal::try_from(raw_config).is_err());
+    }
+
+    #[test]
+    fn test_has_any_file_matching() {
+        let config = config_from_yaml(
+            r#"
+workspace_definitions:
+  - name: dotnet
+    has_any_file_matching: ['\.sln$', '^Cargo\.']
+"#,
+        );
+        assert_eq!(
+            config.workspace_definitions[0].conditions,
+            [WorkspaceConditionEnum::from(
+                RegexCondition::new(vec![r"\.sln$".into(), r"^Cargo\.".into()]).unwrap()
+            )]
+        );
+        let raw_config = RawTwmGlobal::from_str(
+            r#"
+workspace_definitions:
+  - name: broken
+    has_any_file_matching: ["("]
+"#,
+        )
+        .unwrap();
+        assert!(TwmGlobal::try_from(raw_config).is_err());
+    }
+
+    #[test]
+    fn test_fallback_layout_applies_unless_opted_out() {
+        let config = config_from_yaml(
+            r#"
+fallback_layout: fallback
+workspace_definitions:
+  - name: unset
+  - name: named
+    default_layout: named
+  - name: opted-out
+    no_default_layout: true
+"#,
+        );
+        let layouts: Vec<Option<&str>> = config
+            .workspace_definitions
+            .iter()
+            .map(|d| d.default_layout.as_deref())
+            .collect();
+        assert_eq!(layouts, vec![Some("fallback"), Some("named"), None]);
+    }
+
+    #[test]
+    fn test_search_path_max_depth() {
+        let config = config_from_yaml(
+            r#"
+search_paths: ["~/work", { path: "~/experiments", max_depth: 1 }, { path: /src }]
+max_search_depth: 2
+"#,
+        );
+        let experiments = shellexpand::tilde("~/experiments").to_string();
+        assert_eq!(
+            config.search_paths,
+            [shellexpand::tilde("~/work").as_ref(), &experiments, "/src"]
+        );
+        assert_eq!(config.max_search_depth_for(&experiments), 1);
+        assert_eq!(config.max_search_depth_for("/src"), 2);
+        assert!(RawTwmGlobal::from_str("search_paths: [{ path: /src, depth: 1 }]").is_err());
+    }
+
+    #[test]
+    fn test_apply_profile() {
+        let raw_config = RawTwmGlobal::from_str(
+            r#"
+search_paths: ["~/personal", "~/scratch"]
+max_search_depth: 5
+profiles:
+  work:
+    search_paths: ["~/work"]
+    grouped: true
+"#,
+        )
+        .unwrap();
+        let config = TwmGlobal::try_from(raw_config.apply_profile("work").unwrap()).unwrap();
+        assert_eq!(
+            config.search_paths,
+            vec![shellexpand::tilde("~/work").to_string()]
+        );
+        assert!(config.grouped);
+        assert_eq!(config.max_search_depth, 5);
+    }
+
+    #[test]
+    fn test_apply_profile_errors() {
+        let raw_config = RawTwmGlobal::from_str(
+            r#"
+profiles:
+  work:
+    not_an_option: true
+"#,
+        )
+        .unwrap();
+        assert!(raw_config.apply_profile("personal").is_err());
+        let raw_config =
+            RawTwmGlobal::from_str("profiles:\n  work:\n    not_an_option: true").unwrap();
+        assert!(raw_config.apply_profile("work").is_err());
+    }
+
+    #[test]
+    fn test_restrict_search_paths() {
+        let config = config_from_yaml("search_paths: [/src/work, /src/work-old, /home/vinny]");
+        let scoped = |scope: &str| {
+            let mut config = config.clone();
+            config
+                .restrict_search_paths(scope)
+                .map(|_| config.search_paths)
+        };
+        assert_eq!(scoped("2").unwrap(), vec!["/home/vinny"]);
+        assert_eq!(scoped("/home").unwrap(), vec!["/home/vinny"]);
+        assert_eq!(scoped("/src/work").unwrap(), vec!["/src/work"]);
+        assert!(scoped("/src/wo").is_err());
+        assert!(scoped("/tmp").is_err());
+        assert!(scoped("3").is_err());
+    }
+
+    #[test]
+    fn test_unselectable_layouts_can_be_inherited() {
+        let config = config_from_yaml(
+            r#"
+layouts:
+  - name: base
+    selectable: false
+    commands: [tmux split-window -h]
+  - name: rust
+    inherits: [base]
+    commands: [nvim .]
+"#,
+        );
+        assert_eq!(
+            crate::layout::get_layout_names(&config.layouts),
+            vec!["rust"]
+        );
+        assert_eq!(
+            crate::layout::get_commands_from_layout_name("rust", &config.layouts)
+                .into_iter()
+                .map(|command| command.command().unwrap().to_owned())
+                .collect::<Vec<_>>(),
+            vec!["tmux split-window -h", "nvim ."]
+        );
+    }
+
+    #[test]
+    fn test_inheritance_cycles_are_broken() {
+        let config = config_from_yaml(
+            r#"
+layouts:
+  - name: a
+    inherits: [b]
+    commands: [echo a]
+  - name: b
+    inherits: [a]
+    commands: [echo b]
+  - name: self
+    inherits: [self]
+    commands: [echo self]
+"#,
+        );
+        let commands = |name| {
+            crate::layout::get_commands_from_layout_name(name, &config.layouts)
+                .into_iter()
+                .map(|command| command.command().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(commands("a"), ["echo b", "echo a"]);
+        assert_eq!(commands("b"), ["echo a", "echo b"]);
+        assert_eq!(commands("self"), ["echo self"]);
+        let zoom_pane =
+            |name| crate::layout::ResolvedLayout::from_layout_name(name, &config.layouts).zoom_pane;
+        assert_eq!(zoom_pane("a"), None);
+        assert_eq!(zoom_pane("self"), None);
+    }
+
+    #[test]
+    fn test_local_layout_inherits_global_layout_with_same_name() {
+        let config = config_from_yaml(
+            r#"
+layouts:
+  - name: rust
+    commands: [cargo build]
+"#,
+        );
+        let local: TwmLayout = serde_yaml::from_str(
+            r#"
+layout:
+  name: rust
+  inherits: [rust]
+  commands: [nvim .]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ResolvedLayout::from_layout(&local.layout, &config.layouts)
+                .commands
+                .into_iter()
+                .map(|command| command.command().unwrap().to_owned())
+                .collect::<Vec<_>>(),
+            ["cargo build", "nvim ."]
+        );
+    }
+
+    #[test]
+    fn test_zoom_pane_is_inherited() {
+        let config = config_from_yaml(
+            r#"
+layouts:
+  - name: base
+    zoom_pane: 0
+  - name: rust
+    inherits: [base]
+  - name: python
+    inherits: [base]
+    zoom_pane: 2
+"#,
+        );
+        let zoom_pane = |name| ResolvedLayout::from_layout_name(name, &config.layouts).zoom_pane;
+        assert_eq!(zoom_pane("rust"), Some(0));
+        assert_eq!(zoom_pane("python"), Some(2));
+        assert_eq!(zoom_pane("default"), None);
+    }
+
+    #[test]
+    fn test_composed_layouts_run_in_selection_order() {
+        let config = config_from_yaml(
+            r#"
+layouts:
+  - name: base
+    commands: ["echo base"]
+    zoom_pane: 1
+  - name: debugger
+    inherits: [base]
+    commands: ["echo debugger"]
+"#,
+        );
+        let layout = ResolvedLayout::from_layout_names(
+            &["debugger".to_owned(), "base".to_owned()],
+            &config.layouts,
+        );
+        let commands: Vec<String> = layout.commands.iter().map(|c| c.to_string()).collect();
+        assert_eq!(commands, ["echo base", "echo debugger", "echo base"]);
+        assert_eq!(layout.zoom_pane, Some(1));
+    }
+
+    #[test]
+    fn test_layout_windows_expand_into_commands() {
+        let config = config_from_yaml(
+            r#"
+layouts:
+  - name: base
+    commands: ["echo base"]
+  - name: rust
+    inherits: [base]
+    windows:
+      - name: editor
+        layout: main-vertical
+        panes:
+          - commands: [nvim .]
+          - commands: [cargo watch, clear]
+      - panes: [{}]
+    commands: ["echo rust"]
+"#,
+        );
+        let layout = ResolvedLayout::from_layout_name("rust", &config.layouts);
+        let commands: Vec<String> = layout.commands.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            commands,
+            [
+                "echo base",
+                "[window editor]",
+                "nvim .",
+                "[split pane]",
+                "cargo watch",
+                "clear",
+                "[select layout main-vertical]",
+                "[window]",
+                "echo rust"
+            ]
+        );
+        assert!(RawTwmGlobal::from_str(
+            "layouts:\n  - name: foo\n    windows: [{ panes: [{ command: nvim }] }]"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_default_layout_with_no_default_layout_is_error() {
+        let raw_config = RawTwmGlobal::from_str(
+            r#"
+workspace_definitions:
+  - name: conflicting
+    default_layout: named
+    no_default_layout: true
+"#,
+        )
+        .unwrap();
+        assert!(TwmGlobal::try_from(raw_config).is_err());
+    }
+
+    #[test]
+    fn test_workspace_tags_match_however_the_path_is_written() {
+        let root = TestDir::new("workspace-tags");
+        fs::create_dir_all(root.join("src/api")).unwrap();
+        std::os::unix::fs::symlink(root.join("src"), root.join("link")).unwrap();
+        let config = config_from_yaml(&format!(
+            "workspace_tags:\n  {root}/src/api/: [backend]\n  {root}/missing//web: [frontend]",
+            root = root.display()
+        ));
+        let tags = |path: PathBuf| config.tags_for_workspace(&path).to_vec();
+        assert_eq!(tags(root.join("src/api")), ["backend"]);
+        assert_eq!(tags(root.join("src/../src/api")), ["backend"]);
+        assert_eq!(tags(root.join("link/api")), ["backend"]);
+        assert_eq!(tags(root.join("missing/web")), ["frontend"]);
+        assert!(tags(root.join("src")).is_empty());
+    }
+
+    #[test]
+    fn test_check_reports_every_problem() {
+        let dir = TestDir::new("check-config");
+        let path = dir.join("twm.yaml");
+        fs::write(
+            &path,
+            r#"
+fallback_layout: missing
+workspace_definitions:
+  - name: rust
+    has_any_file: [Cargo.toml]
+    default_layout: also-missing
+layouts:
+  - name: a
+    inherits: [b]
+  - name: b
+    inherits: [a, c]
+profiles:
+  broken:
+    discovery_threads: 0
+  fine:
+    max_search_depth: 5
+"#,
+        )
+        .unwrap();
+        let errors: Vec<String> = TwmGlobal::check(&path)
+            .iter()
+            .map(|e| format!("{e:#}"))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "Layouts inherit from each other in a cycle: a -> b -> a",
+                "Layout `b` inherits from layout `c`, which isn't defined.",
+                "`fallback_layout` is set to layout `missing`, which isn't defined.",
+                "Workspace definition `rust` uses layout `also-missing`, which isn't defined.",
+                "In profile `broken`: `discovery_threads` must be greater than 0.",
+            ]
+        );
+        fs::write(&path, "max_search_depth: 2").unwrap();
+        assert!(TwmGlobal::check(&path).is_empty());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(TwmGlobal::check(&path).len(), 1);
+    }
+
+    #[test]
+    fn test_check_layouts_reports_undefined_layouts() {
+        let raw_config = RawTwmGlobal::from_str(
+            r#"
+scratch_layout: missing
+layouts:
+  - name: base
+  - name: rust
+    inherits: [base, gone]
+"#,
+        )
+        .unwrap();
+        let builtin_definitions = raw_config.uses_builtin_definitions();
+        let mut config = TwmGlobal::try_from(raw_config).unwrap();
+        let errors: Vec<String> = config
+            .check_layouts(None, !builtin_definitions)
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        // the built-in definitions use the built-in `default` layout, which isn't reported
+        assert_eq!(
+            errors,
+            [
+                "Layout `rust` inherits from layout `gone`, which isn't defined.",
+                "`scratch_layout` is set to layout `missing`, which isn't defined.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zero_discovery_threads_is_error() {
+        let raw_config = RawTwmGlobal::from_str("discovery_threads: 0").unwrap();
+        assert!(TwmGlobal::try_from(raw_config).is_err());
+    }
+
+    #[test]
+    fn test_zero_session_name_path_components_is_error() {
+        let raw_config = RawTwmGlobal::from_str("session_name_path_components: 0").unwrap();
+        let err = TwmGlobal::try_from(raw_config).unwrap_err();
+        assert!(err.to_string().contains("session_name_path_components"));
+    }
+
+    #[test]
+    fn test_expand_project_root() {
+        let config = config_from_yaml("project_roots:\n  work: /home/vinny/work");
+        assert_eq!(
+            config.expand_project_root("work:service-a"),
+            "/home/vinny/work/service-a"
+        );
+        assert_eq!(config.expand_project_root("play:foo"), "play:foo");
+        assert_eq!(config.expand_project_root("/tmp/foo"), "/tmp/foo");
+    }
+
+    #[test]
+    fn test_attach_command_template_requires_placeholder() {
+        let raw_config =
+            RawTwmGlobal::from_str("attach_command_template: kitty tmux attach").unwrap();
+        assert!(TwmGlobal::try_from(raw_config).is_err());
+
+        let raw_config =
+            RawTwmGlobal::from_str("attach_command_template: kitty tmux attach -t {session}")
+                .unwrap();
+        assert!(TwmGlobal::try_from(raw_config).is_ok());
+    }
+
     /// Make noise if we change which env var overrides the config file path or it breaks
     #[test]
     #[serial]
@@ -522,6 +2189,82 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_layout_pane_commands() {
+        let layout = TwmLayout::from_str(
+            "layout:\n  name: foo\n  commands:\n    - tmux split-window -h\n    - { pane: 1, command: nvim . }",
+        )
+        .unwrap()
+        .layout;
+        let commands = layout.commands.unwrap();
+        assert_eq!(commands[0].pane(), None);
+        assert_eq!(commands[1].pane(), Some(1));
+        assert_eq!(commands[1].command(), Some("nvim ."));
+        assert!(TwmLayout::from_str(
+            "layout:\n  name: foo\n  commands: [{ pane: 1, cmd: nvim . }]"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_layout_commands_file() {
+        let dir = TestDir::new("layout-commands-file");
+        std::fs::write(dir.join("commands.sh"), "tmux split-window -h\n\nnvim .\n").unwrap();
+        let mut layout = TwmLayout::from_str("layout:\n  name: foo\n  commands_file: commands.sh")
+            .unwrap()
+            .layout;
+        layout.load_commands_file(&dir).unwrap();
+        assert_eq!(
+            layout.commands,
+            Some(vec!["tmux split-window -h".into(), "nvim .".into()])
+        );
+
+        let mut missing = TwmLayout::from_str("layout:\n  name: foo\n  commands_file: missing.sh")
+            .unwrap()
+            .layout;
+        assert!(missing.load_commands_file(&dir).is_err());
+    }
+
+    #[test]
+    fn test_parse_sessions_file() {
+        let sessions = SessionEntry::parse_all(
+            "sessions:\n  - path: ~/foo\n  - path: bar\n    name: baz\n    layout: rust-dev",
+        )
+        .unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].name, None);
+        assert_eq!(sessions[1].layout.as_deref(), Some("rust-dev"));
+        let sessions =
+            SessionEntry::parse_all("name: foo\nroot: ~/foo\nwindows:\n  - editor: vim").unwrap();
+        assert_eq!(
+            sessions,
+            [SessionEntry {
+                path: "~/foo".into(),
+                name: Some("foo".into()),
+                layout: None,
+            }]
+        );
+        assert!(SessionEntry::parse_all("sessions:\n  - name: foo").is_err());
+    }
+
+    #[test]
+    fn test_local_layout_precedence() {
+        let dir = TestDir::new("local-layout-precedence");
+        assert!(LocalLayout::load(&dir).unwrap().is_none());
+
+        std::fs::write(dir.join(".twm-layout"), "rust-dev\n").unwrap();
+        assert!(matches!(
+            LocalLayout::load(&dir).unwrap(),
+            Some(LocalLayout::Named(name)) if name == "rust-dev"
+        ));
+
+        std::fs::write(dir.join(".twm.yaml"), DEFAULT_LAYOUT_CONFIG_TEMPLATE).unwrap();
+        assert!(matches!(
+            LocalLayout::load(&dir).unwrap(),
+            Some(LocalLayout::Config(_))
+        ));
+    }
+
     #[test]
     fn test_default_layout_config_template_is_valid() {
         TwmLayout::from_str(DEFAULT_LAYOUT_CONFIG_TEMPLATE).unwrap();
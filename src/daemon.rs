@@ -0,0 +1,399 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{ErrorKind, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::crate_name;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+
+use crate::config::TwmGlobal;
+use crate::matches::{is_hidden, path_is_excluded, scan_workspaces_in_dir, SymlinkCycles};
+
+const SOCKET_FILE_NAME: &str = "daemon.sock";
+
+/// How long to wait for more filesystem changes before rescanning, since changes usually come in bursts.
+const RESCAN_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How long to wait for the daemon to answer before falling back to searching for workspaces normally.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long the daemon waits on a client sending its query or reading the answer before dropping it, so a stuck
+/// client can't keep others waiting.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A workspace found by the daemon.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndexedWorkspace {
+    pub path: String,
+    pub workspace_type: Option<String>,
+}
+
+/// The workspaces found in each search path, keyed by the search path.
+pub type WorkspaceIndex = HashMap<String, Vec<IndexedWorkspace>>;
+
+/// The options deciding which workspaces are found in the search paths.
+///
+/// Clients send theirs with every query, and the daemon only answers with its index if they match the ones it was
+/// started with, so e.g. a different `--profile` doesn't get workspaces found with another configuration. The search
+/// paths themselves aren't part of it, since clients only take the search paths they're searching from the index.
+#[derive(Serialize)]
+struct IndexKey<'a> {
+    search_path_depths: &'a HashMap<String, usize>,
+    max_search_depth: usize,
+    exclude_path_components: &'a [String],
+    case_insensitive_excludes: bool,
+    workspace_definitions: &'a [crate::workspace::WorkspaceDefinition],
+    follow_links: bool,
+    search_hidden: bool,
+    include_hidden_workspaces: bool,
+    respect_gitignore: bool,
+    collapse_to_git_root: bool,
+    max_injected: usize,
+}
+
+/// Gets the key identifying the index built for `config`.
+///
+/// It's converted to a JSON value, whose maps are sorted, so keys can be compared however they were serialized.
+fn index_key(config: &TwmGlobal) -> serde_json::Value {
+    serde_json::to_value(IndexKey {
+        search_path_depths: &config.search_path_depths,
+        max_search_depth: config.max_search_depth,
+        exclude_path_components: &config.exclude_path_components,
+        case_insensitive_excludes: config.case_insensitive_excludes,
+        workspace_definitions: &config.workspace_definitions,
+        follow_links: config.follow_links,
+        search_hidden: config.search_hidden,
+        include_hidden_workspaces: config.include_hidden_workspaces,
+        respect_gitignore: config.respect_gitignore,
+        collapse_to_git_root: config.collapse_to_git_root,
+        max_injected: config.max_injected,
+    })
+    .expect("Index key can always be serialized")
+}
+
+/// Gets the current workspace index from a running daemon.
+///
+/// Errors if the daemon isn't running, doesn't answer in time, or indexes the search paths with a configuration
+/// other than `config`.
+pub fn query_index(config: &TwmGlobal) -> Result<WorkspaceIndex> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(crate_name!())?;
+    let socket_path = xdg_dirs
+        .find_runtime_file(SOCKET_FILE_NAME)
+        .context("The twm daemon isn't running.")?;
+    query_index_at(&socket_path, &index_key(config))
+}
+
+fn query_index_at(socket_path: &Path, key: &serde_json::Value) -> Result<WorkspaceIndex> {
+    let mut stream = UnixStream::connect(socket_path)?;
+    stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    stream.set_write_timeout(Some(QUERY_TIMEOUT))?;
+    serde_json::to_writer(&mut stream, key)?;
+    // the daemon reads the query until the end of the stream
+    stream.shutdown(Shutdown::Write)?;
+    let index: Option<WorkspaceIndex> = serde_json::from_reader(stream)?;
+    index.context("The twm daemon was started with a different configuration.")
+}
+
+/// Runs the daemon, keeping an index of the workspaces in all search paths up to date until killed.
+pub fn run(config: TwmGlobal) -> Result<()> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(crate_name!())?;
+    let socket_path = xdg_dirs
+        .place_runtime_file(SOCKET_FILE_NAME)
+        .context("Failed to create the twm daemon socket. Is $XDG_RUNTIME_DIR set?")?;
+    let listener = bind_socket(&socket_path)?;
+
+    let key = index_key(&config);
+    let index: Arc<RwLock<WorkspaceIndex>> = Arc::default();
+    // kept across rescans so each link is only warned about once
+    let symlink_cycles: Arc<SymlinkCycles> = Arc::default();
+    let (tx, rx) = channel();
+    let mut watches = Watches {
+        watcher: notify::recommended_watcher(tx)?,
+        watched: HashMap::new(),
+    };
+    for dir in &config.search_paths {
+        watches.update(dir, &config)?;
+        rescan(dir, &config, &index, &symlink_cycles);
+    }
+    eprintln!(
+        "twm daemon is indexing {} search paths, listening on {}",
+        config.search_paths.len(),
+        socket_path.display()
+    );
+
+    let rescan_index = index.clone();
+    std::thread::spawn(move || {
+        rescan_on_changes(&config, &rescan_index, &symlink_cycles, &mut watches, &rx);
+    });
+
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        // a client going away or sending garbage isn't our problem
+        let _ = serve_client(stream, &key, &index);
+    }
+    Ok(())
+}
+
+/// Answers a client's query with the index, or with `null` if the client's configuration doesn't match `key`.
+fn serve_client(
+    mut stream: UnixStream,
+    key: &serde_json::Value,
+    index: &RwLock<WorkspaceIndex>,
+) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let client_key: serde_json::Value = serde_json::from_reader(&stream)?;
+    let response = if client_key == *key {
+        // serialized before writing so a slow client doesn't hold up rescans waiting for the lock
+        let index = index.read().expect("Workspace index lock poisoned");
+        serde_json::to_vec(&Some(&*index))?
+    } else {
+        serde_json::to_vec(&None::<WorkspaceIndex>)?
+    };
+    stream.write_all(&response)?;
+    Ok(())
+}
+
+/// The directories watched for changes in each search path.
+///
+/// Only directories up to the search depth are watched, each on its own, since a recursive watch on a search path
+/// like `~` would watch every directory below it.
+struct Watches {
+    watcher: RecommendedWatcher,
+    watched: HashMap<String, HashSet<PathBuf>>,
+}
+
+impl Watches {
+    /// Watches the directories currently in the search path `root`, and stops watching the ones that are gone.
+    fn update(&mut self, root: &str, config: &TwmGlobal) -> Result<()> {
+        self.watcher
+            .watch(Path::new(root), RecursiveMode::NonRecursive)
+            .with_context(|| format!("Failed to watch search path {root} for changes."))?;
+        let mut dirs = HashSet::new();
+        find_watched_dirs(Path::new(root), Path::new(root), 0, config, &mut dirs);
+        let previous = self.watched.insert(root.to_owned(), dirs.clone());
+        for dir in previous.unwrap_or_default().difference(&dirs) {
+            // nested search paths can watch the same directories
+            if !self.watched.values().any(|watched| watched.contains(dir)) {
+                let _ = self.watcher.unwatch(dir);
+            }
+        }
+        for dir in dirs.iter().filter(|dir| *dir != Path::new(root)) {
+            // directories can disappear while they're being found, which the next rescan will take care of
+            let _ = self.watcher.watch(dir, RecursiveMode::NonRecursive);
+        }
+        Ok(())
+    }
+}
+
+/// Finds the directories in the search path `root` whose entries can change which workspaces are found in it.
+fn find_watched_dirs(
+    dir: &Path,
+    root: &Path,
+    depth: usize,
+    config: &TwmGlobal,
+    dirs: &mut HashSet<PathBuf>,
+) {
+    dirs.insert(dir.to_path_buf());
+    // the entries of a workspace at the search depth decide whether it matches, but nothing deeper does
+    if depth >= config.max_search_depth_for(&root.to_string_lossy()) {
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(std::result::Result::ok) {
+        let path = entry.path();
+        let is_dir = match config.follow_links {
+            true => path.is_dir(),
+            false => entry.file_type().is_ok_and(|t| t.is_dir()),
+        };
+        if !is_dir || path_is_excluded(&path, root, config) {
+            continue;
+        }
+        if is_hidden(&entry.file_name()) && !config.search_hidden {
+            // hidden directories aren't searched, but can still be matched as workspaces themselves
+            if config.include_hidden_workspaces {
+                dirs.insert(path);
+            }
+            continue;
+        }
+        find_watched_dirs(&path, root, depth + 1, config, dirs);
+    }
+}
+
+/// Binds the daemon socket, cleaning up a socket left behind by a daemon that didn't exit cleanly.
+fn bind_socket(socket_path: &Path) -> Result<UnixListener> {
+    match UnixListener::bind(socket_path) {
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            if UnixStream::connect(socket_path).is_ok() {
+                anyhow::bail!("The twm daemon is already running.");
+            }
+            std::fs::remove_file(socket_path)?;
+            Ok(UnixListener::bind(socket_path)?)
+        }
+        listener => Ok(listener?),
+    }
+}
+
+fn rescan(
+    dir: &str,
+    config: &TwmGlobal,
+    index: &RwLock<WorkspaceIndex>,
+    symlink_cycles: &Arc<SymlinkCycles>,
+) {
+    let workspaces = scan_workspaces_in_dir(dir, config, symlink_cycles)
+        .into_iter()
+        .map(|item| IndexedWorkspace {
+            path: item.text,
+            workspace_type: item.group,
+        })
+        .collect();
+    index
+        .write()
+        .expect("Workspace index lock poisoned")
+        .insert(dir.to_owned(), workspaces);
+    symlink_cycles.report();
+}
+
+/// Rescans the search paths containing changed files whenever the watcher reports changes.
+fn rescan_on_changes(
+    config: &TwmGlobal,
+    index: &RwLock<WorkspaceIndex>,
+    symlink_cycles: &Arc<SymlinkCycles>,
+    watches: &mut Watches,
+    events: &Receiver<notify::Result<Event>>,
+) {
+    while let Ok(event) = events.recv() {
+        let mut changed = HashSet::new();
+        add_changed_search_paths(config, event, &mut changed);
+        let deadline = Instant::now() + RESCAN_DEBOUNCE;
+        while let Ok(event) =
+            events.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            add_changed_search_paths(config, event, &mut changed);
+        }
+        for dir in changed {
+            // directories created or removed since the last rescan need to be watched or unwatched
+            if let Err(e) = watches.update(dir, config) {
+                eprintln!("{e:#}");
+            }
+            rescan(dir, config, index, symlink_cycles);
+        }
+    }
+}
+
+fn add_changed_search_paths<'a>(
+    config: &'a TwmGlobal,
+    event: notify::Result<Event>,
+    changed: &mut HashSet<&'a str>,
+) {
+    let Ok(event) = event else {
+        return;
+    };
+    // scanning reads directories itself, so rescanning on reads would never stop
+    if matches!(event.kind, EventKind::Access(_)) {
+        return;
+    }
+    for path in &event.paths {
+        for dir in &config.search_paths {
+            if is_relevant_change(path, dir, config) {
+                changed.insert(dir.as_str());
+            }
+        }
+    }
+}
+
+/// Checks whether a change to `path` can change which workspaces are found in the search path `root`.
+fn is_relevant_change(path: &Path, root: &str, config: &TwmGlobal) -> bool {
+    let Ok(relative_path) = path.strip_prefix(root) else {
+        return false;
+    };
+    // workspaces can be found up to the search depth, and whether they match depends on their contents
+    relative_path.components().count() <= config.max_search_depth_for(root) + 1
+        && !path_is_excluded(path, Path::new(root), config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{config_from_yaml, TestDir};
+
+    #[test]
+    fn test_is_relevant_change() {
+        let config = config_from_yaml(
+            "search_paths: [/src, { path: /flat, max_depth: 1 }]\nmax_search_depth: 2\nexclude_path_components: [node_modules]",
+        );
+        let relevant = |path: &str| is_relevant_change(Path::new(path), "/src", &config);
+        assert!(relevant("/src/foo/bar/Cargo.toml"));
+        assert!(!relevant("/src/foo/bar/baz/Cargo.toml"));
+        assert!(!relevant("/src/foo/node_modules"));
+        assert!(!relevant("/elsewhere/foo"));
+        assert!(is_relevant_change(
+            Path::new("/flat/foo/Cargo.toml"),
+            "/flat",
+            &config
+        ));
+        assert!(!is_relevant_change(
+            Path::new("/flat/foo/bar/Cargo.toml"),
+            "/flat",
+            &config
+        ));
+    }
+
+    #[test]
+    fn test_query_index() {
+        let dir = TestDir::new("query-index");
+        let socket_path = dir.join(SOCKET_FILE_NAME);
+        let listener = bind_socket(&socket_path).unwrap();
+        let key = index_key(&config_from_yaml(
+            "search_paths: [/src]\nmax_search_depth: 2",
+        ));
+        let workspace = IndexedWorkspace {
+            path: "/src/foo".into(),
+            workspace_type: Some("rust".into()),
+        };
+        let index = RwLock::new(WorkspaceIndex::from([(
+            "/src".to_owned(),
+            vec![workspace.clone()],
+        )]));
+        let server = std::thread::spawn(move || {
+            for stream in listener.incoming().take(3) {
+                serve_client(stream.unwrap(), &key, &index).unwrap();
+            }
+        });
+
+        let index = query_index_at(
+            &socket_path,
+            &index_key(&config_from_yaml(
+                "search_paths: [/src]\nmax_search_depth: 2",
+            )),
+        );
+        assert_eq!(index.unwrap()["/src"], vec![workspace]);
+        // clients searching fewer search paths can still use the index
+        let scoped = query_index_at(
+            &socket_path,
+            &index_key(&config_from_yaml(
+                "search_paths: [/src, /other]\nmax_search_depth: 2",
+            )),
+        );
+        assert!(scoped.is_ok());
+        let mismatched = query_index_at(
+            &socket_path,
+            &index_key(&config_from_yaml(
+                "search_paths: [/src]\nmax_search_depth: 3",
+            )),
+        );
+        assert!(mismatched.is_err());
+
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use crate::tmux::TmuxCommandFailed;
+use crate::ui::SelectionAborted;
+
+/// Error returned by `cli::run`, telling apart the ways twm can fail so callers can match on them.
+///
+/// Most variants wrap an `anyhow::Error` to keep the context explaining what twm was doing when it failed. The
+/// underlying error can still be reached with `downcast_ref`, e.g. `std::io::Error` for `Io`.
+#[derive(Debug)]
+pub enum Error {
+    /// The config file couldn't be read or isn't valid.
+    Config(anyhow::Error),
+    /// A tmux command couldn't be run or didn't succeed.
+    Tmux(anyhow::Error),
+    /// The user closed a picker without selecting anything.
+    NoSelection(SelectionAborted),
+    /// Reading or writing a file or running a process failed.
+    Io(anyhow::Error),
+    /// Anything else, e.g. no workspace matching a query.
+    Other(anyhow::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Unwraps the error back into the `anyhow::Error` it was made from, keeping its original backtrace.
+    pub fn into_anyhow(self) -> anyhow::Error {
+        match self {
+            Error::Config(e) | Error::Tmux(e) | Error::Io(e) | Error::Other(e) => e,
+            Error::NoSelection(aborted) => aborted.into(),
+        }
+    }
+
+    fn inner(&self) -> Option<&anyhow::Error> {
+        match self {
+            Error::Config(e) | Error::Tmux(e) | Error::Io(e) | Error::Other(e) => Some(e),
+            Error::NoSelection(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self, self.inner()) {
+            (Error::NoSelection(aborted), _) => aborted.fmt(f),
+            (_, Some(e)) => e.fmt(f),
+            (_, None) => unreachable!("Only `NoSelection` has no inner error"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    // the inner error is displayed as this error, so the chain continues with its source
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner().and_then(|e| e.source())
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    /// Sorts the error into a variant by what caused it, looking through any context added on top.
+    fn from(error: anyhow::Error) -> Self {
+        // an `Error` that went through `anyhow` on the way keeps its variant, along with any context added since
+        match error.downcast_ref::<Error>() {
+            Some(Error::Config(_)) => return Error::Config(error),
+            Some(Error::Tmux(_)) => return Error::Tmux(error),
+            Some(Error::Io(_)) => return Error::Io(error),
+            Some(Error::Other(_)) => return Error::Other(error),
+            Some(Error::NoSelection(aborted)) => {
+                return Error::NoSelection(SelectionAborted(aborted.0))
+            }
+            None => {}
+        }
+        let error = match error.downcast::<SelectionAborted>() {
+            Ok(aborted) => return Error::NoSelection(aborted),
+            Err(error) => error,
+        };
+        if error.downcast_ref::<TmuxCommandFailed>().is_some() {
+            Error::Tmux(error)
+        } else if error.downcast_ref::<std::io::Error>().is_some() {
+            Error::Io(error)
+        } else {
+            Error::Other(error)
+        }
+    }
+}
+
+impl From<SelectionAborted> for Error {
+    fn from(aborted: SelectionAborted) -> Self {
+        Error::NoSelection(aborted)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_from_anyhow_classifies_through_context() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "missing");
+        let error = Err::<(), _>(io).context("Failed to read file").unwrap_err();
+        assert!(matches!(Error::from(error), Error::Io(_)));
+
+        let tmux =
+            anyhow::Error::new(TmuxCommandFailed("failed".into())).context("Failed to attach");
+        let error = Error::from(tmux);
+        assert!(matches!(error, Error::Tmux(_)));
+        assert_eq!(error.to_string(), "Failed to attach");
+
+        let aborted = anyhow::Error::new(SelectionAborted("workspace"));
+        assert!(matches!(Error::from(aborted), Error::NoSelection(_)));
+
+        let config = anyhow::Error::new(Error::Config(anyhow::anyhow!("bad config")));
+        assert!(matches!(Error::from(config), Error::Config(_)));
+
+        assert!(matches!(
+            Error::from(anyhow::anyhow!("no match")),
+            Error::Other(_)
+        ));
+    }
+
+    #[test]
+    fn test_from_anyhow_keeps_context_on_wrapped_errors() {
+        let config = anyhow::Error::new(Error::Config(anyhow::anyhow!("bad config")))
+            .context("Failed to load the config for profile `work`");
+        let error = Error::from(config);
+        assert!(matches!(error, Error::Config(_)));
+        assert_eq!(
+            error.to_string(),
+            "Failed to load the config for profile `work`"
+        );
+        assert_eq!(
+            format!("{:#}", error.into_anyhow()),
+            "Failed to load the config for profile `work`: bad config"
+        );
+    }
+}
@@ -1,26 +1,125 @@
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{crate_name, CommandFactory};
 use clap_complete::{generate, Shell};
 
 use crate::{
-    cli::Arguments,
-    config::{RawTwmGlobal, TwmGlobal, TwmLayout},
-    matches::find_workspaces_in_dir,
+    cli::{Arguments, CompletionKind},
+    config::{GroupFallback, RawTwmGlobal, SessionEntry, TwmGlobal, TwmLayout},
+    daemon,
+    layout::get_layout_names,
+    matches::{
+        count_workspaces, find_best_workspace_match, find_unambiguous_workspace_match,
+        find_workspace_items, inject_workspaces, FoundWorkspaces, SymlinkCycles,
+    },
+    state::last_session,
     tmux::{
-        attach_to_tmux_session, get_tmux_sessions, open_workspace, open_workspace_in_group,
-        session_name_for_path_recursive,
+        attach_to_tmux_session, current_tmux_session, current_twm_root, cycle_session_group,
+        get_layout_for_workspace, get_tmux_sessions, kill_tmux_sessions, list_twm_sessions,
+        open_workspace, open_workspace_in_group, open_workspace_into_session, reload_local_layout,
+        session_name_for_path, shell_quote, tmux_has_session_exact, SessionList,
     },
     ui::Tui,
-    workspace::get_workspace_type_for_path,
+    workspace::{get_workspace_definition_for_path, get_workspace_type_for_path},
 };
 
-use crate::ui::{Picker, PickerSelection};
+use crate::ui::{ModifiedSelectionAction, Picker, PickerItem, PickerSelection, SelectionAborted};
 
-fn print_completion(shell: Shell) -> Result<()> {
+/// Bash function completing values that are only known at runtime by calling back into twm.
+/// It's called at the start of the generated completion function, which continues as normal if it returns non-zero.
+const BASH_DYNAMIC_COMPLETION: &str = r#"_twm_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}" kind=""
+    # bash splits `--layout=foo` into `--layout`, `=` and `foo`
+    if [[ "${cur}" == "=" && "${prev}" == "--layout" ]]; then
+        cur="" kind="layouts"
+    elif [[ "${prev}" == "=" && "${COMP_WORDS[COMP_CWORD-2]}" == "--layout" ]]; then
+        kind="layouts"
+    elif [[ "${prev}" == "--attach" ]]; then
+        kind="sessions"
+    elif [[ "${prev}" == "--profile" ]]; then
+        kind="profiles"
+    else
+        return 1
+    fi
+    COMPREPLY=($(compgen -W "$(twm --complete "${kind}" 2>/dev/null)" -- "${cur}"))
+    return 0
+}
+
+"#;
+
+/// Zsh equivalent of `BASH_DYNAMIC_COMPLETION`.
+const ZSH_DYNAMIC_COMPLETION: &str = r#"_twm_dynamic() {
+    local kind
+    if [[ "${PREFIX}" == --layout=* ]]; then
+        compset -P '--layout='
+        kind="layouts"
+    elif [[ "${words[CURRENT-1]}" == "--attach" ]]; then
+        kind="sessions"
+    elif [[ "${words[CURRENT-1]}" == "--profile" ]]; then
+        kind="profiles"
+    else
+        return 1
+    fi
+    compadd -- ${(f)"$(twm --complete "${kind}" 2>/dev/null)"}
+    return 0
+}
+
+"#;
+
+/// Fish merges completions for the same option, so these are just added after the generated ones.
+const FISH_DYNAMIC_COMPLETION: &str = r#"complete -c twm -s l -l layout -f -a "(twm --complete layouts 2>/dev/null)"
+complete -c twm -l attach -f -a "(twm --complete sessions 2>/dev/null)"
+complete -c twm -l profile -f -a "(twm --complete profiles 2>/dev/null)"
+"#;
+
+fn completion_script(shell: Shell) -> Result<String> {
     let mut cmd = Arguments::command();
-    generate(shell, &mut cmd, crate_name!(), &mut std::io::stdout());
+    let mut script = Vec::new();
+    generate(shell, &mut cmd, crate_name!(), &mut script);
+    let script = String::from_utf8(script)?;
+    // the generated scripts define their completion function as `_twm`
+    let completion_fn = "_twm() {\n";
+    let with_dynamic = |dynamic: &str| {
+        if !script.contains(completion_fn) {
+            anyhow::bail!("Failed to add dynamic completions to the generated completion script.");
+        }
+        Ok(script.replacen(
+            completion_fn,
+            &format!("{dynamic}{completion_fn}    _twm_dynamic && return 0\n"),
+            1,
+        ))
+    };
+    match shell {
+        Shell::Bash => with_dynamic(BASH_DYNAMIC_COMPLETION),
+        Shell::Zsh => with_dynamic(ZSH_DYNAMIC_COMPLETION),
+        Shell::Fish => Ok(script + FISH_DYNAMIC_COMPLETION),
+        _ => Ok(script),
+    }
+}
+
+fn print_completion(shell: Shell) -> Result<()> {
+    print!("{}", completion_script(shell)?);
+    Ok(())
+}
+
+pub fn handle_complete(kind: CompletionKind, args: &Arguments) -> Result<()> {
+    let values = match kind {
+        CompletionKind::Layouts => {
+            get_layout_names(&TwmGlobal::load(args.profile.as_deref())?.layouts)
+        }
+        CompletionKind::Sessions => get_tmux_sessions()?,
+        CompletionKind::Profiles => TwmGlobal::load(None)?.profile_names,
+    };
+    for value in values {
+        println!("{value}");
+    }
     Ok(())
 }
 
@@ -53,6 +152,76 @@ pub fn handle_print_man() -> Result<()> {
     Ok(())
 }
 
+pub fn handle_group_cycle(group: &str, args: &Arguments) -> Result<()> {
+    let mut config = TwmGlobal::load(args.profile.as_deref())?;
+    config.detach_others = args.detach_others;
+    config.dry_run = args.dry_run;
+    cycle_session_group(group, &config)
+}
+
+pub fn handle_reload_layout(args: &Arguments) -> Result<()> {
+    let mut config = TwmGlobal::load(args.profile.as_deref())?;
+    config.dry_run = args.dry_run;
+    reload_local_layout(&config)
+}
+
+pub fn handle_print_workspace_type(path: &str, args: &Arguments) -> Result<()> {
+    let config = TwmGlobal::load(args.profile.as_deref())?;
+    let path_full = std::fs::canonicalize(path)?;
+    let Some(workspace_definition) =
+        get_workspace_definition_for_path(&path_full, &config.workspace_definitions)
+    else {
+        bail!("No workspace type matches path `{}`", path_full.display());
+    };
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "path": path_full,
+                "workspace_type": workspace_definition.name,
+                "definition": workspace_definition,
+            }))
+            .context("Failed to serialize the workspace definition")?
+        );
+    } else {
+        println!("{}", workspace_definition.name);
+    }
+    Ok(())
+}
+
+pub fn handle_attach(session_name: &str, args: &Arguments) -> Result<()> {
+    let mut config = TwmGlobal::load(args.profile.as_deref())?;
+    config.detach_others = args.detach_others;
+    config.dry_run = args.dry_run;
+    if !tmux_has_session_exact(session_name) {
+        bail!("No tmux session named `{session_name}` exists");
+    }
+    attach_to_tmux_session(session_name, &config)
+}
+
+pub fn handle_list_sessions(args: &Arguments) -> Result<()> {
+    let config = TwmGlobal::load(args.profile.as_deref())?;
+    let mut sessions = list_twm_sessions(&config)?;
+    if args.twm_only {
+        sessions.retain(|session| session.twm);
+    }
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&sessions).context("Failed to serialize the sessions")?
+        );
+    } else {
+        sessions
+            .iter()
+            .for_each(|session| println!("{}", session.name));
+    }
+    Ok(())
+}
+
+pub fn handle_daemon(args: &Arguments) -> Result<()> {
+    daemon::run(TwmGlobal::load(args.profile.as_deref())?)
+}
+
 pub const DEFAULT_LAYOUT_CONFIG_TEMPLATE: &str = r#"layout:
   name: local-layout
   commands:
@@ -60,6 +229,7 @@ pub const DEFAULT_LAYOUT_CONFIG_TEMPLATE: &str = r#"layout:
     - tmux split-window -h
 "#;
 
+#[allow(clippy::unnecessary_unwrap)]
 pub fn handle_make_default_layout_config(args: &Arguments) -> Result<()> {
     let config_filename = format!(".{}.yaml", crate_name!());
 
@@ -74,10 +244,10 @@ pub fn handle_make_default_layout_config(args: &Arguments) -> Result<()> {
     };
 
     if config_path.exists() {
-        anyhow::bail!(format!(
+        bail!(
             "Configuration file already exists. Please move or rename the file `{}` before trying again.",
             config_path.display()
-        ));
+        );
     }
 
     if let Some(parent) = config_path.parent() {
@@ -89,6 +259,32 @@ pub fn handle_make_default_layout_config(args: &Arguments) -> Result<()> {
     Ok(())
 }
 
+pub fn handle_check_config(args: &Arguments) -> Result<()> {
+    let config_path = match &args.config_file {
+        Some(path) => PathBuf::from(shellexpand::tilde(path).as_ref()),
+        None => TwmGlobal::get_config_path()?
+            .context("No config file found. Create one with `--make-default-config`.")?,
+    };
+    // `TWM_CONFIG_FILE` is taken at face value, so it can point at a file that doesn't exist too
+    if !config_path.is_file() {
+        bail!("Config file {} doesn't exist", config_path.display());
+    }
+    let errors = TwmGlobal::check(&config_path);
+    if errors.is_empty() {
+        println!("Config file {} is valid.", config_path.display());
+        return Ok(());
+    }
+    for error in &errors {
+        eprintln!("error: {error:#}");
+    }
+    Err(anyhow!(
+        "Found {} problem(s) in config file {}",
+        errors.len(),
+        config_path.display()
+    ))
+}
+
+#[allow(clippy::unnecessary_unwrap)]
 pub fn handle_make_default_config(args: &Arguments) -> Result<()> {
     let config_filename = format!("{}.yaml", crate_name!());
     let schema_filename = format!("{}.schema.json", crate_name!());
@@ -99,7 +295,8 @@ pub fn handle_make_default_config(args: &Arguments) -> Result<()> {
         }
         (path.join(&config_filename), path.join(&schema_filename))
     } else {
-        let base_dirs = xdg::BaseDirectories::with_prefix(crate_name!())?;
+        let base_dirs =
+            xdg::BaseDirectories::with_prefix(crate_name!()).context("Failed to load XDG dirs.")?;
         (
             base_dirs.get_config_file(&config_filename),
             base_dirs.get_config_file(&schema_filename),
@@ -107,14 +304,14 @@ pub fn handle_make_default_config(args: &Arguments) -> Result<()> {
     };
 
     if config_path.exists() || schema_path.exists() {
-        anyhow::bail!(format!(
+        bail!(
             "Configuration files already exist. Please move or rename any existing files:
 - {}
 - {}
 before running this command again.",
             config_path.display(),
             schema_path.display()
-        ));
+        );
     }
 
     // make sure parent directories exist
@@ -131,83 +328,515 @@ before running this command again.",
 {}
         ",
             schema_filename,
-            &serde_yaml::to_string(&RawTwmGlobal::default())?
+            &serde_yaml::to_string(&RawTwmGlobal::default())
+                .context("Failed to serialize the default config")?
         ),
     )?;
     Ok(())
 }
 
-pub fn handle_existing_session_selection(tui: &mut Tui) -> Result<()> {
-    let existing_sessions = get_tmux_sessions()?;
-    let session_name = match Picker::new(
-        &existing_sessions,
+pub fn handle_existing_session_selection(config: &TwmGlobal, tui: &mut Tui) -> Result<()> {
+    let sessions = SessionList::load(config)?;
+    if sessions.is_empty() {
+        println!("No existing sessions");
+        return Ok(());
+    }
+    let selection = match Picker::with_items(
+        sessions.into_items(),
         "Select an existing session to attach to: ".into(),
+        config,
     )
+    .with_description("Existing session mode: Enter attaches to the highlighted session.")
     .get_selection(tui)?
     {
-        PickerSelection::None => anyhow::bail!("No session selected"),
+        PickerSelection::None => return Err(SelectionAborted("session").into()),
         PickerSelection::Selection(s) => s,
         PickerSelection::ModifiedSelection(s) => s,
     };
-    attach_to_tmux_session(&session_name)?;
+    if config.dry_run {
+        tui.suspend()?;
+    }
+    attach_to_tmux_session(&selection, config)?;
     Ok(())
 }
 
-pub fn handle_group_session_selection(args: &Arguments, tui: &mut Tui) -> Result<()> {
-    let existing_sessions = get_tmux_sessions()?;
-    let group_session_name = match Picker::new(
-        &existing_sessions,
+pub fn handle_kill_session_selection(
+    config: &TwmGlobal,
+    args: &Arguments,
+    tui: &mut Tui,
+) -> Result<()> {
+    let sessions = SessionList::load(config)?;
+    if sessions.is_empty() {
+        println!("No existing sessions");
+        return Ok(());
+    }
+    let mut picker = Picker::with_items(
+        sessions.into_items(),
+        "Select sessions to kill: ".into(),
+        config,
+    )
+    .with_description(
+        "Kill mode: Enter kills the highlighted session. Mark sessions with Tab to kill all of them at once.",
+    )
+    .with_multi_select();
+    let selection = match picker.get_selection(tui)? {
+        PickerSelection::None => return Err(SelectionAborted("session").into()),
+        PickerSelection::Selection(s) => s,
+        PickerSelection::ModifiedSelection(s) => s,
+    };
+    let mut session_names = picker.take_marked();
+    if session_names.is_empty() {
+        session_names.push(selection);
+    }
+    tui.suspend()?;
+    kill_tmux_sessions(&session_names, args.force, config)
+}
+
+pub fn handle_group_session_selection(
+    config: &TwmGlobal,
+    args: &Arguments,
+    tui: &mut Tui,
+) -> Result<()> {
+    let sessions = SessionList::load(config)?;
+    if sessions.is_empty() {
+        println!("No existing sessions");
+        return Ok(());
+    }
+    let selection = match Picker::with_items(
+        sessions.into_items(),
         "Select a session to group with: ".into(),
+        config,
     )
-    .get_selection(tui)?
-    {
-        PickerSelection::None => anyhow::bail!("No session selected"),
+    .with_description(
+        "Group mode: Enter starts a new session sharing the highlighted session's windows. With \
+        `keep_open_on_modified_enter`, modified Enter does so without attaching and keeps the picker open.",
+    )
+    .get_selection_with_action(tui, |selection| {
+        // with `--dry-run` the commands are printed, which can't be done with the picker still open
+        if !config.keep_open_on_modified_enter || config.dry_run {
+            return Ok(ModifiedSelectionAction::Exit);
+        }
+        // create the session in the background so more can be created without relaunching the picker
+        let background_args = Arguments {
+            dont_attach: true,
+            ..Default::default()
+        };
+        open_workspace_in_group(selection, config, &background_args)?;
+        Ok(ModifiedSelectionAction::StayOpen(
+            SessionList::load(config)?.into_items(),
+        ))
+    })? {
+        PickerSelection::None => return Err(SelectionAborted("session").into()),
         PickerSelection::Selection(s) => s,
         PickerSelection::ModifiedSelection(s) => s,
     };
-    open_workspace_in_group(&group_session_name, args)?;
+    if config.dry_run {
+        tui.suspend()?;
+    }
+    open_workspace_in_group(&selection, config, args)?;
     Ok(())
 }
 
-pub fn handle_workspace_selection(args: &Arguments, tui: &mut Tui) -> Result<()> {
-    let config = TwmGlobal::load()?;
+/// The workspace of the twm session twm is being run in, if `exclude_current_workspace` leaves it out of the picker.
+fn excluded_workspace(config: &TwmGlobal) -> Option<String> {
+    config
+        .exclude_current_workspace
+        .then(|| current_twm_root(config))
+        .flatten()
+}
+
+/// The outcome of matching a query against the workspaces without showing the picker.
+pub enum WorkspaceQuery {
+    /// The workspace clearly matching the query best.
+    Match(String),
+    /// No workspace clearly matches the query, so the picker is shown with the workspaces that were found.
+    Ambiguous(FoundWorkspaces),
+}
+
+/// Finds the workspace that best matches `query` without showing the picker, if there is a clear best match.
+pub fn handle_workspace_query(config: &TwmGlobal, query: &str) -> Result<WorkspaceQuery> {
+    let workspaces = find_workspace_items(config, excluded_workspace(config).as_deref());
+    Ok(match find_unambiguous_workspace_match(query, &workspaces) {
+        Some(path) => WorkspaceQuery::Match(path),
+        None => WorkspaceQuery::Ambiguous(workspaces),
+    })
+}
+
+pub fn handle_first_workspace(config: &TwmGlobal, query: Option<&str>) -> Result<String> {
+    match find_best_workspace_match(query, config, excluded_workspace(config).as_deref()) {
+        Some(path) => Ok(path),
+        None => match query {
+            Some(query) => Err(anyhow!("No workspace matches `{query}`")),
+            None => Err(anyhow!("No workspaces found")),
+        },
+    }
+}
+
+pub fn handle_count(config: &TwmGlobal, query: Option<&str>) -> Result<()> {
+    println!("{}", count_workspaces(query, config));
+    Ok(())
+}
+
+/// Attaches to the session twm attached to most recently, returning whether there still was such a session.
+pub fn handle_last_session(config: &TwmGlobal) -> Result<bool> {
+    match last_session(current_tmux_session().as_deref()) {
+        Some(session_name) if tmux_has_session_exact(&session_name) => {
+            attach_to_tmux_session(&session_name, config)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Creates a scratch directory and points `args` at it, naming the session after the time it was created.
+pub fn handle_scratch(config: &TwmGlobal, args: &mut Arguments) -> Result<()> {
+    let base_dir = match &config.scratch_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::temp_dir(),
+    };
+    if !config.dry_run {
+        std::fs::create_dir_all(&base_dir).with_context(|| {
+            format!("Failed to create scratch directory {}", base_dir.display())
+        })?;
+    }
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("System time is before the Unix epoch")?
+        .as_secs();
+    let mut name = format!("scratch-{timestamp}");
+    let mut suffix = 1;
+    // two scratch workspaces opened in the same second would otherwise end up in the same directory
+    while let Err(e) = create_scratch_dir(&base_dir.join(&name), config.dry_run) {
+        if e.kind() != std::io::ErrorKind::AlreadyExists {
+            let context = format!(
+                "Failed to create scratch directory in {}",
+                base_dir.display()
+            );
+            return Err(anyhow::Error::new(e).context(context));
+        }
+        suffix += 1;
+        name = format!("scratch-{timestamp}-{suffix}");
+    }
+    args.path = Some(base_dir.join(&name).to_string_lossy().into_owned());
+    args.name.get_or_insert(name);
+    if args.layout.is_none() {
+        args.layout = config.scratch_layout.clone().map(Some);
+    }
+    Ok(())
+}
+
+/// Creates the scratch directory `path`, or only prints the command doing so with `--dry-run`.
+fn create_scratch_dir(path: &Path, dry_run: bool) -> std::io::Result<()> {
+    if !dry_run {
+        return std::fs::create_dir(path);
+    }
+    if path.exists() {
+        return Err(std::io::ErrorKind::AlreadyExists.into());
+    }
+    println!("mkdir -p {}", shell_quote(&path.to_string_lossy()));
+    Ok(())
+}
+
+/// Clones the git repository at `url` into `clone_root` and points `args` at it. A directory that's already there is
+/// used as is, without cloning anything.
+pub fn handle_clone(config: &TwmGlobal, args: &mut Arguments, url: &str) -> Result<()> {
+    let Some(clone_root) = config
+        .clone_root
+        .as_ref()
+        .or_else(|| config.search_paths.first())
+    else {
+        bail!("No `clone_root` or search path to clone {url} into");
+    };
+    let Some(dir_name) = repository_dir_name(url) else {
+        bail!("Failed to get a directory name from repository URL `{url}`");
+    };
+    let clone_path = Path::new(clone_root).join(dir_name);
+    if !clone_path.exists() && config.dry_run {
+        println!(
+            "git clone -- {} {}",
+            shell_quote(url),
+            shell_quote(&clone_path.to_string_lossy())
+        );
+    } else if !clone_path.exists() {
+        std::fs::create_dir_all(clone_root)
+            .with_context(|| format!("Failed to create clone directory {clone_root}"))?;
+        // git's progress goes to the terminal, nothing else is drawn yet
+        // `--` keeps a URL starting with `-` from being read as an option
+        let status = Command::new("git")
+            .arg("clone")
+            .arg("--")
+            .arg(url)
+            .arg(&clone_path)
+            .status()
+            .context("Failed to run `git clone`")?;
+        if !status.success() {
+            bail!("`git clone {url}` exited with {status}");
+        }
+    }
+    args.path = Some(clone_path.to_string_lossy().into_owned());
+    Ok(())
+}
+
+/// Gets the name of the directory `git clone` would clone the repository at `url` into, e.g. `twm` for
+/// `git@github.com:vinnymeller/twm.git`.
+fn repository_dir_name(url: &str) -> Option<&str> {
+    let url = url.trim_end_matches('/');
+    let url = url.strip_suffix(".git").unwrap_or(url);
+    let name = url.rsplit(['/', ':']).next()?;
+    (!matches!(name, "" | "." | "..")).then_some(name)
+}
+
+/// Opens the workspace at `-p/--path`, or the one selected in the picker otherwise.
+///
+/// The picker lists `found_workspaces` if they were already found, and searches for workspaces itself otherwise.
+pub fn handle_workspace_selection(
+    config: &TwmGlobal,
+    args: &Arguments,
+    found_workspaces: Option<FoundWorkspaces>,
+    tui: &mut Tui,
+) -> Result<()> {
     let (workspace_path, try_grouping) = if let Some(path) = &args.path {
-        let path_full = std::fs::canonicalize(path)?;
+        let path = config.expand_project_root(path);
+        let path_full = match std::fs::canonicalize(&path) {
+            Ok(path_full) => path_full,
+            // the scratch or clone directory isn't created with `--dry-run`
+            Err(e) if config.dry_run && e.kind() == std::io::ErrorKind::NotFound => {
+                PathBuf::from(path)
+            }
+            Err(e) => return Err(e.into()),
+        };
         match path_full.to_str() {
             Some(p) => (p.to_owned(), false),
-            None => anyhow::bail!("Path is not valid UTF-8"),
+            None => return Err(anyhow!("Path is not valid UTF-8")),
         }
     } else {
-        let mut picker = Picker::new(&[], "Select a workspace: ".into());
+        if config.using_defaults {
+            // printed before the picker takes over the screen, so it's still there once twm exits
+            eprintln!(
+                "No config file found, searching {} {} levels deep. Run `twm --make-default-config` to create one.",
+                config.search_paths.join(", "),
+                config.max_search_depth
+            );
+        }
+        let mut picker = Picker::new(&[], "Select a workspace: ".into(), config).with_description(
+            "Workspace mode: Enter opens the highlighted workspace in its own session, or attaches to its \
+            session if it's already open. Modified Enter opens it in a new session grouped with its existing one.",
+        )
+        .with_preview(&config.preview_command, config.show_preview)
+        .with_sort_by(config.sort_by);
+        if let Some(query) = &args.query {
+            picker.set_filter(query);
+        }
         let injector = picker.injector.clone();
-        let config = config.clone();
-        std::thread::spawn(move || {
-            for dir in &config.search_paths {
-                find_workspaces_in_dir(dir, &config, injector.clone())
+        let discovery_done = Arc::new(AtomicBool::new(false));
+        if config.auto_select_single {
+            picker.auto_select_single(discovery_done.clone());
+        }
+        let truncated = Arc::new(AtomicBool::new(false));
+        picker.show_truncation(truncated.clone());
+        let symlink_cycles = Arc::new(SymlinkCycles::default());
+        if let Some(found_workspaces) = found_workspaces {
+            for item in found_workspaces.items {
+                item.inject(&injector);
             }
-        });
-        match picker.get_selection(tui)? {
-            PickerSelection::None => anyhow::bail!("No workspace selected"),
+            truncated.store(found_workspaces.truncated, Ordering::Release);
+            discovery_done.store(true, Ordering::Release);
+        } else {
+            let search_symlink_cycles = symlink_cycles.clone();
+            let config = config.clone();
+            let excluded_workspace = excluded_workspace(&config);
+            std::thread::spawn(move || {
+                if inject_workspaces(
+                    &config,
+                    injector,
+                    excluded_workspace.as_deref(),
+                    &search_symlink_cycles,
+                ) {
+                    truncated.store(true, Ordering::Release);
+                }
+                discovery_done.store(true, Ordering::Release);
+            });
+        }
+        let selection = picker.get_selection(tui)?;
+        if symlink_cycles.has_unreported() {
+            tui.suspend()?;
+            symlink_cycles.report();
+        }
+        match selection {
+            PickerSelection::None => return Err(SelectionAborted("workspace").into()),
             PickerSelection::Selection(s) => (s, false),
             PickerSelection::ModifiedSelection(s) => (s, true),
         }
     };
 
+    if args.print_path {
+        tui.suspend()?;
+        println!("{workspace_path}");
+        return Ok(());
+    }
+
+    let workspace_type =
+        || get_workspace_type_for_path(Path::new(&workspace_path), &config.workspace_definitions);
+
+    if args.show_layout {
+        let layout =
+            get_layout_for_workspace(&workspace_path, workspace_type(), config, args, tui)?;
+        tui.suspend()?;
+        match layout {
+            Some(layout) => {
+                layout
+                    .commands
+                    .iter()
+                    .for_each(|command| println!("{command}"));
+                if let Some(pane) = layout.zoom_pane {
+                    println!("[zoom pane {pane}]");
+                }
+            }
+            None => eprintln!("No layout applies to {workspace_path}"),
+        }
+        return Ok(());
+    }
+
+    if let Some(session_name) = &args.into {
+        open_workspace_into_session(
+            session_name,
+            &workspace_path,
+            workspace_type(),
+            config,
+            args,
+            tui,
+        )?;
+        return Ok(());
+    }
+
     if try_grouping {
         // see if we already have a twm-generated session for the workspace path we're trying to open
         if let Ok(Some(group_session_name)) =
-            session_name_for_path_recursive(&workspace_path, config.session_name_path_components)
+            session_name_for_path(&workspace_path, workspace_type(), config)
         {
-            open_workspace_in_group(group_session_name.as_str(), args)?;
+            open_workspace_in_group(group_session_name.as_str(), config, args)?;
             return Ok(());
         }
+        match config.group_fallback {
+            GroupFallback::Open => {
+                tui.suspend()?;
+                eprintln!(
+                    "No twm session to group with for {workspace_path}, opening a new session"
+                )
+            }
+            GroupFallback::Pick => return handle_group_session_selection(config, args, tui),
+            GroupFallback::Error => {
+                return Err(anyhow!("No twm session to group with for {workspace_path}"))
+            }
+        }
     }
 
     // if we couldn't find a correct session to group with, open the workspace normally
-
-    let workspace_type =
-        get_workspace_type_for_path(Path::new(&workspace_path), &config.workspace_definitions);
-    open_workspace(&workspace_path, workspace_type, &config, args, tui)?;
+    open_workspace(&workspace_path, workspace_type(), config, args, tui)?;
 
     Ok(())
 }
+
+/// Opens sessions listed in a sessions file, either every one of them with `--all` or one picked by the user.
+pub fn handle_sessions_file(
+    path: &str,
+    config: &TwmGlobal,
+    args: &Arguments,
+    tui: &mut Tui,
+) -> Result<()> {
+    let sessions = SessionEntry::load_all(Path::new(&shellexpand::tilde(path).into_owned()))?;
+    let session_args = |session: &SessionEntry, dont_attach: bool| Arguments {
+        path: Some(session.path.clone()),
+        name: session.name.clone().or_else(|| args.name.clone()),
+        layout: match &session.layout {
+            Some(layout) => Some(Some(layout.clone())),
+            None => args.layout.clone(),
+        },
+        dont_attach,
+        ..args.clone()
+    };
+    if args.all {
+        let Some((first, rest)) = sessions.split_first() else {
+            println!("No sessions in {path}");
+            return Ok(());
+        };
+        for session in rest {
+            handle_workspace_selection(config, &session_args(session, true), None, tui)?;
+        }
+        // the first session is opened last so twm attaches to it
+        return handle_workspace_selection(
+            config,
+            &session_args(first, args.dont_attach),
+            None,
+            tui,
+        );
+    }
+    let items = sessions
+        .iter()
+        .enumerate()
+        .map(|(index, session)| {
+            let text = match &session.name {
+                Some(name) => format!("{} [{name}]", session.path),
+                None => session.path.clone(),
+            };
+            PickerItem::with_value(text, None, index)
+        })
+        .collect();
+    let index = match Picker::with_items(items, "Select a session to open: ".into(), config)
+        .with_description("Sessions file mode: Enter opens the highlighted session from the file.")
+        .get_selection(tui)?
+    {
+        PickerSelection::None => return Err(SelectionAborted("session").into()),
+        PickerSelection::Selection(index) => index,
+        PickerSelection::ModifiedSelection(index) => index,
+    };
+    handle_workspace_selection(
+        config,
+        &session_args(&sessions[index], args.dont_attach),
+        None,
+        tui,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repository_dir_name() {
+        assert_eq!(
+            repository_dir_name("https://github.com/vinnymeller/twm"),
+            Some("twm")
+        );
+        assert_eq!(
+            repository_dir_name("git@github.com:vinnymeller/twm.git"),
+            Some("twm")
+        );
+        assert_eq!(repository_dir_name("host:twm.git/"), Some("twm"));
+        assert_eq!(repository_dir_name("../twm/"), Some("twm"));
+        assert_eq!(repository_dir_name("host:"), None);
+        assert_eq!(repository_dir_name(".."), None);
+    }
+
+    #[test]
+    fn test_check_config_requires_the_file() {
+        let args = Arguments {
+            check_config: true,
+            config_file: Some("/nonexistent/twm.yaml".into()),
+            ..Default::default()
+        };
+        let error = handle_check_config(&args).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Config file /nonexistent/twm.yaml doesn't exist"
+        );
+    }
+
+    #[test]
+    fn test_dynamic_completions_are_added() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = completion_script(shell).unwrap();
+            assert!(script.contains("twm --complete"));
+        }
+    }
+}
@@ -1,5 +1,8 @@
+use anyhow::{Context, Result};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
 
 #[derive(Serialize, Deserialize, Debug, Clone, JsonSchema, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
@@ -7,6 +10,9 @@ pub struct LayoutDefinition {
     /// Name of the layout.
     ///
     /// This is the name that should be referenced in workspace definitions' `default_layout` field.
+    ///
+    /// The window the layout is applied to is named after it, unless the workspace definition sets `window_name` or
+    /// the layout's first window has a `name`.
     pub name: String,
 
     /// List of layout names that this layout should inherit commands from.
@@ -25,14 +31,320 @@ pub struct LayoutDefinition {
     /// to run the workspace-specific commands for each respective workspace type.
     pub inherits: Option<Vec<String>>,
 
+    /// List of windows to build when a session using this layout is initialized, each with its own panes.
+    ///
+    /// If unset, no windows are built and the layout only runs its `commands`.
+    ///
+    /// Windows are built after the commands from inherited layouts and before this layout's own `commands`, which
+    /// go to the last window built. The first window of the whole layout is the window the layout is applied to,
+    /// which is renamed and split rather than opening a new window. Every later window is opened after it.
+    pub windows: Option<Vec<LayoutWindow>>,
+
     /// List of commands to run when a session using this layout is initialized.
     ///
     /// If unset, no commands are run when the session is initialized.
     ///
-    /// Commands defined here are run after commands from inherited layouts.
+    /// Commands defined here are run after commands from inherited layouts and the windows of this layout.
     ///
     /// These commands are passed to the  shell as-is via tmux's `send-keys` command.
-    pub commands: Option<Vec<String>>,
+    ///
+    /// Each command is either a string, which is typed into the active pane, or a `{ pane, command }` map, which is
+    /// typed into the pane with index `pane` in the session's current window. Pane indices are the ones shown by
+    /// `tmux display-panes` and start at tmux's `pane-base-index`, 0 by default. The pane must already exist when the
+    /// command is sent, e.g. created by an earlier `tmux split-window` command.
+    pub commands: Option<Vec<LayoutCommand>>,
+
+    /// Path to a file containing the commands to run when a session using this layout is initialized, one per line.
+    ///
+    /// If unset, commands are taken from the `commands` field. This can't be set along with `commands`.
+    ///
+    /// Relative paths are resolved from the directory containing the configuration file the layout is defined in.
+    /// Blank lines in the file are ignored.
+    pub commands_file: Option<String>,
+
+    /// Whether the layout is offered when selecting a layout with `-l/--layout`.
+    ///
+    /// If unset, defaults to true.
+    ///
+    /// Setting this to false is useful for base layouts that are only meant to be inherited from. Layouts that
+    /// aren't selectable can still be used in `inherits` and `default_layout`.
+    #[serde(default = "default_selectable")]
+    pub selectable: bool,
+
+    /// Index of the pane in the session's current window, or the last window built by `windows`, to zoom once the
+    /// layout's commands have been sent.
+    ///
+    /// If unset, the zoom pane of the last inherited layout that sets one is used, if any.
+    ///
+    /// twm waits briefly for the pane to be created by the layout's commands and leaves the window as it is if it
+    /// never shows up. Panes created after the zoomed pane exists unzoom it again, so set `layout_command_timeout`
+    /// if later commands create more panes.
+    pub zoom_pane: Option<usize>,
+}
+
+fn default_selectable() -> bool {
+    true
+}
+
+/// A window built by a layout, with the panes it's split into.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct LayoutWindow {
+    /// Name of the window.
+    ///
+    /// If unset, the first window of the layout keeps its name, and tmux names later windows after the program
+    /// running in their active pane.
+    pub name: Option<String>,
+
+    /// tmux layout to arrange the window's panes with once they're all created, e.g. `main-vertical` or `tiled`.
+    ///
+    /// If unset, each pane is split off the pane created before it.
+    pub layout: Option<String>,
+
+    /// Panes of the window, in the order they're created.
+    ///
+    /// If unset, the window only has the pane it's opened with.
+    #[serde(default)]
+    pub panes: Vec<LayoutPane>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct LayoutPane {
+    /// Commands typed into the pane once it's created, in order.
+    ///
+    /// If unset, the pane is left with just a shell.
+    #[serde(default)]
+    pub commands: Vec<String>,
+}
+
+impl LayoutWindow {
+    /// Expands the window into the commands building it: opening the window, splitting off each pane after the
+    /// first and typing the pane's commands into it, then arranging the panes.
+    fn to_commands(&self) -> Vec<LayoutCommand> {
+        let mut commands = vec![LayoutCommand::Window(WindowCommand::Open(
+            self.name.clone(),
+        ))];
+        for (index, pane) in self.panes.iter().enumerate() {
+            if index > 0 {
+                commands.push(LayoutCommand::Window(WindowCommand::SplitPane));
+            }
+            // a new pane is the active one, so its commands can simply go to the active pane
+            commands.extend(
+                pane.commands
+                    .iter()
+                    .map(|c| LayoutCommand::from(c.as_str())),
+            );
+        }
+        if let Some(layout) = &self.layout {
+            commands.push(LayoutCommand::Window(WindowCommand::SelectLayout(
+                layout.clone(),
+            )));
+        }
+        commands
+    }
+}
+
+/// A command from a layout's `commands`, along with the pane to type it into.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum LayoutCommand {
+    /// A command typed into the active pane.
+    Active(String),
+    /// A command typed into a specific pane.
+    Pane(PaneCommand),
+    /// A tmux command building one of a layout's `windows`, run by twm rather than typed into a pane.
+    #[serde(skip)]
+    Window(WindowCommand),
+}
+
+/// A step in building a window from a layout's `windows`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WindowCommand {
+    /// Opens a window with the given name, which the commands after it go to. The first window of a layout is the
+    /// window the layout is applied to instead.
+    Open(Option<String>),
+    /// Splits the window's active pane, making the new pane the active one.
+    SplitPane,
+    /// Arranges the window's panes with the given tmux layout.
+    SelectLayout(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PaneCommand {
+    /// Index of the pane in the session's current window to type the command into.
+    pub pane: usize,
+
+    /// The command to type into the pane.
+    pub command: String,
+}
+
+impl LayoutCommand {
+    /// Gets the command typed into a pane, or `None` for commands building a window.
+    pub fn command(&self) -> Option<&str> {
+        match self {
+            LayoutCommand::Active(command) => Some(command),
+            LayoutCommand::Pane(pane_command) => Some(&pane_command.command),
+            LayoutCommand::Window(_) => None,
+        }
+    }
+
+    /// Gets the index of the pane the command is typed into, or `None` for the active pane.
+    pub fn pane(&self) -> Option<usize> {
+        match self {
+            LayoutCommand::Active(_) | LayoutCommand::Window(_) => None,
+            LayoutCommand::Pane(pane_command) => Some(pane_command.pane),
+        }
+    }
+}
+
+impl From<&str> for LayoutCommand {
+    fn from(command: &str) -> Self {
+        LayoutCommand::Active(command.to_owned())
+    }
+}
+
+impl std::fmt::Display for LayoutCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayoutCommand::Active(command) => write!(f, "{command}"),
+            LayoutCommand::Pane(PaneCommand { pane, command }) => {
+                write!(f, "[pane {pane}] {command}")
+            }
+            LayoutCommand::Window(WindowCommand::Open(Some(name))) => write!(f, "[window {name}]"),
+            LayoutCommand::Window(WindowCommand::Open(None)) => write!(f, "[window]"),
+            LayoutCommand::Window(WindowCommand::SplitPane) => write!(f, "[split pane]"),
+            LayoutCommand::Window(WindowCommand::SelectLayout(layout)) => {
+                write!(f, "[select layout {layout}]")
+            }
+        }
+    }
+}
+
+impl LayoutDefinition {
+    /// Reads the layout's `commands_file`, if set, into its `commands`.
+    ///
+    /// `base_dir` is the directory relative `commands_file` paths are resolved from.
+    pub fn load_commands_file(&mut self, base_dir: &Path) -> Result<()> {
+        let Some(commands_file) = self.commands_file.take() else {
+            return Ok(());
+        };
+        if self.commands.is_some() {
+            anyhow::bail!(
+                "Layout `{}` can't set both `commands` and `commands_file`.",
+                self.name
+            );
+        }
+        let path = base_dir.join(shellexpand::tilde(&commands_file).as_ref());
+        let contents = std::fs::read_to_string(&path).with_context(|| {
+            format!(
+                "Failed to read commands file {path:#?} for layout `{}`",
+                self.name
+            )
+        })?;
+        self.commands = Some(
+            contents
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(LayoutCommand::from)
+                .collect(),
+        );
+        Ok(())
+    }
+}
+
+/// A layout ready to be applied to a session, with the commands it inherits included in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolvedLayout {
+    /// Name of the layout, or the names of the layouts combined into it separated by commas.
+    pub name: String,
+    pub commands: Vec<LayoutCommand>,
+    pub zoom_pane: Option<usize>,
+}
+
+impl ResolvedLayout {
+    pub fn from_layout(layout: &LayoutDefinition, layouts: &[LayoutDefinition]) -> Self {
+        Self {
+            name: layout.name.clone(),
+            commands: get_commands_from_layout(layout, layouts),
+            zoom_pane: get_zoom_pane_from_layout(layout, layouts),
+        }
+    }
+
+    /// Resolves the layout named `layout_name`, which has no commands if it isn't defined.
+    pub fn from_layout_name(layout_name: &str, layouts: &[LayoutDefinition]) -> Self {
+        match get_layout_by_name(layout_name, layouts) {
+            Some(layout) => Self::from_layout(layout, layouts),
+            None => Self::default(),
+        }
+    }
+
+    /// Resolves several layouts as one, running their commands one after the other in the given order.
+    ///
+    /// Commands are kept as they are, so a command in more than one of the layouts runs once for each. The pane
+    /// zoomed is the one from the last layout that zooms one.
+    pub fn from_layout_names(layout_names: &[String], layouts: &[LayoutDefinition]) -> Self {
+        let combined = Self {
+            name: layout_names.join(","),
+            ..Self::default()
+        };
+        layout_names
+            .iter()
+            .map(|name| Self::from_layout_name(name, layouts))
+            .fold(combined, |mut combined, layout| {
+                combined.commands.extend(layout.commands);
+                combined.zoom_pane = layout.zoom_pane.or(combined.zoom_pane);
+                combined
+            })
+    }
+}
+
+/// Gets the pane to zoom after applying `layout`, falling back to the last inherited layout that sets one.
+pub fn get_zoom_pane_from_layout(
+    layout: &LayoutDefinition,
+    layouts: &[LayoutDefinition],
+) -> Option<usize> {
+    fn find_zoom_pane<'a>(
+        layout: &'a LayoutDefinition,
+        layouts: &'a [LayoutDefinition],
+        chain: &mut Vec<&'a LayoutDefinition>,
+    ) -> Option<usize> {
+        if layout.zoom_pane.is_some() {
+            return layout.zoom_pane;
+        }
+        chain.push(layout);
+        let zoom_pane = inherited_layouts(layout, layouts, chain)
+            .into_iter()
+            .rev()
+            .find_map(|inherited| find_zoom_pane(inherited, layouts, chain));
+        chain.pop();
+        zoom_pane
+    }
+    find_zoom_pane(layout, layouts, &mut Vec::new())
+}
+
+/// Gets the defined layouts `layout` inherits from, in order, leaving out the layouts in `chain` that it's being
+/// inherited through. Inheriting from one of those again would go around an inheritance cycle forever.
+///
+/// Layouts in `chain` are compared by identity rather than by name, since a layout from a `.twm.yaml` file can inherit
+/// from the global layout with the same name.
+fn inherited_layouts<'a>(
+    layout: &LayoutDefinition,
+    layouts: &'a [LayoutDefinition],
+    chain: &[&LayoutDefinition],
+) -> Vec<&'a LayoutDefinition> {
+    layout
+        .inherits
+        .iter()
+        .flatten()
+        .filter_map(|name| get_layout_by_name(name, layouts))
+        .filter(|inherited| {
+            !chain
+                .iter()
+                .any(|visited| std::ptr::eq(*visited, *inherited))
+        })
+        .collect()
 }
 
 pub fn get_layout_by_name<'a>(
@@ -42,32 +354,137 @@ pub fn get_layout_by_name<'a>(
     layouts.iter().find(|l| l.name == name)
 }
 
-pub fn get_commands_from_layout<'a: 'c, 'b: 'c, 'c>(
-    layout: &'a LayoutDefinition,
-    layouts: &'b [LayoutDefinition],
-) -> Vec<&'c str> {
-    let mut commands = Vec::<&str>::new();
-    if let Some(inherits_list) = &layout.inherits {
-        for inherits_from_name in inherits_list {
-            commands.extend(get_commands_from_layout_name(inherits_from_name, layouts));
+/// Gets the commands of `layout` with the commands it inherits included in order and its windows expanded into the
+/// commands building them.
+///
+/// Inheritance cycles are rejected when the config is loaded, but a layout inheriting from a layout it's already being
+/// inherited through is skipped here anyway rather than recursing forever.
+pub fn get_commands_from_layout(
+    layout: &LayoutDefinition,
+    layouts: &[LayoutDefinition],
+) -> Vec<LayoutCommand> {
+    fn collect_commands<'a>(
+        layout: &'a LayoutDefinition,
+        layouts: &'a [LayoutDefinition],
+        chain: &mut Vec<&'a LayoutDefinition>,
+        commands: &mut Vec<LayoutCommand>,
+    ) {
+        chain.push(layout);
+        for inherited in inherited_layouts(layout, layouts, chain) {
+            collect_commands(inherited, layouts, chain, commands);
+        }
+        chain.pop();
+        for window in layout.windows.iter().flatten() {
+            commands.extend(window.to_commands());
+        }
+        if let Some(layout_commands) = &layout.commands {
+            commands.extend(layout_commands.iter().cloned());
         }
     }
-    if let Some(layout_commands) = &layout.commands {
-        commands.extend(layout_commands.iter().map(String::as_str));
-    }
+    let mut commands = Vec::new();
+    collect_commands(layout, layouts, &mut Vec::new(), &mut commands);
     commands
 }
 
-pub fn get_commands_from_layout_name<'a: 'c, 'b: 'c, 'c>(
-    layout_name: &'a str,
-    layouts: &'b [LayoutDefinition],
-) -> Vec<&'c str> {
+pub fn get_commands_from_layout_name(
+    layout_name: &str,
+    layouts: &[LayoutDefinition],
+) -> Vec<LayoutCommand> {
     match get_layout_by_name(layout_name, layouts) {
         Some(layout) => get_commands_from_layout(layout, layouts),
         None => Vec::new(),
     }
 }
 
+/// Checks that layout names are unique. Only the first of several layouts with the same name is ever used.
+pub fn check_layout_names(layouts: &[LayoutDefinition]) -> Vec<anyhow::Error> {
+    let mut names = HashSet::new();
+    layouts
+        .iter()
+        .filter(|layout| !names.insert(layout.name.as_str()))
+        .map(|layout| {
+            anyhow::anyhow!(
+                "Layout `{}` is defined more than once, only the first definition is used.",
+                layout.name
+            )
+        })
+        .collect()
+}
+
+/// Checks that every inherited layout is defined, without any inheritance cycles. The commands of a layout that isn't
+/// defined would otherwise just be left out.
+///
+/// Returns every problem found rather than stopping at the first one.
+pub fn check_layout_inheritance(layouts: &[LayoutDefinition]) -> Vec<anyhow::Error> {
+    let mut errors = Vec::new();
+    for layout in layouts {
+        for inherits_from_name in layout.inherits.iter().flatten() {
+            if get_layout_by_name(inherits_from_name, layouts).is_none() {
+                errors.push(anyhow::anyhow!(
+                    "Layout `{}` inherits from layout `{inherits_from_name}`, which isn't defined.",
+                    layout.name
+                ));
+            }
+        }
+        if let Some(cycle) = find_inheritance_cycle(layout, layouts) {
+            // every layout in the cycle finds it, so only report it once
+            if cycle.iter().min() == Some(&layout.name.as_str()) {
+                errors.push(anyhow::anyhow!(
+                    "Layouts inherit from each other in a cycle: {} -> {}",
+                    cycle.join(" -> "),
+                    layout.name
+                ));
+            }
+        }
+    }
+    errors
+}
+
+/// Finds a chain of inherited layouts leading from `layout` back to itself.
+fn find_inheritance_cycle<'a>(
+    layout: &'a LayoutDefinition,
+    layouts: &'a [LayoutDefinition],
+) -> Option<Vec<&'a str>> {
+    fn visit<'a>(
+        current: &'a LayoutDefinition,
+        target: &str,
+        layouts: &'a [LayoutDefinition],
+        visited: &mut HashSet<&'a str>,
+        chain: &mut Vec<&'a str>,
+    ) -> bool {
+        chain.push(&current.name);
+        for inherits_from_name in current.inherits.iter().flatten() {
+            if inherits_from_name == target {
+                return true;
+            }
+            if !visited.insert(inherits_from_name) {
+                continue;
+            }
+            if let Some(next) = get_layout_by_name(inherits_from_name, layouts) {
+                if visit(next, target, layouts, visited, chain) {
+                    return true;
+                }
+            }
+        }
+        chain.pop();
+        false
+    }
+    let mut chain = Vec::new();
+    visit(
+        layout,
+        &layout.name,
+        layouts,
+        &mut HashSet::new(),
+        &mut chain,
+    )
+    .then_some(chain)
+}
+
+/// Gets the names of the layouts that can be selected with `-l/--layout`.
 pub fn get_layout_names(layouts: &[LayoutDefinition]) -> Vec<String> {
-    layouts.iter().map(|l| l.name.clone()).collect()
+    layouts
+        .iter()
+        .filter(|l| l.selectable)
+        .map(|l| l.name.clone())
+        .collect()
 }
@@ -1,8 +1,16 @@
+pub mod cache;
 pub mod cli;
 pub mod config;
+pub mod daemon;
+pub mod error;
 pub mod handler;
 pub mod layout;
 pub mod matches;
+pub mod state;
+#[cfg(test)]
+mod test_utils;
 pub mod tmux;
 pub mod ui;
 pub mod workspace;
+
+pub use error::{Error, Result};
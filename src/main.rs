@@ -1,7 +1,7 @@
 use anyhow::Result;
+use std::process::ExitCode;
 use twm::cli;
 
-fn main() -> Result<()> {
-    cli::parse()?;
-    Ok(())
+fn main() -> Result<ExitCode> {
+    cli::parse()
 }
@@ -1,51 +1,1224 @@
-use crate::config::TwmGlobal;
-use crate::workspace::path_meets_workspace_conditions;
+use crate::cache::{CachedWorkspace, WorkspaceCache};
+use crate::config::{SortBy, TwmGlobal};
+use crate::daemon;
+use crate::state::History;
+use crate::ui::{compare_matches, PickerItem};
+use crate::workspace::{get_last_modified, get_workspace_definition_for_path};
 
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use jwalk::{
     rayon::{
         current_num_threads,
         iter::{ParallelBridge, ParallelIterator},
     },
-    WalkDir,
+    DirEntry, Parallelism, WalkDirGeneric,
 };
-use nucleo::Injector;
+use nucleo::{
+    pattern::{CaseMatching, Normalization, Pattern},
+    Injector, Matcher, Nucleo, Utf32Str,
+};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Mutex, OnceLock,
+};
+use std::time::SystemTime;
+
+/// How many times better the best match's score has to be than the runner-up's for a query to be considered
+/// unambiguous.
+const UNAMBIGUOUS_SCORE_RATIO: u32 = 2;
+
+/// Checks whether any component of `path` below the search root `root` is in `exclude_path_components`.
+///
+/// Components of the root itself are ignored, so explicitly searching inside an excluded directory still works.
+// this can definitely be improved in the future
+pub(crate) fn path_is_excluded(path: &Path, root: &Path, config: &TwmGlobal) -> bool {
+    let relative_path = path.strip_prefix(root).unwrap_or(path);
+    relative_path
+        .components()
+        .any(|c| match c.as_os_str().to_str() {
+            // excludes were already lowercased when loading the config
+            Some(s) if config.case_insensitive_excludes => {
+                config.exclude_path_components.contains(&s.to_lowercase())
+            }
+            Some(s) => config.exclude_path_components.iter().any(|e| s == e),
+            None => true,
+        })
+}
+
+/// Finds the nearest directory containing a `.git` file or directory among `path` and its ancestors, without
+/// looking above the search root `root`.
+fn nearest_git_root(path: &Path, root: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .take_while(|ancestor| ancestor.starts_with(root))
+        .find(|ancestor| ancestor.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+/// Caps how many workspaces are injected into the picker, shared between every thread searching for workspaces.
+pub struct InjectionLimit {
+    injected: AtomicUsize,
+    max: usize,
+    truncated: AtomicBool,
+}
+
+impl InjectionLimit {
+    pub fn new(max: usize) -> Self {
+        Self {
+            injected: AtomicUsize::new(0),
+            max,
+            truncated: AtomicBool::new(false),
+        }
+    }
+
+    /// Reserves room for one more workspace. Once the cap is reached this returns false and marks the search as
+    /// truncated.
+    fn reserve(&self) -> bool {
+        if self.injected.fetch_add(1, Ordering::Relaxed) < self.max {
+            return true;
+        }
+        self.truncated.store(true, Ordering::Relaxed);
+        false
+    }
+
+    /// Whether any workspace was left out because the cap was reached.
+    pub fn truncated(&self) -> bool {
+        self.truncated.load(Ordering::Relaxed)
+    }
+}
+
+/// Gets the time workspaces must have been modified after to be listed, if `max_age_days` is set.
+fn modified_cutoff(config: &TwmGlobal) -> Option<SystemTime> {
+    config.max_age.map(|age| {
+        SystemTime::now()
+            .checked_sub(age)
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    })
+}
 
-pub fn find_workspaces_in_dir(dir: &str, config: &TwmGlobal, injector: Injector<String>) {
-    WalkDir::new(dir)
-        .max_depth(config.max_search_depth)
+/// Checks whether the workspace at `path` was modified after `cutoff`. Everything is recent enough without a cutoff.
+fn is_recent_enough(path: &Path, cutoff: Option<SystemTime>) -> bool {
+    match cutoff {
+        Some(cutoff) => get_last_modified(path).is_some_and(|modified| modified >= cutoff),
+        None => true,
+    }
+}
+
+/// File in a search path listing patterns of directories twm never searches, in addition to `.gitignore` files.
+pub const TWMIGNORE_FILE_NAME: &str = ".twmignore";
+
+pub const GITIGNORE_FILE_NAME: &str = ".gitignore";
+
+/// Client state of the walk. Each directory read carries the ignore files that apply to its children, from the
+/// outermost directory to the innermost.
+type WalkState = (Vec<Arc<Gitignore>>, ());
+
+/// Reads the ignore file `file_name` in `dir`, if there is one. Patterns that can't be parsed are skipped.
+fn load_ignore_file(dir: &Path, file_name: &str) -> Option<Gitignore> {
+    let path = dir.join(file_name);
+    if !path.is_file() {
+        return None;
+    }
+    let mut builder = GitignoreBuilder::new(dir);
+    // the error only lists the lines that were skipped, the rest of the file still applies
+    let _ = builder.add(path);
+    builder.build().ok()
+}
+
+/// Checks whether `path` is ignored by any of `ignore_files`, which are ordered from the outermost directory to the
+/// innermost. Like with git, the ignore file closest to `path` has the final say.
+fn is_ignored(path: &Path, is_dir: bool, ignore_files: &[Arc<Gitignore>]) -> bool {
+    ignore_files
+        .iter()
+        .rev()
+        // a file only applies to the directory containing it, which isn't checked for paths outside of it
+        .filter(|ignore_file| path.starts_with(ignore_file.path()))
+        .map(|ignore_file| ignore_file.matched(path, is_dir))
+        .find(|matched| !matched.is_none())
+        .is_some_and(|matched| matched.is_ignore())
+}
+
+/// Symlinks found while searching that lead back to one of their own parent directories.
+///
+/// Following one would walk the same directories over and over, so they're skipped and collected here to be reported
+/// once nothing is drawing over stderr anymore.
+#[derive(Default)]
+pub struct SymlinkCycles {
+    // whether each link was reported yet, so every link is only warned about once
+    links: Mutex<BTreeMap<PathBuf, bool>>,
+}
+
+impl SymlinkCycles {
+    fn record(&self, link: PathBuf) {
+        self.links
+            .lock()
+            .expect("Symlink cycles lock poisoned")
+            .entry(link)
+            .or_insert(false);
+    }
+
+    /// Whether any link was found that wasn't reported yet.
+    pub fn has_unreported(&self) -> bool {
+        self.links
+            .lock()
+            .expect("Symlink cycles lock poisoned")
+            .values()
+            .any(|reported| !reported)
+    }
+
+    /// Prints a warning to stderr for every link that wasn't reported yet.
+    pub fn report(&self) {
+        let mut links = self.links.lock().expect("Symlink cycles lock poisoned");
+        for (link, reported) in links.iter_mut().filter(|(_, reported)| !**reported) {
+            eprintln!(
+                "Warning: not following symlink {}, it points back to one of its parent directories.",
+                link.display()
+            );
+            *reported = true;
+        }
+    }
+}
+
+/// Stops the walk from descending into symlinked directories in `dir` that were already visited through another
+/// link, or that point back to `dir` or one of its parents. The latter are recorded in `cycles`.
+fn break_symlink_cycles(
+    dir: &Path,
+    children: &mut [jwalk::Result<DirEntry<WalkState>>],
+    visited_links: &Mutex<HashSet<PathBuf>>,
+    cycles: &SymlinkCycles,
+) {
+    // only canonicalized if there's a symlink to compare against, most directories don't have any
+    let mut canonical_dir = None;
+    for child in children.iter_mut() {
+        let child = match child {
+            Ok(child) => child,
+            // jwalk catches links pointing at the exact path of one of their parents itself
+            Err(e) => {
+                if let (Some(link), Some(_)) = (e.path(), e.loop_ancestor()) {
+                    cycles.record(link.to_path_buf());
+                }
+                continue;
+            }
+        };
+        if child.read_children_path.is_none() || !child.path_is_symlink() {
+            continue;
+        }
+        let link = child.path();
+        let Ok(target) = std::fs::canonicalize(&link) else {
+            continue;
+        };
+        let canonical_dir = canonical_dir
+            .get_or_insert_with(|| std::fs::canonicalize(dir).unwrap_or_else(|_| dir.into()));
+        if canonical_dir.starts_with(&target) {
+            child.read_children_path = None;
+            cycles.record(link);
+        } else if !visited_links
+            .lock()
+            .expect("Visited links lock poisoned")
+            .insert(target)
+        {
+            // links pointing at each other can still loop without either pointing at its own parents
+            child.read_children_path = None;
+        }
+    }
+}
+
+/// Checks whether `path` is one of the configured favorites, which are injected before anything else is found.
+fn is_favorite(path: &str, config: &TwmGlobal) -> bool {
+    config.favorites.iter().any(|favorite| favorite == path)
+}
+
+pub(crate) fn is_hidden(name: &std::ffi::OsStr) -> bool {
+    name.to_str().is_some_and(|name| name.starts_with('.'))
+}
+
+/// Search of a search path whose cached workspaces were already injected, which collects every workspace found to
+/// update the cache with.
+#[derive(Default)]
+pub struct CacheRefresh {
+    /// Paths injected from the cache, which aren't injected again when they're found.
+    cached: HashSet<String>,
+    found: Mutex<Vec<CachedWorkspace>>,
+}
+
+impl CacheRefresh {
+    /// Takes the workspaces found by the search.
+    fn into_found(self) -> Vec<CachedWorkspace> {
+        self.found
+            .into_inner()
+            .expect("Cache refresh lock poisoned")
+    }
+}
+
+/// Where a search of one search path adds the workspaces it finds, and which of them it leaves out.
+pub struct DirSearch<'a> {
+    pub injector: Injector<PickerItem>,
+    /// Whether workspaces are grouped under the search path they're found in rather than under their type.
+    pub group_by_search_path: bool,
+    /// Workspace that's left out wherever it's found.
+    pub excluded_workspace: Option<&'a str>,
+    pub limit: &'a InjectionLimit,
+    pub cycles: &'a Arc<SymlinkCycles>,
+    /// Set when the search refreshes the cached workspaces of the search path.
+    pub refresh: Option<&'a CacheRefresh>,
+}
+
+/// Searches `dir` for workspaces, adding them to the picker as they're found.
+pub fn find_workspaces_in_dir(dir: &str, config: &TwmGlobal, search: DirSearch) {
+    let DirSearch {
+        injector,
+        group_by_search_path,
+        excluded_workspace,
+        limit,
+        cycles,
+        refresh,
+    } = search;
+    let parallelism = match config.discovery_threads {
+        Some(1) => Parallelism::Serial,
+        Some(threads) => Parallelism::RayonNewPool(threads),
+        None => Parallelism::RayonNewPool(std::cmp::max(1, current_num_threads() - 1)),
+    };
+    let search_hidden = config.search_hidden;
+    let follow_links = config.follow_links;
+    let visited_links = Mutex::new(HashSet::new());
+    let cycles = cycles.clone();
+    let respect_gitignore = config.respect_gitignore;
+    let twmignore = load_ignore_file(Path::new(dir), TWMIGNORE_FILE_NAME);
+    let entries = WalkDirGeneric::<WalkState>::new(dir)
+        .root_read_dir_state(twmignore.map(Arc::new).into_iter().collect())
+        .max_depth(config.max_search_depth_for(dir))
+        // hidden entries are always yielded so they can still be matched as workspaces, whether we descend into
+        // them is decided separately below
         .skip_hidden(false)
         .follow_links(config.follow_links)
-        .parallelism(jwalk::Parallelism::RayonNewPool(std::cmp::max(
-            1,
-            current_num_threads() - 1,
-        )))
+        .parallelism(parallelism)
+        .process_read_dir(move |depth, dir, ignore_files, children| {
+            // the root entry is read with its parent directory as `dir`, it can't be part of a cycle or be ignored
+            if depth.is_none() {
+                return;
+            }
+            if follow_links {
+                break_symlink_cycles(dir, children, &visited_links, &cycles);
+            }
+            if respect_gitignore {
+                if let Some(gitignore) = load_ignore_file(dir, GITIGNORE_FILE_NAME) {
+                    ignore_files.push(Arc::new(gitignore));
+                }
+            }
+            // ignored directories are dropped entirely, so they're neither searched nor matched as workspaces
+            if !ignore_files.is_empty() {
+                children.retain(|child| {
+                    child.as_ref().map_or(true, |child| {
+                        !is_ignored(&child.path(), child.file_type.is_dir(), ignore_files)
+                    })
+                });
+            }
+            if search_hidden {
+                return;
+            }
+            for child in children.iter_mut().flatten() {
+                if is_hidden(&child.file_name) {
+                    child.read_children_path = None;
+                }
+            }
+        })
         .into_iter()
-        .par_bridge()
         .filter_map(std::result::Result::ok)
         .filter(|e| {
             e.file_type().is_dir()
-                // this can definitely be improved in the future 
-                && !e.path().components().any(|c| match c.as_os_str().to_str() {
-                    Some(s) => config.exclude_path_components.iter().any(|e| s == e),
-                    None => true,
-                })
-        })
-        .for_each(|entry| {
-            for workspace_definition in &config.workspace_definitions {
-                if path_meets_workspace_conditions(&entry.path(), &workspace_definition.conditions)
-                {
-                    // just skip the path if it's not valid utf-8 since we can't use it
-                    // skip here instead of checking earlier because i don't expect people having a bunch of non-utf8 paths to be common, so defer the check only if we have a match in the first place
-                    if let Some(utf8_path) = entry.path().to_str() {
-                        // previously we also stored which workspace type we matched on, but i decided to change it because we only ever need to know the workspace type for the workspace we're opening anyways
-                        // having to re-lookup the workspace type on user selection is surely better than the hashmap we were using before, but better would probably be to just keep track of which WorkspaceDefinition matched here
-                        // main reason I haven't yet is because I'm not entirely sure how to make that work nicely with the fuzzy finders
-                        injector.push(utf8_path.to_string(), |_, dst| {
-                            dst[0] = utf8_path.to_string().into()
-                        });
+                // the search root is always matchable, even if it's hidden itself
+                && (config.include_hidden_workspaces || e.depth() == 0 || !is_hidden(e.file_name()))
+                && !path_is_excluded(&e.path(), Path::new(dir), config)
+        });
+    let cutoff = modified_cutoff(config);
+    // every directory in a repository collapses to the same root, so this keeps track of which were already found
+    let collapsed_roots = Mutex::new(HashSet::new());
+    // returns None once the cap is reached to stop the walk
+    let inject_workspace = |entry: DirEntry<WalkState>| -> Option<()> {
+        let mut path = entry.path();
+        let Some(mut workspace_definition) =
+            get_workspace_definition_for_path(&path, &config.workspace_definitions)
+        else {
+            return Some(());
+        };
+        if config.collapse_to_git_root {
+            if let Some(root) = nearest_git_root(&path, Path::new(dir)) {
+                if root != path {
+                    // the root is listed under its own workspace type if it has one
+                    if let Some(root_definition) =
+                        get_workspace_definition_for_path(&root, &config.workspace_definitions)
+                    {
+                        workspace_definition = root_definition;
                     }
-                    break;
+                    path = root;
                 }
             }
-        });
+            if !collapsed_roots
+                .lock()
+                .expect("Collapsed roots lock poisoned")
+                .insert(path.clone())
+            {
+                return Some(());
+            }
+        }
+        // the cache keeps workspaces of every age, since `max_age_days` is checked again when they're listed
+        if let (Some(refresh), Some(utf8_path)) = (refresh, path.to_str()) {
+            refresh
+                .found
+                .lock()
+                .expect("Cache refresh lock poisoned")
+                .push(CachedWorkspace::new(
+                    utf8_path,
+                    Some(&workspace_definition.name),
+                ));
+        }
+        // only stat matched workspaces, checking every directory visited would slow down the search a lot more
+        if !is_recent_enough(&path, cutoff) {
+            return Some(());
+        }
+        // just skip the path if it's not valid utf-8 since we can't use it
+        // skip here instead of checking earlier because i don't expect people having a bunch of non-utf8 paths to be common, so defer the check only if we have a match in the first place
+        if let Some(utf8_path) = path.to_str().filter(|p| {
+            Some(*p) != excluded_workspace
+                && !is_favorite(p, config)
+                && !refresh.is_some_and(|refresh| refresh.cached.contains(*p))
+        }) {
+            if !limit.reserve() {
+                return None;
+            }
+            workspace_item(
+                utf8_path,
+                Some(&workspace_definition.name),
+                Some(dir),
+                group_by_search_path,
+                config,
+            )
+            .inject(&injector);
+        }
+        Some(())
+    };
+    // a sequential walk should also check workspace conditions sequentially, otherwise we'd still be hitting
+    // the filesystem from every thread in the global pool
+    if config.discovery_threads == Some(1) {
+        entries
+            .map(inject_workspace)
+            .take_while(Option::is_some)
+            .for_each(drop);
+    } else {
+        entries.par_bridge().try_for_each(inject_workspace);
+    }
+}
+
+/// Builds the picker item for the workspace at `path`, labelled with its type if `show_workspace_type` is set and
+/// including any tags configured for it.
+///
+/// The item is grouped under `search_path`, the search path it was found in, if `group_by_search_path` is set, and
+/// under its type otherwise.
+fn workspace_item(
+    path: &str,
+    workspace_type: Option<&str>,
+    search_path: Option<&str>,
+    group_by_search_path: bool,
+    config: &TwmGlobal,
+) -> PickerItem {
+    let group = match group_by_search_path {
+        true => search_path,
+        false => workspace_type,
+    };
+    let mut item = PickerItem::new(path.to_owned(), group.map(String::from));
+    if let Some(workspace_type) = workspace_type.filter(|_| config.show_workspace_type) {
+        item = item.with_label(workspace_type);
+    }
+    item.with_tags(config.tags_for_workspace(Path::new(path)))
+        .with_favorite(is_favorite(path, config))
+        .with_rank(workspace_rank(path, config))
+}
+
+/// Finds the configured search path that `path` is in, if any.
+fn containing_search_path<'a>(path: &Path, config: &'a TwmGlobal) -> Option<&'a str> {
+    config
+        .search_paths
+        .iter()
+        .find(|dir| path.starts_with(dir))
+        .map(String::as_str)
+}
+
+/// Finds the configured search path that searching would find the favorite at `path` in, if any.
+///
+/// Favorites are listed without searching, so this checks the same depth, excludes and hidden directories a search
+/// would.
+fn favorite_search_path<'a>(path: &Path, config: &'a TwmGlobal) -> Option<&'a str> {
+    config
+        .search_paths
+        .iter()
+        .find(|dir| {
+            let Ok(relative_path) = path.strip_prefix(dir) else {
+                return false;
+            };
+            let components: Vec<_> = relative_path.components().collect();
+            let Some((last, parents)) = components.split_last() else {
+                // the search root is always matchable
+                return true;
+            };
+            components.len() <= config.max_search_depth_for(dir)
+                && !path_is_excluded(path, Path::new(dir), config)
+                && (config.search_hidden || !parents.iter().any(|c| is_hidden(c.as_os_str())))
+                && (config.include_hidden_workspaces || !is_hidden(last.as_os_str()))
+        })
+        .map(String::as_str)
+}
+
+/// History of opened workspaces, only read the first time a workspace's rank is needed.
+static HISTORY: OnceLock<History> = OnceLock::new();
+
+/// Scores how much the workspace at `path` was used, if the picker is ordered by it.
+fn workspace_rank(path: &str, config: &TwmGlobal) -> u32 {
+    if config.sort_by != SortBy::Frecency {
+        return 0;
+    }
+    HISTORY
+        .get_or_init(History::load)
+        .frecency(path, SystemTime::now())
+}
+
+/// Adds the workspaces in all configured search paths to the picker.
+///
+/// Favorites are added first, so they're listed first while the picker's query is empty. Search paths indexed by a
+/// running `twm --daemon` are taken from its index, the rest are searched as normal. With `cache_ttl` set, the
+/// workspaces cached for those are added before searching them, which then only adds the ones that weren't cached
+/// and updates the cache. `excluded_workspace` is left out wherever it's found. Returns whether the list was
+/// truncated because `max_injected` workspaces were found.
+pub fn inject_workspaces(
+    config: &TwmGlobal,
+    injector: Injector<PickerItem>,
+    excluded_workspace: Option<&str>,
+    cycles: &Arc<SymlinkCycles>,
+) -> bool {
+    let limit = InjectionLimit::new(config.max_injected);
+    let cutoff = modified_cutoff(config);
+    for favorite in &config.favorites {
+        let path = Path::new(favorite);
+        if Some(favorite.as_str()) == excluded_workspace || !path.is_dir() {
+            continue;
+        }
+        // favorites are only listed if searching would have found them
+        let Some(search_path) = favorite_search_path(path, config) else {
+            continue;
+        };
+        let Some(workspace_definition) =
+            get_workspace_definition_for_path(path, &config.workspace_definitions)
+        else {
+            continue;
+        };
+        if !is_recent_enough(path, cutoff) {
+            continue;
+        }
+        if !limit.reserve() {
+            return true;
+        }
+        workspace_item(
+            favorite,
+            Some(&workspace_definition.name),
+            Some(search_path),
+            config.group_by_search_path,
+            config,
+        )
+        .inject(&injector);
+    }
+    if let Some(command) = &config.workspace_command {
+        // there's nowhere to show an error while the picker is open, so a failing command just adds nothing
+        for path in run_workspace_command(command).unwrap_or_default() {
+            if Some(path.as_str()) == excluded_workspace || is_favorite(&path, config) {
+                continue;
+            }
+            if !limit.reserve() {
+                return true;
+            }
+            let workspace_definition =
+                get_workspace_definition_for_path(Path::new(&path), &config.workspace_definitions);
+            workspace_item(
+                &path,
+                workspace_definition.map(|d| d.name.as_str()),
+                containing_search_path(Path::new(&path), config),
+                config.group_by_search_path,
+                config,
+            )
+            .inject(&injector);
+        }
+    }
+    let mut index = daemon::query_index(config).ok();
+    let mut cache = config.cache_ttl.map(|_| WorkspaceCache::load());
+    let now = SystemTime::now();
+    // the index and the cache can be older than `max_age_days`, so their workspaces are checked again here
+    let is_listed = |path: &str| {
+        Some(path) != excluded_workspace
+            && !is_favorite(path, config)
+            && is_recent_enough(Path::new(path), cutoff)
+    };
+    // indexed and cached workspaces are all listed before any search path is searched
+    let mut unindexed = Vec::new();
+    for dir in &config.search_paths {
+        if let Some(workspaces) = index.as_mut().and_then(|index| index.remove(dir)) {
+            for workspace in workspaces {
+                if !is_listed(&workspace.path) {
+                    continue;
+                }
+                if !limit.reserve() {
+                    return true;
+                }
+                workspace_item(
+                    &workspace.path,
+                    workspace.workspace_type.as_deref(),
+                    Some(dir),
+                    config.group_by_search_path,
+                    config,
+                )
+                .inject(&injector);
+            }
+            continue;
+        }
+        let Some((cache, ttl)) = cache.as_ref().zip(config.cache_ttl) else {
+            unindexed.push((dir, None));
+            continue;
+        };
+        let mut refresh = CacheRefresh::default();
+        for workspace in cache.get(dir, ttl, now).unwrap_or_default() {
+            let Some(workspace_type) = revalidate_cached_workspace(workspace, config) else {
+                continue;
+            };
+            if !is_listed(&workspace.path) {
+                continue;
+            }
+            if !limit.reserve() {
+                return true;
+            }
+            workspace_item(
+                &workspace.path,
+                workspace_type,
+                Some(dir),
+                config.group_by_search_path,
+                config,
+            )
+            .inject(&injector);
+            refresh.cached.insert(workspace.path.clone());
+        }
+        unindexed.push((dir, Some(refresh)));
+    }
+    for (dir, refresh) in unindexed {
+        find_workspaces_in_dir(
+            dir,
+            config,
+            DirSearch {
+                injector: injector.clone(),
+                group_by_search_path: config.group_by_search_path,
+                excluded_workspace,
+                limit: &limit,
+                cycles,
+                refresh: refresh.as_ref(),
+            },
+        );
+        if limit.truncated() {
+            return true;
+        }
+        if let (Some(cache), Some(refresh)) = (&mut cache, refresh) {
+            cache.insert(dir, refresh.into_found(), now);
+        }
+    }
+    if let Some(cache) = cache.filter(|_| !config.dry_run) {
+        // the cache only speeds up the next run, so failing to write it shouldn't hold up this one
+        let _ = cache.save();
+    }
+    false
+}
+
+/// Gets the type of the cached workspace, looking it up again if the workspace was modified since it was cached.
+/// Returns `None` if the workspace doesn't exist anymore or no longer matches any workspace definition.
+fn revalidate_cached_workspace<'a>(
+    workspace: &'a CachedWorkspace,
+    config: &'a TwmGlobal,
+) -> Option<Option<&'a str>> {
+    let path = Path::new(&workspace.path);
+    if !path.is_dir() {
+        return None;
+    }
+    if !workspace.is_modified() {
+        return Some(workspace.workspace_type.as_deref());
+    }
+    get_workspace_definition_for_path(path, &config.workspace_definitions)
+        .map(|definition| Some(definition.name.as_str()))
+}
+
+/// Runs `workspace_command` and returns the workspace paths it printed, one per line.
+fn run_workspace_command(command: &str) -> Result<Vec<String>> {
+    let shell = std::env::var("SHELL").unwrap_or("sh".to_string());
+    // stderr would draw over the picker
+    let output = Command::new(shell)
+        .args(["-c", command])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run workspace command `{command}`"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Workspace command `{command}` exited with {}",
+            output.status
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| shellexpand::tilde(line).into_owned())
+        .collect())
+}
+
+/// Waits for everything injected into `matcher` to be processed and returns the items.
+fn collect_items(mut matcher: Nucleo<PickerItem>) -> Vec<PickerItem> {
+    while matcher.tick(10).running {}
+    matcher
+        .snapshot()
+        .matched_items(..)
+        .map(|item| item.data.clone())
+        .collect()
+}
+
+/// Searches `dir` for workspaces, blocking until the search is finished.
+///
+/// The items are always grouped under their workspace type, whatever `group_by_search_path` is set to.
+pub fn scan_workspaces_in_dir(
+    dir: &str,
+    config: &TwmGlobal,
+    cycles: &Arc<SymlinkCycles>,
+) -> Vec<PickerItem> {
+    let matcher = Nucleo::new(nucleo::Config::DEFAULT, Arc::new(|| {}), None, 1);
+    let limit = InjectionLimit::new(config.max_injected);
+    find_workspaces_in_dir(
+        dir,
+        config,
+        DirSearch {
+            injector: matcher.injector(),
+            // the daemon's index keeps each workspace's type in its group
+            group_by_search_path: false,
+            excluded_workspace: None,
+            limit: &limit,
+            cycles,
+            refresh: None,
+        },
+    );
+    collect_items(matcher)
+}
+
+/// Workspaces found before showing the picker, which it can list instead of searching for them again.
+pub struct FoundWorkspaces {
+    pub items: Vec<PickerItem>,
+    /// Whether the search stopped early because `max_injected` workspaces were found.
+    pub truncated: bool,
+}
+
+/// Finds the workspaces in all configured search paths as they'd be listed in the picker, blocking until the search
+/// is finished. `excluded_workspace` is left out wherever it's found.
+pub fn find_workspace_items(
+    config: &TwmGlobal,
+    excluded_workspace: Option<&str>,
+) -> FoundWorkspaces {
+    let matcher = Nucleo::new(nucleo::Config::DEFAULT, Arc::new(|| {}), None, 1);
+    let cycles = Arc::default();
+    let truncated = inject_workspaces(config, matcher.injector(), excluded_workspace, &cycles);
+    cycles.report();
+    FoundWorkspaces {
+        items: collect_items(matcher),
+        truncated,
+    }
+}
+
+/// Finds the workspaces in all configured search paths, blocking until the search is finished.
+pub fn find_all_workspaces(config: &TwmGlobal) -> Vec<String> {
+    find_workspace_items(config, None)
+        .items
+        .into_iter()
+        .map(|item| item.text)
+        .collect()
+}
+
+/// Scores `items` against `query` the way the picker does, leaving out the ones that don't match. The matches are
+/// ordered by score, with equal scores keeping their order in `items`.
+fn score_items<'a>(query: &str, items: &'a [PickerItem]) -> Vec<(&'a PickerItem, u32)> {
+    let pattern = Pattern::parse(query, CaseMatching::Smart, Normalization::Smart);
+    let mut matcher = Matcher::new(nucleo::Config::DEFAULT);
+    let mut buf = Vec::new();
+    let mut matches: Vec<(&PickerItem, u32)> = items
+        .iter()
+        .filter_map(|item| {
+            let match_text = item.match_text();
+            let score = pattern.score(Utf32Str::new(&match_text, &mut buf), &mut matcher)?;
+            Some((item, score))
+        })
+        .collect();
+    matches.sort_by_key(|(_, score)| Reverse(*score));
+    matches
+}
+
+/// Counts the workspaces in all configured search paths, or only the ones fuzzy matching `query` if given.
+pub fn count_workspaces(query: Option<&str>, config: &TwmGlobal) -> usize {
+    let workspaces = find_workspace_items(config, None).items;
+    match query.filter(|q| !q.is_empty()) {
+        Some(query) => score_items(query, &workspaces).len(),
+        None => workspaces.len(),
+    }
+}
+
+/// Fuzzy matches `query` against every workspace but `excluded_workspace` and returns the match the picker would list
+/// first, or the first workspace the picker would list if there's no query.
+///
+/// Matches tied on every `tiebreak` criterion are broken alphabetically, so the result doesn't depend on the order
+/// workspaces are found in.
+pub fn find_best_workspace_match(
+    query: Option<&str>,
+    config: &TwmGlobal,
+    excluded_workspace: Option<&str>,
+) -> Option<String> {
+    let mut workspaces = find_workspace_items(config, excluded_workspace).items;
+    let Some(query) = query.filter(|q| !q.is_empty()) else {
+        let mut workspaces: Vec<String> = workspaces.into_iter().map(|item| item.text).collect();
+        sort_unfiltered(&mut workspaces, config, |path| workspace_rank(path, config));
+        return workspaces.into_iter().next();
+    };
+    workspaces.sort_unstable_by(|a, b| a.text.cmp(&b.text));
+    // the first of equally good matches wins, so ties keep their alphabetical order
+    score_items(query, &workspaces)
+        .into_iter()
+        .min_by(|a, b| compare_matches(*a, *b, &config.tiebreak))
+        .map(|(best, _)| best.text.clone())
+}
+
+/// Orders workspaces the way the picker lists them while nothing is typed: favorites first in the order they're
+/// configured, then the rest by `sort_by`, with `rank` scoring them for `frecency`. Ties are broken alphabetically.
+fn sort_unfiltered(workspaces: &mut [String], config: &TwmGlobal, rank: impl Fn(&str) -> u32) {
+    workspaces.sort_by_cached_key(|path| {
+        let favorite = config
+            .favorites
+            .iter()
+            .position(|favorite| favorite == path);
+        let rank = match config.sort_by {
+            SortBy::Frecency => rank(path),
+            SortBy::Path | SortBy::None => 0,
+        };
+        (favorite.unwrap_or(usize::MAX), Reverse(rank), path.clone())
+    });
+}
+
+/// Fuzzy matches `query` against the found workspaces, returning the best match only if it's the sole match or its
+/// score clearly beats every other match.
+pub fn find_unambiguous_workspace_match(
+    query: &str,
+    workspaces: &FoundWorkspaces,
+) -> Option<String> {
+    let mut matches = score_items(query, &workspaces.items).into_iter();
+    let (best, best_score) = matches.next()?;
+    match matches.next() {
+        Some((_, runner_up_score))
+            if best_score < runner_up_score.saturating_mul(UNAMBIGUOUS_SCORE_RATIO) =>
+        {
+            None
+        }
+        _ => Some(best.text.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{config_from_yaml, config_searching, TestDir};
+    use serial_test::serial;
+
+    fn config_with_excludes(case_insensitive: bool) -> TwmGlobal {
+        config_from_yaml(&format!(
+            "exclude_path_components: [node_modules]\ncase_insensitive_excludes: {case_insensitive}"
+        ))
+    }
+
+    #[test]
+    fn test_case_insensitive_excludes() {
+        let root = Path::new("/home/vinny");
+        let path = Path::new("/home/vinny/project/Node_Modules/foo");
+        assert!(!path_is_excluded(path, root, &config_with_excludes(false)));
+        assert!(path_is_excluded(path, root, &config_with_excludes(true)));
+        assert!(path_is_excluded(
+            Path::new("/home/vinny/project/node_modules"),
+            root,
+            &config_with_excludes(false)
+        ));
+    }
+
+    #[test]
+    fn test_excludes_ignore_search_root() {
+        let config = config_with_excludes(false);
+        let root = Path::new("/home/vinny/node_modules/vendored");
+        assert!(!path_is_excluded(root, root, &config));
+        assert!(!path_is_excluded(
+            Path::new("/home/vinny/node_modules/vendored/foo"),
+            root,
+            &config
+        ));
+        assert!(path_is_excluded(
+            Path::new("/home/vinny/node_modules/vendored/foo/node_modules"),
+            root,
+            &config
+        ));
+    }
+
+    #[test]
+    fn test_is_recent_enough() {
+        let dir = std::env::temp_dir();
+        let hour = std::time::Duration::from_secs(60 * 60);
+        assert!(is_recent_enough(&dir, None));
+        assert!(is_recent_enough(&dir, Some(SystemTime::UNIX_EPOCH)));
+        assert!(!is_recent_enough(&dir, Some(SystemTime::now() + hour)));
+        assert!(!is_recent_enough(
+            Path::new("/definitely/not/a/dir"),
+            Some(SystemTime::UNIX_EPOCH)
+        ));
+    }
+
+    #[test]
+    fn test_hidden_directory_search_and_matching() {
+        let root =
+            TestDir::new("hidden-workspaces").with_git_repos(&[".dotfiles", ".stash/project"]);
+        let find = |search_hidden: bool, include_hidden_workspaces: bool| {
+            let config = config_searching(
+                &root,
+                &format!("search_hidden: {search_hidden}\ninclude_hidden_workspaces: {include_hidden_workspaces}"),
+            );
+            let mut found = root.relative(find_all_workspaces(&config));
+            found.sort();
+            found
+        };
+        assert_eq!(find(true, true), ["/.dotfiles", "/.stash/project"]);
+        assert_eq!(find(false, true), ["/.dotfiles"]);
+        assert_eq!(find(true, false), ["/.stash/project"]);
+    }
+
+    #[test]
+    fn test_max_injected() {
+        let root = TestDir::new("max-injected").with_git_repos(&["a", "b", "c", "d"]);
+        let config = config_searching(&root, "max_injected: 3");
+        let matcher = Nucleo::new(nucleo::Config::DEFAULT, Arc::new(|| {}), None, 1);
+        let cycles = Arc::default();
+        assert!(inject_workspaces(
+            &config,
+            matcher.injector(),
+            None,
+            &cycles
+        ));
+        assert_eq!(collect_items(matcher).len(), 3);
+        let matcher = Nucleo::new(nucleo::Config::DEFAULT, Arc::new(|| {}), None, 1);
+        assert!(!inject_workspaces(
+            &config,
+            matcher.injector(),
+            Some(root.join("a").to_str().unwrap()),
+            &cycles
+        ));
+        assert_eq!(collect_items(matcher).len(), 3);
+    }
+
+    #[test]
+    fn test_symlink_cycles_are_not_followed() {
+        let root = TestDir::new("symlink-cycles").with_git_repos(&["project"]);
+        std::fs::create_dir_all(root.join("nested/inner")).unwrap();
+        // relative links get past jwalk's own loop detection
+        std::os::unix::fs::symlink("..", root.join("nested/back")).unwrap();
+        std::os::unix::fs::symlink("../..", root.join("nested/inner/up")).unwrap();
+        let config = config_searching(&root, "follow_links: true\nmax_search_depth: 20");
+        let matcher = Nucleo::new(nucleo::Config::DEFAULT, Arc::new(|| {}), None, 1);
+        let cycles = Arc::new(SymlinkCycles::default());
+        inject_workspaces(&config, matcher.injector(), None, &cycles);
+        let found: Vec<String> = collect_items(matcher)
+            .into_iter()
+            .map(|item| item.text)
+            .collect();
+        assert_eq!(found, [root.join("project").to_str().unwrap()]);
+        assert_eq!(
+            cycles.links.lock().unwrap().keys().collect::<Vec<_>>(),
+            [&root.join("nested/back"), &root.join("nested/inner/up")]
+        );
+        assert!(cycles.has_unreported());
+        cycles.report();
+        assert!(!cycles.has_unreported());
+    }
+
+    #[test]
+    fn test_favorites_are_injected_first() {
+        let root = TestDir::new("favorites").with_git_repos(&[
+            "a",
+            "b",
+            "c",
+            "d/deep/e",
+            ".hidden",
+            "node_modules/f",
+        ]);
+        std::fs::create_dir_all(root.join("not-a-workspace")).unwrap();
+        // favorites searching wouldn't find are ignored like missing ones
+        let config = config_searching(
+            &root,
+            &format!(
+                "max_search_depth: 2\nexclude_path_components: [node_modules]\n\
+                include_hidden_workspaces: false\n\
+                favorites: [{root}/c/, {root}/missing, {root}/a, /, {root}/not-a-workspace, {root}/d/deep/e, \
+                {root}/.hidden, {root}/node_modules/f]",
+                root = root.display()
+            ),
+        );
+        assert_eq!(
+            root.relative(find_all_workspaces(&config)),
+            ["/c", "/a", "/b"]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_cached_workspaces_are_refreshed() {
+        let root = TestDir::new("workspace-cache").with_git_repos(&["src/a", "src/b"]);
+        std::fs::create_dir_all(root.join("src/not-a-workspace")).unwrap();
+        let orig_cache = std::env::var_os("XDG_CACHE_HOME");
+        std::env::set_var("XDG_CACHE_HOME", root.join("cache"));
+        let search_path = root.join("src").to_str().unwrap().to_owned();
+        let config = config_searching(Path::new(&search_path), "cache_ttl: 60");
+        let found = || {
+            let mut found: Vec<String> = find_all_workspaces(&config)
+                .into_iter()
+                .map(|p| p.strip_prefix(&search_path).unwrap().to_owned())
+                .collect();
+            found.sort();
+            found
+        };
+
+        assert_eq!(found(), ["/a", "/b"]);
+        // whatever is cached is listed, as long as it still exists
+        let mut cache = WorkspaceCache::load();
+        let stale = ["a", "b", "not-a-workspace", "deleted"]
+            .map(|dir| CachedWorkspace::new(&format!("{search_path}/{dir}"), Some("default")));
+        cache.insert(&search_path, stale.to_vec(), SystemTime::now());
+        cache.save().unwrap();
+        assert_eq!(found(), ["/a", "/b", "/not-a-workspace"]);
+        // searching again replaced the cache
+        assert_eq!(found(), ["/a", "/b"]);
+
+        match orig_cache {
+            Some(cache) => std::env::set_var("XDG_CACHE_HOME", cache),
+            None => std::env::remove_var("XDG_CACHE_HOME"),
+        }
+    }
+
+    #[test]
+    fn test_ignored_directories_are_skipped() {
+        let root = TestDir::new("ignored-directories").with_git_repos(&[
+            "app",
+            "mono/vendor/dep",
+            "mono/lib",
+            "generated/out",
+        ]);
+        std::fs::write(root.join("mono/.gitignore"), "vendor/\n").unwrap();
+        std::fs::write(root.join(".twmignore"), "generated\n").unwrap();
+        let find = |respect_gitignore: bool| {
+            let config =
+                config_searching(&root, &format!("respect_gitignore: {respect_gitignore}"));
+            let mut found = root.relative(find_all_workspaces(&config));
+            found.sort();
+            found
+        };
+        assert_eq!(find(true), ["/app", "/mono/lib"]);
+        assert_eq!(find(false), ["/app", "/mono/lib", "/mono/vendor/dep"]);
+    }
+
+    #[test]
+    fn test_closest_ignore_file_wins() {
+        let ignore_file = |base: &str, line: &str| {
+            let mut builder = GitignoreBuilder::new(base);
+            builder.add_line(None, line).unwrap();
+            Arc::new(builder.build().unwrap())
+        };
+        let files = [
+            ignore_file("/repo", "generated"),
+            ignore_file("/repo/app", "!generated"),
+        ];
+        assert!(is_ignored(Path::new("/repo/lib/generated"), true, &files));
+        assert!(!is_ignored(Path::new("/repo/app/generated"), true, &files));
+        assert!(!is_ignored(Path::new("/repo/lib/other"), true, &files));
+    }
+
+    #[test]
+    fn test_run_workspace_command() {
+        assert_eq!(
+            run_workspace_command("printf '/a/b\\n\\n  /c  \\n'").unwrap(),
+            ["/a/b", "/c"]
+        );
+        assert!(run_workspace_command("echo /a; exit 1").is_err());
+    }
+
+    #[test]
+    fn test_find_best_workspace_match() {
+        let root =
+            TestDir::new("best-workspace-match").with_git_repos(&["beta", "alpha", "alphabet"]);
+        let config = config_searching(&root, "");
+        let best = |query| {
+            root.relative(find_best_workspace_match(query, &config, None))
+                .pop()
+        };
+        assert_eq!(best(None).as_deref(), Some("/alpha"));
+        assert_eq!(best(Some("")).as_deref(), Some("/alpha"));
+        assert_eq!(best(Some("beta")).as_deref(), Some("/beta"));
+        assert_eq!(best(Some("zzz")), None);
+    }
+
+    #[test]
+    fn test_best_workspace_match_is_listed_first_by_the_picker() {
+        let root =
+            TestDir::new("best-workspace-match-picker").with_git_repos(&["bb", "abb", "qux"]);
+        let best = |query, yaml: &str, excluded: Option<&str>| {
+            let config = config_searching(&root, yaml);
+            root.relative(find_best_workspace_match(Some(query), &config, excluded))
+                .pop()
+        };
+        assert_eq!(best("bb", "", None).as_deref(), Some("/bb"));
+        assert_eq!(
+            best("bb", "tiebreak: [path]", None).as_deref(),
+            Some("/abb")
+        );
+        let excluded = root.join("bb");
+        assert_eq!(best("bb", "", excluded.to_str()).as_deref(), Some("/abb"));
+        // tags are matched like in the picker
+        let tags = format!("workspace_tags:\n  {}/qux: [zzz]", root.display());
+        assert_eq!(best("zzz", &tags, None).as_deref(), Some("/qux"));
+    }
+
+    #[test]
+    fn test_sort_unfiltered() {
+        let mut config = config_from_yaml("sort_by: frecency\nfavorites: [/src/fav, /src/pinned]");
+        let rank = |path: &str| match path {
+            "/src/often" => 20,
+            "/src/rarely" => 1,
+            _ => 0,
+        };
+        let sorted = |config: &TwmGlobal| {
+            let mut workspaces = [
+                "/src/b",
+                "/src/rarely",
+                "/src/pinned",
+                "/src/often",
+                "/src/a",
+                "/src/fav",
+            ]
+            .map(String::from);
+            sort_unfiltered(&mut workspaces, config, rank);
+            workspaces
+        };
+        assert_eq!(
+            sorted(&config),
+            [
+                "/src/fav",
+                "/src/pinned",
+                "/src/often",
+                "/src/rarely",
+                "/src/a",
+                "/src/b"
+            ]
+        );
+        config.sort_by = SortBy::Path;
+        assert_eq!(
+            sorted(&config),
+            [
+                "/src/fav",
+                "/src/pinned",
+                "/src/a",
+                "/src/b",
+                "/src/often",
+                "/src/rarely"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_count_workspaces() {
+        let root = TestDir::new("count-workspaces").with_git_repos(&["beta", "alpha", "alphabet"]);
+        let config = config_searching(
+            &root,
+            &format!("workspace_tags:\n  {}/beta: [qux]", root.display()),
+        );
+        assert_eq!(count_workspaces(None, &config), 3);
+        assert_eq!(count_workspaces(Some(""), &config), 3);
+        assert_eq!(count_workspaces(Some("alpha"), &config), 2);
+        // tags are matched like in the picker
+        assert_eq!(count_workspaces(Some("qux"), &config), 1);
+        assert_eq!(count_workspaces(Some("zzz"), &config), 0);
+    }
+
+    #[test]
+    fn test_find_unambiguous_workspace_match() {
+        let workspaces = FoundWorkspaces {
+            items: ["/src/twm", "/src/tmux", "/src/nucleo"]
+                .into_iter()
+                .map(|path| PickerItem::new(path.to_owned(), None))
+                .collect(),
+            truncated: false,
+        };
+        assert_eq!(
+            find_unambiguous_workspace_match("nucleo", &workspaces).as_deref(),
+            Some("/src/nucleo")
+        );
+        assert_eq!(
+            find_unambiguous_workspace_match("/src/t", &workspaces),
+            None
+        );
+        assert_eq!(find_unambiguous_workspace_match("zzz", &workspaces), None);
+
+        // the type label is matched too, so a type narrows things down like in the picker
+        let workspaces = FoundWorkspaces {
+            items: [
+                ("/src/rust-book", "default"),
+                ("/src/twm", "rust"),
+                ("/src/nucleo", "rust"),
+            ]
+            .into_iter()
+            .map(|(path, workspace_type)| {
+                PickerItem::new(path.to_owned(), None).with_label(workspace_type)
+            })
+            .collect(),
+            truncated: false,
+        };
+        assert_eq!(find_unambiguous_workspace_match("rust", &workspaces), None);
+        assert_eq!(
+            find_unambiguous_workspace_match("default", &workspaces).as_deref(),
+            Some("/src/rust-book")
+        );
+    }
+
+    #[test]
+    fn test_nearest_git_root() {
+        let root = TestDir::new("nearest-git-root").with_git_repos(&["mono"]);
+        std::fs::create_dir_all(root.join("mono/crates/foo")).unwrap();
+        std::fs::create_dir_all(root.join("mono/vendor/sub")).unwrap();
+        std::fs::write(
+            root.join("mono/vendor/sub/.git"),
+            "gitdir: ../../.git/modules/sub",
+        )
+        .unwrap();
+        assert_eq!(
+            nearest_git_root(&root.join("mono/crates/foo"), &root),
+            Some(root.join("mono"))
+        );
+        // submodules are repositories of their own
+        assert_eq!(
+            nearest_git_root(&root.join("mono/vendor/sub"), &root),
+            Some(root.join("mono/vendor/sub"))
+        );
+        // never look above the search root
+        assert_eq!(
+            nearest_git_root(&root.join("mono/crates/foo"), &root.join("mono/crates")),
+            None
+        );
+    }
 }
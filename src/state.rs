@@ -0,0 +1,180 @@
+use anyhow::{Context, Result};
+use clap::crate_name;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// File under `$XDG_STATE_HOME/twm/` listing the sessions twm attached to most recently, most recent first.
+const LAST_SESSIONS_FILE_NAME: &str = "last_sessions";
+
+/// How many sessions are remembered. Two are enough to find the previous session from inside the current one.
+const LAST_SESSIONS_KEPT: usize = 2;
+
+/// Remembers that twm attached to `session_name`, coming from the session `previous_session` if it was run inside
+/// tmux.
+pub fn record_attached_session(session_name: &str, previous_session: Option<&str>) -> Result<()> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(crate_name!())?;
+    let path = xdg_dirs
+        .place_state_file(LAST_SESSIONS_FILE_NAME)
+        .context("Failed to create the twm state directory")?;
+    let contents = std::fs::read_to_string(&path).unwrap_or_default();
+    let mut sessions = parse_last_sessions(&contents);
+    // the session being left counts as used too, even if twm didn't attach to it
+    for name in previous_session.into_iter().chain([session_name]) {
+        sessions.retain(|session| *session != name);
+        sessions.insert(0, name);
+    }
+    sessions.truncate(LAST_SESSIONS_KEPT);
+    std::fs::write(&path, sessions.join("\n") + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Gets the session twm attached to most recently other than `current_session`, if any.
+pub fn last_session(current_session: Option<&str>) -> Option<String> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(crate_name!()).ok()?;
+    let path = xdg_dirs.find_state_file(LAST_SESSIONS_FILE_NAME)?;
+    let contents = std::fs::read_to_string(path).ok()?;
+    parse_last_sessions(&contents)
+        .into_iter()
+        .find(|session| Some(*session) != current_session)
+        .map(str::to_owned)
+}
+
+fn parse_last_sessions(contents: &str) -> Vec<&str> {
+    contents.lines().filter(|line| !line.is_empty()).collect()
+}
+
+/// File under `$XDG_STATE_HOME/twm/` with how often and how recently each workspace was opened.
+const HISTORY_FILE_NAME: &str = "history";
+
+/// How many workspaces the history keeps, dropping the ones opened least recently beyond that.
+const MAX_HISTORY_ENTRIES: usize = 1000;
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+const WEEK: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How often and how recently workspaces were opened, used to list the ones used most first with
+/// `sort_by: frecency`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct History {
+    workspaces: HashMap<String, HistoryEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct HistoryEntry {
+    /// How many times the workspace was opened.
+    count: u32,
+    /// When the workspace was last opened, in seconds since the Unix epoch.
+    last_opened: u64,
+}
+
+impl History {
+    /// Reads the history, which is empty if it was never written or can't be read.
+    pub fn load() -> Self {
+        xdg::BaseDirectories::with_prefix(crate_name!())
+            .ok()
+            .and_then(|xdg_dirs| xdg_dirs.find_state_file(HISTORY_FILE_NAME))
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// Records that the workspace at `path` was opened.
+    pub fn record_opened_workspace(path: &str) -> Result<()> {
+        let mut history = Self::load();
+        history.record(path, SystemTime::now());
+        let xdg_dirs = xdg::BaseDirectories::with_prefix(crate_name!())?;
+        let history_path = xdg_dirs
+            .place_state_file(HISTORY_FILE_NAME)
+            .context("Failed to create the twm state directory")?;
+        std::fs::write(&history_path, serde_json::to_string(&history)?)
+            .with_context(|| format!("Failed to write {}", history_path.display()))
+    }
+
+    fn record(&mut self, path: &str, now: SystemTime) {
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let entry = self
+            .workspaces
+            .entry(path.to_owned())
+            .or_insert(HistoryEntry {
+                count: 0,
+                last_opened: now,
+            });
+        entry.count += 1;
+        entry.last_opened = now;
+        if self.workspaces.len() > MAX_HISTORY_ENTRIES {
+            let mut last_opened: Vec<u64> =
+                self.workspaces.values().map(|e| e.last_opened).collect();
+            last_opened.sort_unstable_by(|a, b| b.cmp(a));
+            let cutoff = last_opened[MAX_HISTORY_ENTRIES - 1];
+            self.workspaces
+                .retain(|_, entry| entry.last_opened >= cutoff);
+        }
+    }
+
+    /// Scores how much the workspace at `path` was used, counting recent opens more than old ones. Workspaces that
+    /// were never opened score 0.
+    pub fn frecency(&self, path: &str, now: SystemTime) -> u32 {
+        let Some(entry) = self.workspaces.get(path) else {
+            return 0;
+        };
+        let age = now
+            .duration_since(UNIX_EPOCH + Duration::from_secs(entry.last_opened))
+            .unwrap_or_default();
+        // recent opens count 4 times, older ones 2, 1/2 and 1/4 times, scaled up so the score stays an integer
+        let weight = match age {
+            age if age < HOUR => 16,
+            age if age < DAY => 8,
+            age if age < WEEK => 2,
+            _ => 1,
+        };
+        entry.count.saturating_mul(weight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestDir;
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    fn test_last_session_skips_current_session() {
+        let dir = TestDir::new("last-session");
+        let orig_state = std::env::var_os("XDG_STATE_HOME");
+        std::env::set_var("XDG_STATE_HOME", &*dir);
+
+        assert_eq!(last_session(None), None);
+        record_attached_session("foo", None).unwrap();
+        assert_eq!(last_session(None).as_deref(), Some("foo"));
+        record_attached_session("bar", Some("baz")).unwrap();
+        assert_eq!(last_session(None).as_deref(), Some("bar"));
+        assert_eq!(last_session(Some("bar")).as_deref(), Some("baz"));
+        // switching back keeps the pair, just swapped
+        record_attached_session("baz", Some("bar")).unwrap();
+        assert_eq!(last_session(Some("baz")).as_deref(), Some("bar"));
+
+        match orig_state {
+            Some(state) => std::env::set_var("XDG_STATE_HOME", state),
+            None => std::env::remove_var("XDG_STATE_HOME"),
+        }
+    }
+
+    #[test]
+    fn test_frecency_favors_recent_workspaces() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let mut history = History::default();
+        for _ in 0..3 {
+            history.record("/old", start);
+        }
+        history.record("/new", start + WEEK);
+        let now = start + WEEK + Duration::from_secs(60);
+        assert_eq!(history.frecency("/old", now), 3);
+        assert_eq!(history.frecency("/new", now), 16);
+        assert_eq!(history.frecency("/never", now), 0);
+        // an hour later the new workspace is still ahead
+        assert!(history.frecency("/new", now + HOUR) > history.frecency("/old", now + HOUR));
+    }
+}
@@ -0,0 +1,59 @@
+//! Fixtures shared by the unit tests.
+
+use crate::config::{RawTwmGlobal, TwmGlobal};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Parses `yaml` the way the config file is parsed.
+pub fn config_from_yaml(yaml: &str) -> TwmGlobal {
+    TwmGlobal::try_from(RawTwmGlobal::from_str(yaml).unwrap()).unwrap()
+}
+
+/// Parses `yaml` with `root` as the only search path.
+pub fn config_searching(root: &Path, yaml: &str) -> TwmGlobal {
+    config_from_yaml(&format!("search_paths: [{}]\n{yaml}", root.display()))
+}
+
+/// An empty directory under the system temp directory, removed when dropped.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    /// Creates `twm-test-{name}`, clearing out whatever a previous run left there.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("twm-test-{name}"));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    /// Creates a git repository, which the default workspace definition matches, at each of `dirs`.
+    pub fn with_git_repos(self, dirs: &[&str]) -> Self {
+        for dir in dirs {
+            std::fs::create_dir_all(self.join(dir).join(".git")).unwrap();
+        }
+        self
+    }
+
+    /// Strips this directory from each of `paths`.
+    pub fn relative(&self, paths: impl IntoIterator<Item = String>) -> Vec<String> {
+        paths
+            .into_iter()
+            .map(|p| p.strip_prefix(self.to_str().unwrap()).unwrap().to_owned())
+            .collect()
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -1,12 +1,23 @@
 use crate::cli::Arguments;
-use crate::config::{TwmGlobal, TwmLayout};
-use crate::layout::{get_commands_from_layout, get_commands_from_layout_name, get_layout_names};
+use crate::config::{
+    CollisionStrategy, LocalLayout, SessionDisplay, SessionSort, SortBy, TwmGlobal,
+};
+use crate::layout::{
+    get_layout_by_name, get_layout_names, LayoutCommand, PaneCommand, ResolvedLayout, WindowCommand,
+};
+use crate::state::{record_attached_session, History};
 use crate::ui::Tui;
-use crate::ui::{Picker, PickerSelection};
+use crate::ui::{Picker, PickerItem, PickerSelection, SelectionAborted};
+use crate::workspace::{get_git_branch, WorkspaceDefinition};
 use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::HashSet;
 use std::os::unix::process::CommandExt;
+use std::os::unix::process::ExitStatusExt;
 use std::path::Path;
-use std::process::{Command, Output};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::time::{Duration, Instant};
 
 pub struct SessionName {
     name: String,
@@ -14,55 +25,371 @@ pub struct SessionName {
 
 impl SessionName {
     pub fn new(path: &str, path_components: usize) -> Self {
+        // a trailing slash would otherwise count as an empty last component
+        let path = match path.trim_end_matches('/') {
+            "" => path,
+            trimmed => trimmed,
+        };
         let mut path_parts: Vec<&str> = path.split('/').rev().take(path_components).collect();
         path_parts.reverse();
         let raw_name = path_parts.join("/");
         Self::from(raw_name.as_str())
     }
 
+    /// Appends `suffix` to the name, sanitizing it like the rest of the name.
+    pub fn with_suffix(self, suffix: &str) -> Self {
+        if suffix.is_empty() {
+            return self;
+        }
+        Self::from(format!("{}{suffix}", self.name).as_str())
+    }
+
     pub fn as_str(&self) -> &str {
         &self.name
     }
 }
 
 impl From<&str> for SessionName {
-    // take the last 2 parts of the path and join them back together, replacing any illegal characters with an underscore
+    /// Replaces the characters tmux doesn't allow in session names with an underscore.
+    ///
+    /// tmux itself replaces `.` and `:` since they separate the window and pane in a target, and escapes control
+    /// characters. Doing the same up front means the name twm looks sessions up by is the name tmux actually uses.
     fn from(s: &str) -> Self {
         let name: String = s
             .chars()
             .map(|c| match c {
-                // TODO: go through and find where tmux does the char replacement to get a full list of illegal characters. is it just this?
-                '.' => '_',
+                '.' | ':' => '_',
+                c if c.is_control() => '_',
                 _ => c,
             })
             .collect();
         SessionName { name }
     }
 }
-fn run_tmux_command(args: &[&str]) -> Result<Output> {
-    let output = Command::new("tmux")
-        .args(args)
-        .output()
-        .with_context(|| format!("Failed to run tmux command with args {args:?}"))?;
+pub const NAME_BASENAME_PLACEHOLDER: &str = "{basename}";
+pub const NAME_PARENT_PLACEHOLDER: &str = "{parent}";
+pub const NAME_TYPE_PLACEHOLDER: &str = "{type}";
+pub const NAME_GIT_BRANCH_PLACEHOLDER: &str = "{git_branch}";
+
+/// Expands the placeholders in a name given with `-n/--name` or `session_name_template` using the workspace being
+/// opened.
+///
+/// `{basename}` and `{parent}` are the last two components of the workspace path, `{type}` is the workspace type,
+/// or empty if the workspace didn't match any workspace definition. `{git_branch}` is the checked out branch, or
+/// empty if the workspace isn't a git repository or `HEAD` is detached.
+fn expand_name_placeholders(
+    name: &str,
+    workspace_path: &str,
+    workspace_type: Option<&str>,
+) -> String {
+    let path = Path::new(workspace_path.trim_end_matches('/'));
+    let component = |p: Option<&Path>| {
+        p.and_then(Path::file_name)
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    name.replace(NAME_BASENAME_PLACEHOLDER, &component(Some(path)))
+        .replace(NAME_PARENT_PLACEHOLDER, &component(path.parent()))
+        .replace(NAME_TYPE_PLACEHOLDER, workspace_type.unwrap_or_default())
+        .replace(
+            NAME_GIT_BRANCH_PLACEHOLDER,
+            &git_branch_placeholder(name, path),
+        )
+}
+
+/// Reads the branch for `{git_branch}` only if `name` uses it, since it means reading files in the workspace.
+fn git_branch_placeholder(name: &str, path: &Path) -> String {
+    if !name.contains(NAME_GIT_BRANCH_PLACEHOLDER) {
+        return String::new();
+    }
+    get_git_branch(path).unwrap_or_default()
+}
+
+/// Substrings of tmux errors meaning the server isn't running (yet), as opposed to the command itself failing.
+const SERVER_NOT_RUNNING_ERRORS: &[&str] = &["no server running", "error connecting to"];
+
+/// How many times to retry a command that failed because the tmux server wasn't up yet.
+const SERVER_START_RETRIES: u32 = 3;
+
+const SERVER_START_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Quotes `arg` for a POSIX shell if it contains anything the shell would interpret.
+pub fn shell_quote(arg: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        return arg.to_owned();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Formats a tmux invocation so it can be pasted into a shell.
+fn format_tmux_command(args: &[&str]) -> String {
+    std::iter::once("tmux".to_owned())
+        .chain(args.iter().map(|arg| shell_quote(arg)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn spawn_tmux_command(args: &[&str]) -> std::io::Result<Output> {
+    Command::new("tmux").args(args).output()
+}
+
+/// Runs a tmux command that changes anything, or only prints it with `--dry-run`. Commands that only read state are
+/// run with `spawn_tmux_command` even in a dry run, so twm decides what to do the same way.
+fn spawn_tmux_change(args: &[&str], dry_run: bool) -> std::io::Result<Output> {
+    if !dry_run {
+        return spawn_tmux_command(args);
+    }
+    println!("{}", format_tmux_command(args));
+    // commands printing what they created get their format back, so later commands still have a target to show
+    let stdout = match args.contains(&"-P") {
+        true => args.iter().skip_while(|arg| **arg != "-F").nth(1),
+        false => None,
+    };
+    Ok(Output {
+        status: ExitStatus::from_raw(0),
+        stdout: stdout.map(|s| s.as_bytes().to_vec()).unwrap_or_default(),
+        stderr: Vec::new(),
+    })
+}
+
+/// Error for a tmux command that couldn't be run or exited unsuccessfully, with a message saying which and why.
+#[derive(Debug)]
+pub struct TmuxCommandFailed(pub String);
+
+impl std::fmt::Display for TmuxCommandFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TmuxCommandFailed {}
+
+fn check_tmux_output(args: &[&str], output: std::io::Result<Output>) -> Result<Output> {
+    let output = output.with_context(|| {
+        TmuxCommandFailed(format!("Failed to run tmux command with args {args:?}"))
+    })?;
     if !output.status.success() {
-        bail!(
+        return Err(TmuxCommandFailed(format!(
             "tmux command with args {:?} failed because: {}",
             args,
             String::from_utf8_lossy(&output.stderr)
-        );
+        ))
+        .into());
     }
     Ok(output)
 }
 
+fn run_tmux_command(args: &[&str]) -> Result<Output> {
+    check_tmux_output(args, spawn_tmux_command(args))
+}
+
+fn run_tmux_change(args: &[&str], dry_run: bool) -> Result<Output> {
+    check_tmux_output(args, spawn_tmux_change(args, dry_run))
+}
+
+fn is_server_not_running_error(output: &Output) -> bool {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    SERVER_NOT_RUNNING_ERRORS.iter().any(|e| stderr.contains(e))
+}
+
+/// Runs a tmux command, retrying with an increasing backoff if it failed because the tmux server wasn't running.
+///
+/// Right after boot the first command can race the server starting up, so we explicitly start the server before
+/// each retry. Any other failure is returned right away.
+fn run_tmux_command_with_server_retry(
+    args: &[&str],
+    run: &mut impl FnMut(&[&str]) -> std::io::Result<Output>,
+    backoff: Duration,
+) -> Result<Output> {
+    let mut retries = 0;
+    loop {
+        let output = run(args);
+        match &output {
+            Ok(o) if !o.status.success() && is_server_not_running_error(o) => {
+                if retries == SERVER_START_RETRIES {
+                    return check_tmux_output(args, output);
+                }
+            }
+            _ => return check_tmux_output(args, output),
+        }
+        retries += 1;
+        // if this fails the retry will fail too, which gives a more useful error anyways
+        let _ = run(&["start-server"]);
+        std::thread::sleep(backoff * retries);
+    }
+}
+
+/// An existing tmux session, along with the info needed to sort the session list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TmuxSession {
+    name: String,
+    /// Unix timestamp of the last activity in the session.
+    activity: u64,
+    /// Unix timestamp of when the session was created.
+    created: u64,
+    /// Name of the session group the session is in, or empty if it isn't in a group.
+    group: String,
+}
+
+/// Format for `tmux list-sessions`, with the name last so it's parsed correctly whatever it contains.
+///
+/// Fields are separated by `:`, which tmux doesn't allow in session names, and so neither in group names. Tabs would
+/// be more natural, but tmux prints them as `_`.
+const SESSION_LIST_FORMAT: &str =
+    "#{session_activity}:#{session_created}:#{session_group}:#{session_name}";
+
+fn list_tmux_sessions() -> Result<Vec<TmuxSession>> {
+    let args = ["list-sessions", "-F", SESSION_LIST_FORMAT];
+    parse_tmux_sessions(&args, spawn_tmux_command(&args))
+}
+
 pub fn get_tmux_sessions() -> Result<Vec<String>> {
-    let output = run_tmux_command(&["list-sessions", "-F", "#{session_name}"])?;
+    Ok(list_tmux_sessions()?
+        .into_iter()
+        .map(|session| session.name)
+        .collect())
+}
+
+/// A tmux session, along with the workspace it was opened for if twm created it.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct SessionInfo {
+    pub name: String,
+    /// Whether the session was created by twm, which is the case if its `TWM` environment variable is set.
+    pub twm: bool,
+    pub root: Option<String>,
+    pub workspace_type: Option<String>,
+}
+
+impl SessionInfo {
+    /// Builds the info for the session `name` from the output of `tmux showenv` for it.
+    fn from_showenv(name: String, showenv_output: &str, config: &TwmGlobal) -> Self {
+        let marker = format!("{}=1", config.env_prefix);
+        let type_prefix = format!("{}=", config.env_var("TYPE"));
+        Self {
+            twm: showenv_output.lines().any(|line| line == marker),
+            root: parse_twm_root(showenv_output, config),
+            workspace_type: showenv_output
+                .lines()
+                .find_map(|line| line.strip_prefix(&type_prefix))
+                .filter(|workspace_type| !workspace_type.is_empty())
+                .map(String::from),
+            name,
+        }
+    }
+}
+
+/// Lists all tmux sessions in the order they're shown in the picker, telling apart the ones created by twm.
+pub fn list_twm_sessions(config: &TwmGlobal) -> Result<Vec<SessionInfo>> {
+    let mut sessions = list_tmux_sessions()?;
+    sort_tmux_sessions(&mut sessions, config.session_sort);
+    sessions
+        .into_iter()
+        .map(|session| {
+            let output = run_tmux_command(&["showenv", "-t", &format!("={}", session.name)])?;
+            Ok(SessionInfo::from_showenv(
+                session.name,
+                &String::from_utf8_lossy(&output.stdout),
+                config,
+            ))
+        })
+        .collect()
+}
+
+/// Parses the output of `tmux list-sessions`, treating a server that isn't running as having no sessions.
+fn parse_tmux_sessions(args: &[&str], output: std::io::Result<Output>) -> Result<Vec<TmuxSession>> {
+    if let Ok(o) = &output {
+        // the server exits when its last session is closed, so this just means there are no sessions
+        if !o.status.success() && is_server_not_running_error(o) {
+            return Ok(Vec::new());
+        }
+    }
+    let output = check_tmux_output(args, output)?;
     let out_str = String::from_utf8_lossy(&output.stdout);
-    let sessions: Vec<String> = out_str.lines().map(|s| s.to_string()).collect();
-    Ok(sessions)
+    out_str
+        .lines()
+        .map(|line| {
+            let mut fields = line.splitn(4, ':');
+            let (Some(activity), Some(created), Some(group), Some(name)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                bail!("Unexpected line in tmux session list: `{line}`");
+            };
+            Ok(TmuxSession {
+                name: name.to_owned(),
+                activity: activity.parse().unwrap_or_default(),
+                created: created.parse().unwrap_or_default(),
+                group: group.to_owned(),
+            })
+        })
+        .collect()
 }
 
-fn create_tmux_session(name: &SessionName, workspace_type: Option<&str>, path: &str) -> Result<()> {
-    run_tmux_command(&[
+/// Sorts sessions in the order they should be listed in the picker, with the first session closest to the prompt.
+fn sort_tmux_sessions(sessions: &mut [TmuxSession], sort: SessionSort) {
+    match sort {
+        SessionSort::Name => sessions.sort_by(|a, b| a.name.cmp(&b.name)),
+        SessionSort::Activity => sessions.sort_by_key(|s| Reverse(s.activity)),
+        SessionSort::Created => sessions.sort_by_key(|s| Reverse(s.created)),
+    }
+}
+
+/// Existing tmux sessions, displayed in the picker as configured and selected by their names.
+pub struct SessionList {
+    items: Vec<PickerItem>,
+}
+
+impl SessionList {
+    pub fn load(config: &TwmGlobal) -> Result<Self> {
+        let mut sessions = list_tmux_sessions()?;
+        sort_tmux_sessions(&mut sessions, config.session_sort);
+        let mut items = Vec::with_capacity(sessions.len());
+        let mut displayed = HashSet::with_capacity(sessions.len());
+        for TmuxSession { name, .. } in sessions {
+            let mut display_name = match config.session_display {
+                SessionDisplay::Name => name.clone(),
+                // sessions not created by twm don't have a TWM_ROOT, so they're displayed by name
+                SessionDisplay::Path => {
+                    get_twm_root_for_session(&SessionName::from(name.as_str()), config)
+                        .unwrap_or_else(|_| name.clone())
+                }
+            };
+            // multiple sessions can have the same root, so disambiguate those with the session name
+            if !displayed.insert(display_name.clone()) {
+                display_name = format!("{display_name} [{name}]");
+            }
+            items.push(PickerItem::with_value(display_name, None, name));
+        }
+        Ok(Self { items })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The sessions as they should be listed in the picker, with the session names as values.
+    pub fn into_items(self) -> Vec<PickerItem> {
+        self.items
+    }
+}
+
+fn create_tmux_session(
+    name: &SessionName,
+    workspace_type: Option<&str>,
+    path: &str,
+    workspace_definition: Option<&WorkspaceDefinition>,
+    window_name: Option<&str>,
+    config: &TwmGlobal,
+    env: &[String],
+) -> Result<()> {
+    let tmux_config_file = config.tmux_config_file.as_deref();
+    // tmux only reads its config file when starting the server, which can happen here or when retrying
+    let mut spawn = |args: &[&str]| match tmux_config_file {
+        Some(file) => spawn_tmux_change(&[&["-f", file], args].concat(), config.dry_run),
+        None => spawn_tmux_change(args, config.dry_run),
+    };
+    let twm_env = twm_session_env(path, workspace_type, &name.name, config);
+    let mut args = vec![
         "new-session",
         "-ds",
         &name.name,
@@ -70,61 +397,357 @@ fn create_tmux_session(name: &SessionName, workspace_type: Option<&str>, path: &
         &name.name,
         "-c",
         path,
-        // set TWM env vars for the session
-        "-e",
-        "TWM=1",
-        "-e",
-        &format!("TWM_ROOT={}", path),
-        "-e",
-        &format!("TWM_TYPE={}", workspace_type.unwrap_or("")),
-        "-e",
-        &format!("TWM_NAME={}", name.name),
-    ])
+    ];
+    // set TWM env vars for the session
+    for var in twm_env.iter().chain(env) {
+        args.extend(["-e", var]);
+    }
+    run_tmux_command_with_server_retry(&args, &mut spawn, SERVER_START_BACKOFF).with_context(
+        || {
+            format!(
+                "Failed to create tmux session with name {} at path {path}",
+                &name.name
+            )
+        },
+    )?;
+    // tmux doesn't allow a command or window name together with `-t` in `new-session`, so they're set right after
+    // instead. the shell never gets to read any input, so nothing can race its prompt
+    if let Some(window_name) = window_name {
+        run_tmux_change(
+            &["rename-window", "-t", &name.name, window_name],
+            config.dry_run,
+        )
+        .with_context(|| {
+            format!(
+                "Failed to name the first window of tmux session {} `{window_name}`",
+                &name.name
+            )
+        })?;
+    }
+    if let Some(command) = workspace_definition.and_then(|d| d.initial_command.as_deref()) {
+        run_tmux_change(
+            &["respawn-pane", "-k", "-t", &name.name, "-c", path, command],
+            config.dry_run,
+        )
+        .with_context(|| {
+            format!(
+                "Failed to start initial command `{command}` in tmux session {}",
+                &name.name
+            )
+        })?;
+    }
+    Ok(())
+}
+
+fn create_tmux_session_in_group(
+    group_session_name: &str,
+    name: &SessionName,
+    dry_run: bool,
+) -> Result<()> {
+    run_tmux_change(
+        &["new-session", "-ds", &name.name, "-t", group_session_name],
+        dry_run,
+    )
     .with_context(|| {
         format!(
-            "Failed to create tmux session with name {} at path {path}",
-            &name.name
+            "Failed to create tmux session {} in group {}",
+            &name.name, group_session_name
         )
     })?;
     Ok(())
 }
 
-fn create_tmux_session_in_group(group_session_name: &str, name: &SessionName) -> Result<()> {
-    run_tmux_command(&["new-session", "-ds", &name.name, "-t", group_session_name]).with_context(
-        || {
-            format!(
-                "Failed to create tmux session {} in group {}",
-                &name.name, group_session_name
-            )
-        },
-    )?;
+/// Gets the name of the session twm is being run in, if it's being run inside tmux.
+pub fn current_tmux_session() -> Option<String> {
+    std::env::var_os("TMUX")?;
+    let output = run_tmux_command(&["display-message", "-p", "#{session_name}"]).ok()?;
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_owned(),
+    )
+}
+
+/// Picks the session after `current` among the sessions in `group`, wrapping around to the first one.
+///
+/// If `current` isn't in the group, the first session in the group is picked.
+fn next_session_in_group<'a>(
+    sessions: &'a [TmuxSession],
+    group: &str,
+    current: Option<&str>,
+) -> Option<&'a str> {
+    let members: Vec<&str> = sessions
+        .iter()
+        .filter(|s| s.group == group)
+        .map(|s| s.name.as_str())
+        .collect();
+    let next = members
+        .iter()
+        .position(|name| Some(*name) == current)
+        .map_or(0, |i| (i + 1) % members.len());
+    members.get(next).copied()
+}
+
+/// Switches to the next session in the session group `group`, going through the sessions in order of their names.
+pub fn cycle_session_group(group: &str, config: &TwmGlobal) -> Result<()> {
+    let mut sessions = list_tmux_sessions()?;
+    sort_tmux_sessions(&mut sessions, SessionSort::Name);
+    let current = current_tmux_session();
+    let Some(next) = next_session_in_group(&sessions, group, current.as_deref()) else {
+        bail!("No session group named `{group}` exists");
+    };
+    attach_to_tmux_session(next, config)
+}
+
+/// Detaches every client attached to the session `session_name` except the one twm is being run in, if any.
+fn detach_other_clients(session_name: &str, dry_run: bool) -> Result<()> {
+    let current_client = std::env::var_os("TMUX")
+        .and_then(|_| run_tmux_command(&["display-message", "-p", "#{client_name}"]).ok())
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .trim_end()
+                .to_owned()
+        });
+    let output = run_tmux_command(&[
+        "list-clients",
+        "-t",
+        &format!("={session_name}"),
+        "-F",
+        "#{client_name}",
+    ])?;
+    for client in String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|client| Some(*client) != current_client.as_deref())
+    {
+        run_tmux_change(&["detach-client", "-t", client], dry_run)
+            .with_context(|| format!("Failed to detach client {client} from {session_name}"))?;
+    }
     Ok(())
 }
 
-fn attach_to_tmux_session_inside_tmux(session_name: &str) -> Result<()> {
-    run_tmux_command(&["switch", "-t", session_name]).with_context(|| {
+fn attach_to_tmux_session_inside_tmux(
+    session_name: &str,
+    detach_others: bool,
+    dry_run: bool,
+) -> Result<()> {
+    // `switch` has no `-d` like `attach` does, so the other clients are detached separately
+    if detach_others {
+        detach_other_clients(session_name, dry_run)?;
+    }
+    if current_tmux_session().as_deref() == Some(session_name) {
+        eprintln!("Already attached to tmux session {session_name}");
+        return Ok(());
+    }
+    run_tmux_change(&["switch", "-t", session_name], dry_run).with_context(|| {
         format!("Failed to attach to tmux session with name {session_name} inside tmux")
     })?;
     Ok(())
 }
 
-pub fn attach_to_tmux_session(session_name: &str) -> Result<()> {
+/// Kills the sessions with the given names, running the `pre_kill` and `post_kill` hooks around each. The session twm
+/// is running in, if any, is killed last so the others are gone before tmux moves its client elsewhere.
+///
+/// A session whose `pre_kill` hook fails isn't killed unless `force` is set. Failing to kill one session doesn't stop
+/// the rest from being killed; the returned error names every session that wasn't.
+pub fn kill_tmux_sessions(session_names: &[String], force: bool, config: &TwmGlobal) -> Result<()> {
+    let current_session = current_tmux_session();
+    let (current, others): (Vec<&String>, Vec<&String>) = session_names
+        .iter()
+        .partition(|name| Some(name.as_str()) == current_session.as_deref());
+    kill_each(others.into_iter().chain(current), |session_name| {
+        let target = format!("={session_name}");
+        // the hooks get the session's environment, which is gone once it's killed
+        let environment = match config.pre_kill.is_some() || config.post_kill.is_some() {
+            true => {
+                let output = run_tmux_command(&["showenv", "-t", &target])?;
+                String::from_utf8_lossy(&output.stdout).into_owned()
+            }
+            false => String::new(),
+        };
+        kill_session_with_hooks(session_name, &environment, force, config, || {
+            run_tmux_change(&["kill-session", "-t", &target], config.dry_run)
+                .with_context(|| format!("Failed to kill tmux session {session_name}"))?;
+            Ok(())
+        })?;
+        if !config.dry_run {
+            println!("Killed session {session_name}");
+        }
+        Ok(())
+    })
+}
+
+/// Calls `kill` for each of `session_names`, carrying on past the ones that fail, and returns an error naming each
+/// of those.
+fn kill_each<'a>(
+    session_names: impl IntoIterator<Item = &'a String>,
+    mut kill: impl FnMut(&str) -> Result<()>,
+) -> Result<()> {
+    let failures: Vec<String> = session_names
+        .into_iter()
+        .filter_map(|session_name| {
+            kill(session_name)
+                .err()
+                .map(|error| format!("{session_name}: {error:#}"))
+        })
+        .collect();
+    if !failures.is_empty() {
+        bail!(
+            "Failed to kill {} of the selected sessions:\n{}",
+            failures.len(),
+            failures.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Runs the `pre_kill` hook, `kill` and the `post_kill` hook for the session `session_name`, whose environment is in
+/// `showenv_output`. `kill` isn't called if the `pre_kill` hook fails, unless `force` is set.
+fn kill_session_with_hooks(
+    session_name: &str,
+    showenv_output: &str,
+    force: bool,
+    config: &TwmGlobal,
+    kill: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    if let Some(hook) = &config.pre_kill {
+        let status = run_kill_hook(hook, session_name, showenv_output, config)?;
+        if !status.success() && !force {
+            bail!(
+                "The pre_kill hook exited with {status} for session {session_name}, so it wasn't killed. Use --force to kill it anyway."
+            );
+        }
+    }
+    kill()?;
+    if let Some(hook) = &config.post_kill {
+        let status = run_kill_hook(hook, session_name, showenv_output, config)?;
+        if !status.success() {
+            bail!("The post_kill hook exited with {status} for session {session_name}.");
+        }
+    }
+    Ok(())
+}
+
+/// Runs a `pre_kill` or `post_kill` hook with `$SHELL -c`, in the environment of the session `session_name` read
+/// from `showenv_output`.
+fn run_kill_hook(
+    hook: &str,
+    session_name: &str,
+    showenv_output: &str,
+    config: &TwmGlobal,
+) -> Result<ExitStatus> {
+    if config.dry_run {
+        println!("$SHELL -c {}", shell_quote(hook));
+        return Ok(ExitStatus::from_raw(0));
+    }
+    let shell = std::env::var("SHELL").unwrap_or("sh".to_string());
+    let mut command = Command::new(shell);
+    command.args(["-c", hook]);
+    for line in showenv_output.lines() {
+        // tmux lists variables removed from the session as `-NAME`
+        if let Some(name) = line.strip_prefix('-') {
+            command.env_remove(name);
+        } else if let Some((name, value)) = line.split_once('=') {
+            command.env(name, value);
+        }
+    }
+    command.env(config.env_var("SESSION"), session_name);
+    if let Some(root) =
+        parse_twm_root(showenv_output, config).filter(|root| Path::new(root).is_dir())
+    {
+        command.current_dir(root);
+    }
+    command
+        .status()
+        .with_context(|| format!("Failed to run hook `{hook}` for session {session_name}"))
+}
+
+pub fn attach_to_tmux_session(session_name: &str, config: &TwmGlobal) -> Result<()> {
+    attach_to_session(session_name, config.detach_others, config)
+}
+
+/// Attaches to a session twm just created, which no other client can be attached to yet.
+fn attach_to_new_tmux_session(session_name: &str, config: &TwmGlobal) -> Result<()> {
+    attach_to_session(session_name, false, config)
+}
+
+fn attach_to_session(session_name: &str, detach_others: bool, config: &TwmGlobal) -> Result<()> {
+    // remembering the session for `--last` is best-effort and shouldn't keep anyone from attaching
+    if !config.dry_run {
+        let _ = record_attached_session(session_name, current_tmux_session().as_deref());
+    }
     if std::env::var("TMUX").is_ok() {
-        attach_to_tmux_session_inside_tmux(session_name)
+        attach_to_tmux_session_inside_tmux(session_name, detach_others, config.dry_run)
     } else {
-        attach_to_tmux_session_outside_tmux(session_name)
+        attach_to_tmux_session_outside_tmux(session_name, detach_others, config)
+    }
+}
+
+/// Placeholder in `attach_command_template` that is replaced with the session name.
+pub const ATTACH_COMMAND_SESSION_PLACEHOLDER: &str = "{session}";
+
+/// Checks that an attach command template can actually be used to attach to a session.
+pub fn validate_attach_command_template(template: &str) -> Result<()> {
+    if template.trim().is_empty() {
+        bail!("`attach_command_template` can't be empty.");
+    }
+    if !template.contains(ATTACH_COMMAND_SESSION_PLACEHOLDER) {
+        bail!(
+            "`attach_command_template` must contain the `{ATTACH_COMMAND_SESSION_PLACEHOLDER}` placeholder, got `{template}`."
+        );
+    }
+    Ok(())
+}
+
+/// The shell command attaching to `session_name` from outside tmux. Only the default command can detach the other
+/// clients itself.
+fn outside_tmux_attach_command(
+    session_name: &str,
+    detach_others: bool,
+    config: &TwmGlobal,
+) -> String {
+    match &config.attach_command_template {
+        Some(template) => template.replace(ATTACH_COMMAND_SESSION_PLACEHOLDER, session_name),
+        None if detach_others => format!("tmux attach -d -t {}", session_name),
+        None => format!("tmux attach -t {}", session_name),
     }
 }
 
-fn attach_to_tmux_session_outside_tmux(session_name: &str) -> Result<()> {
+fn attach_to_tmux_session_outside_tmux(
+    session_name: &str,
+    detach_others: bool,
+    config: &TwmGlobal,
+) -> Result<()> {
+    if let Some(template) = &config.attach_command_template {
+        // we're about to exec, so make sure the command makes sense before replacing ourselves with it
+        validate_attach_command_template(template)?;
+        // there's no telling where a `-d` would go in a custom command
+        if detach_others {
+            detach_other_clients(session_name, config.dry_run)?;
+        }
+    }
+    let attach_command = outside_tmux_attach_command(session_name, detach_others, config);
+    if config.dry_run {
+        println!("{attach_command}");
+        return Ok(());
+    }
     let shell = std::env::var("SHELL").unwrap_or("sh".to_string());
-    let exec_error = Command::new(shell)
-        .args(["-c", format!("tmux attach -t {}", session_name).as_str()])
+    let exec_error = Command::new(&shell)
+        .args(["-c", attach_command.as_str()])
+        .exec();
+    // a custom attach command needs the shell, but the default one can be run directly, which also helps in
+    // minimal containers where `$SHELL` points to a shell that isn't installed
+    if config.attach_command_template.is_some() {
+        anyhow::bail!(
+            "Failed to attach to tmux session with name {session_name} outside tmux, couldn't run `{shell}`: {exec_error}"
+        );
+    }
+    let tmux_exec_error = Command::new("tmux")
+        .arg("attach")
+        .args(detach_others.then_some("-d"))
+        .args(["-t", session_name])
         .exec();
     anyhow::bail!(
-        "Failed to attach to tmux session with name {repo_name} outside tmux: {exec_error}",
-        repo_name = session_name,
-        exec_error = exec_error
+        "Failed to attach to tmux session with name {session_name} outside tmux, couldn't run `{shell}` ({exec_error}) or `tmux` ({tmux_exec_error})"
     );
 }
 
@@ -135,97 +758,427 @@ fn tmux_has_session(session_name: &SessionName) -> bool {
     }
 }
 
-fn get_twm_root_for_session(session_name: &SessionName) -> Result<String> {
+/// Checks whether a session with exactly this name exists.
+///
+/// Unlike `tmux_has_session`, the name is used as-is and tmux's prefix matching of session names is disabled.
+pub fn tmux_has_session_exact(session_name: &str) -> bool {
+    run_tmux_command(&["has-session", "-t", &format!("={session_name}")]).is_ok()
+}
+
+/// Builds the `KEY=VALUE` environment variables that mark a session or window as opened by twm for a workspace.
+fn twm_session_env(
+    workspace_path: &str,
+    workspace_type: Option<&str>,
+    session_name: &str,
+    config: &TwmGlobal,
+) -> [String; 4] {
+    [
+        format!("{}=1", config.env_prefix),
+        format!("{}={workspace_path}", config.env_var("ROOT")),
+        format!(
+            "{}={}",
+            config.env_var("TYPE"),
+            workspace_type.unwrap_or("")
+        ),
+        format!("{}={session_name}", config.env_var("NAME")),
+    ]
+}
+
+/// Builds the `KEY=VALUE` environment variables describing where a workspace is being opened from, for layout
+/// commands and scripts to adapt to.
+fn twm_context_env(
+    workspace_path: &str,
+    layout: Option<&ResolvedLayout>,
+    config: &TwmGlobal,
+) -> [String; 3] {
+    let basename = Path::new(workspace_path.trim_end_matches('/'))
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    [
+        format!(
+            "{}={}",
+            config.env_var("PREV_SESSION"),
+            current_tmux_session().unwrap_or_default()
+        ),
+        format!("{}={basename}", config.env_var("WORKSPACE_BASENAME")),
+        format!(
+            "{}={}",
+            config.env_var("LAYOUT_NAME"),
+            layout.map_or("", |layout| layout.name.as_str())
+        ),
+    ]
+}
+
+/// Gets the workspace path of the twm session twm is being run in, if any.
+pub fn current_twm_root(config: &TwmGlobal) -> Option<String> {
+    std::env::var_os("TMUX")?;
+    std::env::var(config.env_var("ROOT"))
+        .ok()
+        .filter(|root| !root.is_empty())
+}
+
+/// Finds the workspace path in the output of `tmux showenv`.
+fn parse_twm_root(showenv_output: &str, config: &TwmGlobal) -> Option<String> {
+    let prefix = format!("{}=", config.env_var("ROOT"));
+    showenv_output
+        .lines()
+        .find_map(|line| line.strip_prefix(&prefix))
+        .map(String::from)
+}
+
+fn get_twm_root_for_session(session_name: &SessionName, config: &TwmGlobal) -> Result<String> {
     let output = run_tmux_command(&["showenv", "-t", &session_name.name])?;
     let out_str = String::from_utf8_lossy(&output.stdout);
-    let twm_root = out_str
-        .lines()
-        .find(|line| line.starts_with("TWM_ROOT="))
-        .with_context(|| {
-            format!(
-                "Failed to find TWM_ROOT variable in tmux session {}",
-                session_name.name
-            )
-        })?
-        .strip_prefix("TWM_ROOT=")
-        .with_context(|| {
-            format!(
-                "Failed to strip TWM_ROOT= prefix from tmux session {}",
-                session_name.name
-            )
-        })?
-        .to_string();
+    parse_twm_root(&out_str, config).with_context(|| {
+        format!(
+            "Failed to find {} variable in tmux session {}",
+            config.env_var("ROOT"),
+            session_name.name
+        )
+    })
+}
 
-    Ok(twm_root)
+/// Sends layout commands to the window `target`, waiting up to `timeout` for each one to finish before sending the
+/// next if set. Returns the target of the window the last commands went to, which differs from `target` once the
+/// layout opened windows of its own.
+///
+/// Commands without a pane go to the window's active pane, the others to the pane with their index in the window.
+fn send_commands_to_session(
+    target: &str,
+    commands: &[LayoutCommand],
+    timeout: Option<Duration>,
+    dry_run: bool,
+) -> Result<String> {
+    let mut window_target = target.to_owned();
+    let mut opened_window = false;
+    for (index, layout_command) in commands.iter().enumerate() {
+        let command = match layout_command {
+            LayoutCommand::Window(window_command) => {
+                run_window_command(&mut window_target, window_command, opened_window, dry_run)?;
+                opened_window |= matches!(window_command, WindowCommand::Open(_));
+                continue;
+            }
+            LayoutCommand::Active(command) | LayoutCommand::Pane(PaneCommand { command, .. }) => {
+                command
+            }
+        };
+        let pane_target = match layout_command.pane() {
+            Some(pane) => pane_target(&window_target, pane),
+            None => window_target.clone(),
+        };
+        // nothing depends on the last command, and `;` can't follow a `&`
+        let should_wait = index + 1 < commands.len() && !is_backgrounded(command);
+        match timeout {
+            Some(timeout) if should_wait => {
+                let channel = format!("twm-{}-{index}", std::process::id());
+                let command = format!("{command}; tmux wait-for -S {channel}");
+                run_tmux_change(&["send-keys", "-t", &pane_target, &command, "C-m"], dry_run)?;
+                wait_for_channel(&channel, timeout, dry_run)?;
+            }
+            _ => {
+                run_tmux_change(&["send-keys", "-t", &pane_target, command, "C-m"], dry_run)?;
+            }
+        }
+    }
+    Ok(window_target)
 }
 
-fn send_commands_to_session(session_name: &str, commands: &[&str]) -> Result<()> {
-    for command in commands {
-        run_tmux_command(&["send-keys", "-t", session_name, command, "C-m"])?;
+/// Runs a step building one of a layout's windows in the window `window_target`, pointing it at the new window when
+/// one is opened.
+///
+/// The first window of a layout is the window the layout is applied to, so it's only renamed.
+fn run_window_command(
+    window_target: &mut String,
+    window_command: &WindowCommand,
+    opened_window: bool,
+    dry_run: bool,
+) -> Result<()> {
+    // new windows and panes start in the directory of the pane they're opened from, i.e. the workspace
+    let current_path = "#{pane_current_path}";
+    match window_command {
+        WindowCommand::Open(name) if !opened_window => {
+            if let Some(name) = name {
+                run_tmux_change(&["rename-window", "-t", window_target, name], dry_run)?;
+            }
+        }
+        WindowCommand::Open(name) => {
+            let mut args = vec![
+                "new-window",
+                "-a",
+                "-P",
+                "-F",
+                "#{window_id}",
+                "-t",
+                window_target,
+                "-c",
+                current_path,
+            ];
+            args.extend(name.iter().flat_map(|name| ["-n", name]));
+            let output = run_tmux_change(&args, dry_run)?;
+            *window_target = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+        }
+        WindowCommand::SplitPane => {
+            run_tmux_change(
+                &["split-window", "-t", window_target, "-c", current_path],
+                dry_run,
+            )?;
+        }
+        WindowCommand::SelectLayout(layout) => {
+            run_tmux_change(&["select-layout", "-t", window_target, layout], dry_run)?;
+        }
     }
     Ok(())
 }
 
-fn get_layout_selection(twm_config: &TwmGlobal, tui: &mut Tui) -> Result<String> {
-    Ok(
-        match Picker::new(
-            &get_layout_names(&twm_config.layouts),
-            "Select a layout: ".into(),
-        )
-        .get_selection(tui)?
-        {
-            PickerSelection::None => bail!("No layout selected"),
-            PickerSelection::Selection(s) => s,
-            PickerSelection::ModifiedSelection(s) => s,
-        },
+/// How long to wait for the pane to zoom to be created by the layout's commands.
+const ZOOM_PANE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Sends the layout's commands to the window `target`, then zooms its `zoom_pane` if set.
+fn apply_layout_to_session(
+    target: &str,
+    layout: &ResolvedLayout,
+    config: &TwmGlobal,
+) -> Result<()> {
+    let window_target = send_commands_to_session(
+        target,
+        &layout.commands,
+        config.layout_command_timeout,
+        config.dry_run,
+    )?;
+    if let Some(pane) = layout.zoom_pane {
+        zoom_pane(&pane_target(&window_target, pane), config.dry_run)?;
+    }
+    Ok(())
+}
+
+/// Zooms the pane `pane_target` once it exists, doing nothing if it isn't created within `ZOOM_PANE_TIMEOUT`.
+fn zoom_pane(pane_target: &str, dry_run: bool) -> Result<()> {
+    if dry_run {
+        // the pane was never created, so there's nothing to wait for
+        run_tmux_change(&["resize-pane", "-Z", "-t", pane_target], dry_run)?;
+        return Ok(());
+    }
+    let start = Instant::now();
+    // commands are typed into the shell, so the panes they create can show up a little after they were sent
+    let zoomed = loop {
+        match run_tmux_command(&[
+            "display-message",
+            "-p",
+            "-t",
+            pane_target,
+            "#{window_zoomed_flag}",
+        ]) {
+            Ok(output) => break String::from_utf8_lossy(&output.stdout).trim() == "1",
+            Err(_) if start.elapsed() < ZOOM_PANE_TIMEOUT => {
+                std::thread::sleep(Duration::from_millis(20))
+            }
+            Err(_) => {
+                eprintln!("Not zooming pane {pane_target} since it doesn't exist");
+                return Ok(());
+            }
+        }
+    };
+    // `resize-pane -Z` toggles the zoom, so a window that's already zoomed would be unzoomed
+    if !zoomed {
+        run_tmux_change(&["resize-pane", "-Z", "-t", pane_target], dry_run)?;
+    }
+    Ok(())
+}
+
+/// Builds the target for pane `pane` of the window `window_target`, e.g. `=foo:.1` for `=foo:`.
+fn pane_target(window_target: &str, pane: usize) -> String {
+    format!("{window_target}.{pane}")
+}
+
+fn is_backgrounded(command: &str) -> bool {
+    let command = command.trim_end();
+    command.ends_with('&') && !command.ends_with("&&")
+}
+
+/// Waits until `channel` is signalled with `tmux wait-for -S`, giving up after `timeout`.
+///
+/// tmux remembers signals sent before anyone waits on the channel, so the signal can't be missed.
+fn wait_for_channel(channel: &str, timeout: Duration, dry_run: bool) -> Result<()> {
+    // nothing was sent that could signal the channel
+    if dry_run {
+        return Ok(());
+    }
+    let mut child = Command::new("tmux")
+        .args(["wait-for", channel])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to wait for tmux channel {channel}"))?;
+    let start = Instant::now();
+    while child.try_wait()?.is_none() {
+        if start.elapsed() >= timeout {
+            // the command is still running, e.g. because it's interactive, so carry on with the rest of the layout
+            child.kill()?;
+            child.wait()?;
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    Ok(())
+}
+
+/// Applies `layout`, if any, to the window `target` and runs the `-c/--command` command. Returns the target of the
+/// window the command was run in.
+///
+/// The command is sent as the last of the layout's commands, so it waits for them like they wait for each other. With
+/// `--command-window` it's run in a new window opened after `target` instead.
+fn apply_layout_and_command(
+    target: &str,
+    workspace_path: &str,
+    layout: Option<ResolvedLayout>,
+    config: &TwmGlobal,
+    args: &Arguments,
+) -> Result<String> {
+    let mut layout = layout.unwrap_or_default();
+    let window_command = match (&args.command, args.command_window) {
+        (Some(command), false) => {
+            layout.commands.push(LayoutCommand::Active(command.clone()));
+            None
+        }
+        (command, _) => command.as_ref(),
+    };
+    apply_layout_to_session(target, &layout, config)?;
+    let Some(command) = window_command else {
+        return Ok(target.to_owned());
+    };
+    let output = run_tmux_change(
+        &[
+            "new-window",
+            "-a",
+            "-P",
+            "-F",
+            "#{window_id}",
+            "-t",
+            target,
+            "-c",
+            workspace_path,
+        ],
+        config.dry_run,
+    )?;
+    let window_id = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    send_commands_to_session(
+        &window_id,
+        &[LayoutCommand::Active(command.clone())],
+        config.layout_command_timeout,
+        config.dry_run,
     )
+    .with_context(|| format!("Failed to run `{command}` in tmux window {window_id}"))?;
+    Ok(window_id)
+}
+
+/// Re-reads the local layout of the workspace twm is being run in and runs its commands in the current session.
+///
+/// The workspace is found with `TWM_ROOT`, so this only works inside a session opened with twm.
+pub fn reload_local_layout(config: &TwmGlobal) -> Result<()> {
+    let (Some(workspace_path), Some(session_name)) =
+        (current_twm_root(config), current_tmux_session())
+    else {
+        bail!("Not running inside a tmux session opened with twm");
+    };
+    let Some(local_config) = find_config_file(Path::new(&workspace_path))? else {
+        bail!("No local layout found for workspace {workspace_path}");
+    };
+    let layout = get_workspace_layout(None, config, &[], Some(&local_config))?.unwrap_or_default();
+    apply_layout_to_session(&format!("={session_name}:"), &layout, config)
 }
 
-fn get_workspace_commands<'a>(
+/// Prompts for the layouts to open the workspace with. Several can be marked, which are returned in the order they
+/// were marked.
+fn get_layout_selection(twm_config: &TwmGlobal, tui: &mut Tui) -> Result<Vec<String>> {
+    let mut picker = Picker::new(
+        &get_layout_names(&twm_config.layouts),
+        "Select a layout: ".into(),
+        twm_config,
+    )
+    .with_description(
+        "Layout mode: Enter opens the workspace with the highlighted layout. Mark layouts with Tab to open \
+        the workspace with all of them, their commands run in the order they were marked.",
+    )
+    .with_multi_select();
+    let selection = match picker.get_selection(tui)? {
+        PickerSelection::None => return Err(SelectionAborted("layout").into()),
+        PickerSelection::Selection(s) => s,
+        PickerSelection::ModifiedSelection(s) => s,
+    };
+    let marked = picker.take_marked();
+    Ok(if marked.is_empty() {
+        vec![selection]
+    } else {
+        marked
+    })
+}
+
+fn get_workspace_layout(
     workspace_type: Option<&str>,
-    twm_config: &'a TwmGlobal,
-    cli_layout: Option<&'a str>,
+    twm_config: &TwmGlobal,
+    cli_layouts: &[String],
 
-    local_config: Option<&'a TwmLayout>,
-) -> Result<Option<Vec<&'a str>>> {
+    local_config: Option<&LocalLayout>,
+) -> Result<Option<ResolvedLayout>> {
     // if user wants to choose a layout do this first
-    if let Some(cli_layout) = cli_layout {
-        return Ok(Some(get_commands_from_layout_name(
-            cli_layout,
+    if !cli_layouts.is_empty() {
+        return Ok(Some(ResolvedLayout::from_layout_names(
+            cli_layouts,
             &twm_config.layouts,
         )));
     }
 
     // next check if a local layout exists
-    if let Some(local) = local_config {
-        return Ok(Some(get_commands_from_layout(
-            &local.layout,
-            &twm_config.layouts,
-        )));
-    }
-
-    match workspace_type {
-        Some(t) => {
-            for workspace_definition in &twm_config.workspace_definitions {
-                if workspace_definition.name == t {
-                    if let Some(layout_name) = &workspace_definition.default_layout {
-                        return Ok(Some(get_commands_from_layout_name(
-                            layout_name,
-                            &twm_config.layouts,
-                        )));
-                    } else {
-                        return Ok(None);
-                    }
-                }
+    match local_config {
+        Some(LocalLayout::Config(local)) => {
+            // local layouts can't be checked along with the config, so they're checked when they're used
+            if let Some(name) = local
+                .layout
+                .inherits
+                .iter()
+                .flatten()
+                .find(|name| get_layout_by_name(name, &twm_config.layouts).is_none())
+            {
+                bail!(
+                    "Layout `{}` from `.twm.yaml` file inherits from layout `{name}`, which is not defined in the twm config.",
+                    local.layout.name
+                );
             }
-            Ok(None)
+            return Ok(Some(ResolvedLayout::from_layout(
+                &local.layout,
+                &twm_config.layouts,
+            )));
         }
-        None => Ok(None),
+        Some(LocalLayout::Named(name)) => {
+            if get_layout_by_name(name, &twm_config.layouts).is_none() {
+                bail!("Layout `{name}` from `.twm-layout` file is not defined in the twm config.");
+            }
+            return Ok(Some(ResolvedLayout::from_layout_name(
+                name,
+                &twm_config.layouts,
+            )));
+        }
+        None => {}
     }
+
+    // definitions already have `fallback_layout` applied unless they opted out, so it's only needed here for
+    // workspaces that don't match any definition
+    let workspace_definition = workspace_type.and_then(|t| {
+        twm_config
+            .workspace_definitions
+            .iter()
+            .find(|definition| definition.name == t)
+    });
+    let layout_name = match workspace_definition {
+        Some(definition) => definition.default_layout.as_deref(),
+        None => twm_config.fallback_layout.as_deref(),
+    };
+    Ok(layout_name.map(|name| ResolvedLayout::from_layout_name(name, &twm_config.layouts)))
 }
 
-fn find_config_file(workspace_path: &Path) -> Result<Option<TwmLayout>> {
-    let local_config = TwmLayout::load(workspace_path)?;
+fn find_config_file(workspace_path: &Path) -> Result<Option<LocalLayout>> {
+    let local_config = LocalLayout::load(workspace_path)?;
     if let Some(local_config) = local_config {
         return Ok(Some(local_config));
     }
@@ -235,43 +1188,95 @@ fn find_config_file(workspace_path: &Path) -> Result<Option<TwmLayout>> {
     }
 }
 
-pub fn session_name_for_path_recursive(
+/// Gets the suffix appended to generated session names for workspaces of the given type, which is empty unless
+/// `session_name_include_type` is set.
+pub fn session_name_suffix(workspace_type: Option<&str>, config: &TwmGlobal) -> String {
+    match workspace_type {
+        Some(workspace_type) if config.session_name_include_type => {
+            format!("{}{workspace_type}", config.session_name_type_separator)
+        }
+        _ => String::new(),
+    }
+}
+
+/// Finds the twm-generated session for the workspace at `path`, if there is one.
+pub fn session_name_for_path(
     path: &str,
-    path_components: usize,
+    workspace_type: Option<&str>,
+    config: &TwmGlobal,
 ) -> Result<Option<SessionName>> {
-    // start out with the session name for the base # of path components passed in
-    let name = SessionName::new(path, path_components);
-
-    // if no session with the auto-generated name exists, we say there is no session
+    // the generated name is either free or already belongs to the session for this path.
     // technically this won't work for custom-named sessions, but the original intention behind
     // allowing a custom name was to keep those sessions somewhat isolated from the builtin functionalities
     // so for now i am calling that behavior a feature not a bug
-    if !tmux_has_session(&name) {
-        return Ok(None);
+    let name = get_session_name(path, workspace_type, config, &|name| {
+        lookup_tmux_session(name, config)
+    })?;
+    Ok(tmux_has_session(&name).then_some(name))
+}
+
+/// Looks up the session with the given name, returning `None` if no such session exists, or the session's
+/// `TWM_ROOT` if it does. The `TWM_ROOT` lookup fails if the session isn't a twm session.
+fn lookup_tmux_session(name: &SessionName, config: &TwmGlobal) -> Option<Result<String>> {
+    if !tmux_has_session(name) {
+        return None;
     }
+    Some(get_twm_root_for_session(name, config))
+}
 
-    // if we successfully parse the TWM_ROOT variable for the session and it matches our path,
-    // we've found the session we're looking for & return that session name
-    if let Ok(twm_root) = get_twm_root_for_session(&name) {
-        if twm_root == path {
-            return Ok(Some(name));
-        }
+fn get_session_name(
+    path: &str,
+    workspace_type: Option<&str>,
+    config: &TwmGlobal,
+    lookup_session: &impl Fn(&SessionName) -> Option<Result<String>>,
+) -> Result<SessionName> {
+    let suffix = session_name_suffix(workspace_type, config);
+    let template_name = config
+        .session_name_template
+        .as_deref()
+        .map(|template| expand_name_placeholders(template, path, workspace_type))
+        .filter(|name| !name.is_empty());
+    // a template can't grow by path components, so a taken name always gets a numeric suffix
+    if let Some(template_name) = template_name {
+        return get_session_name_numeric_suffix(
+            path,
+            SessionName::from(template_name.as_str()),
+            &suffix,
+            lookup_session,
+        );
+    }
+    match config.collision_strategy {
+        CollisionStrategy::PathComponents => get_session_name_recursive(
+            path,
+            config.session_name_path_components,
+            &suffix,
+            lookup_session,
+        ),
+        CollisionStrategy::NumericSuffix => get_session_name_numeric_suffix(
+            path,
+            SessionName::new(path, config.session_name_path_components),
+            &suffix,
+            lookup_session,
+        ),
     }
-    // if we have an error or our path doesn't match the TWM_ROOT, add more path components
-    session_name_for_path_recursive(path, path_components + 1)
 }
 
-fn get_session_name_recursive(path: &str, path_components: usize) -> Result<SessionName> {
-    let name = SessionName::new(path, path_components);
+fn get_session_name_recursive(
+    path: &str,
+    path_components: usize,
+    suffix: &str,
+    lookup_session: &impl Fn(&SessionName) -> Option<Result<String>>,
+) -> Result<SessionName> {
+    let name = SessionName::new(path, path_components).with_suffix(suffix);
     // no session means we can use this name
-    if !tmux_has_session(&name) {
+    let Some(twm_root) = lookup_session(&name) else {
         return Ok(name);
-    }
+    };
 
     // if the name already exists, there are two cases:
     // 1. the session is a twm session, in which case we can extract the TWM_ROOT env var to check if it matches the current path
     // 2. the session is not a twm session, in which case we need to recurse and try a new name
-    match get_twm_root_for_session(&name) {
+    match twm_root {
         // if we successfully get the TWM_ROOT variable, we are in a TWM session. if TWM_ROOT matches the path we're currently trying
         // to open, we can use this name and will simply attach to the existing session
         Ok(twm_root) => {
@@ -279,31 +1284,194 @@ fn get_session_name_recursive(path: &str, path_components: usize) -> Result<Sess
                 Ok(name)
             } else {
                 // if TWM_ROOT doesn't match, we've had a name collision and need to recurse and try a new name with more path components
-                let new_name = get_session_name_recursive(path, path_components + 1)?;
+                let new_name =
+                    get_session_name_recursive(path, path_components + 1, suffix, lookup_session)?;
                 Ok(new_name)
             }
         }
         // if we fail to get the TWM_ROOT variable, either the session is not a TWM session or is broken (e.g. TWM_ROOT is not set)
         // either way we still need to recurse for a new name
         Err(_) => {
-            let new_name = get_session_name_recursive(path, path_components + 2)?;
+            let new_name =
+                get_session_name_recursive(path, path_components + 2, suffix, lookup_session)?;
             Ok(new_name)
         }
     }
 }
 
-fn get_group_session_name(group_session_name: &str) -> Result<SessionName> {
+fn get_session_name_numeric_suffix(
+    path: &str,
+    base_name: SessionName,
+    suffix: &str,
+    lookup_session: &impl Fn(&SessionName) -> Option<Result<String>>,
+) -> Result<SessionName> {
+    let mut name = SessionName::from(base_name.as_str()).with_suffix(suffix);
+    let mut counter = 1;
+    // same as above, we can use the name if it's free or already belongs to the twm session for this path
+    while let Some(twm_root) = lookup_session(&name) {
+        if twm_root.is_ok_and(|root| root == path) {
+            break;
+        }
+        counter += 1;
+        name = SessionName::from(format!("{}-{}", base_name.as_str(), counter).as_str())
+            .with_suffix(suffix);
+    }
+    Ok(name)
+}
+
+pub const GROUP_NAME_BASE_PLACEHOLDER: &str = "{base}";
+pub const GROUP_NAME_COUNTER_PLACEHOLDER: &str = "{n}";
+
+/// Checks that a group name template produces a different name for every counter value.
+pub fn validate_group_name_template(template: &str) -> Result<()> {
+    if !template.contains(GROUP_NAME_COUNTER_PLACEHOLDER) {
+        bail!(
+            "`group_name_template` must contain the `{GROUP_NAME_COUNTER_PLACEHOLDER}` placeholder, got `{template}`."
+        );
+    }
+    Ok(())
+}
+
+fn get_group_session_name(
+    group_session_name: &str,
+    config: &TwmGlobal,
+    session_exists: impl Fn(&SessionName) -> bool,
+) -> Result<SessionName> {
+    let template = config
+        .group_name_template
+        .as_deref()
+        .unwrap_or("{base}-{n}");
+    let make_name = |name_iter: usize| {
+        let raw_name = template
+            .replace(GROUP_NAME_BASE_PLACEHOLDER, group_session_name)
+            .replace(GROUP_NAME_COUNTER_PLACEHOLDER, &name_iter.to_string());
+        SessionName::from(raw_name.as_str())
+    };
     let mut name_iter = 1;
-    let mut temp_name = format!("{}-{}", group_session_name, name_iter);
-    let mut name = SessionName::from(temp_name.as_str());
-    while tmux_has_session(&name) {
+    let mut name = make_name(name_iter);
+    while session_exists(&name) {
         name_iter += 1;
-        temp_name = format!("{}-{}", group_session_name, name_iter);
-        name = SessionName::from(temp_name.as_str());
+        name = make_name(name_iter);
     }
     Ok(name)
 }
 
+/// Gets the layout to apply when opening the workspace, prompting for a layout if `-l/--layout` was given without a
+/// name.
+pub fn get_layout_for_workspace(
+    workspace_path: &str,
+    workspace_type: Option<&str>,
+    config: &TwmGlobal,
+    args: &Arguments,
+    tui: &mut Tui,
+) -> Result<Option<ResolvedLayout>> {
+    let local_config = find_config_file(Path::new(workspace_path))?;
+    let cli_layouts = match &args.layout {
+        Some(Some(name)) => {
+            if get_layout_by_name(name, &config.layouts).is_none() {
+                bail!("Layout `{name}` is not defined in the twm config.");
+            }
+            vec![name.clone()]
+        }
+        Some(None) => get_layout_selection(config, tui)?,
+        None => Vec::new(),
+    };
+    get_workspace_layout(workspace_type, config, &cli_layouts, local_config.as_ref())
+}
+
+/// Maximum number of lines read from a workspace's dotenv file.
+const MAX_DOTENV_LINES: usize = 1000;
+
+/// Parses a minimal dotenv file into `KEY=VALUE` pairs, along with warnings about the lines that were skipped.
+fn parse_dotenv(contents: &str) -> (Vec<String>, Vec<String>) {
+    let mut vars = Vec::new();
+    let mut warnings = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if index == MAX_DOTENV_LINES {
+            warnings.push(format!("only the first {MAX_DOTENV_LINES} lines were read"));
+            break;
+        }
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            warnings.push(format!("skipped line {} without `=`", index + 1));
+            continue;
+        };
+        let key = key.trim();
+        let key_is_valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !key_is_valid {
+            warnings.push(format!(
+                "skipped line {} with invalid name `{key}`",
+                index + 1
+            ));
+            continue;
+        }
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|&quote| value.strip_prefix(quote)?.strip_suffix(quote))
+            .unwrap_or(value);
+        vars.push(format!("{key}={value}"));
+    }
+    (vars, warnings)
+}
+
+/// Reads the environment variables to set in a new session from the workspace's dotenv file, if `load_dotenv` is set.
+fn load_workspace_dotenv(
+    workspace_path: &str,
+    config: &TwmGlobal,
+    tui: &mut Tui,
+) -> Result<Vec<String>> {
+    let dotenv_path = Path::new(workspace_path).join(&config.dotenv_file);
+    if !config.load_dotenv || !dotenv_path.is_file() {
+        return Ok(Vec::new());
+    }
+    let contents = std::fs::read_to_string(&dotenv_path)
+        .with_context(|| format!("Failed to read dotenv file {}", dotenv_path.display()))?;
+    let (vars, warnings) = parse_dotenv(&contents);
+    if !warnings.is_empty() {
+        tui.suspend()?;
+        for warning in warnings {
+            eprintln!("warning: {}: {warning}", dotenv_path.display());
+        }
+    }
+    Ok(vars)
+}
+
+/// Gets the name of the window a workspace is opened in: the workspace definition's `window_name`, or else the name of
+/// the layout it's opened with if `name_window_after_layout` is set. The layout's first window can still rename it if
+/// it has a name of its own.
+fn initial_window_name<'a>(
+    workspace_definition: Option<&'a WorkspaceDefinition>,
+    layout: Option<&'a ResolvedLayout>,
+    config: &TwmGlobal,
+) -> Option<&'a str> {
+    workspace_definition
+        .and_then(|d| d.window_name.as_deref())
+        .or_else(|| {
+            layout
+                .filter(|_| config.name_window_after_layout)
+                .map(|l| l.name.as_str())
+        })
+        .filter(|name| !name.is_empty())
+}
+
+fn get_workspace_definition<'a>(
+    workspace_type: Option<&str>,
+    config: &'a TwmGlobal,
+) -> Option<&'a WorkspaceDefinition> {
+    workspace_type.and_then(|t| {
+        config
+            .workspace_definitions
+            .iter()
+            .find(|definition| definition.name == t)
+    })
+}
+
 pub fn open_workspace(
     workspace_path: &str,
     workspace_type: Option<&str>,
@@ -312,42 +1480,602 @@ pub fn open_workspace(
     tui: &mut Tui,
 ) -> Result<()> {
     let tmux_name = match &args.name {
-        Some(name) => SessionName::from(name.as_str()),
-        None => get_session_name_recursive(workspace_path, config.session_name_path_components)?,
+        Some(name) => SessionName::from(
+            expand_name_placeholders(name, workspace_path, workspace_type).as_str(),
+        ),
+        None => get_session_name(workspace_path, workspace_type, config, &|name| {
+            lookup_tmux_session(name, config)
+        })?,
     };
-    if !tmux_has_session(&tmux_name) {
-        create_tmux_session(&tmux_name, workspace_type, workspace_path)?;
-        let local_config = find_config_file(Path::new(workspace_path))?;
-        let cli_layout = if args.layout {
-            Some(get_layout_selection(config, tui)?)
-        } else {
-            None
-        };
-        let commands = get_workspace_commands(
+    if config.sort_by == SortBy::Frecency && !config.dry_run {
+        // the history only orders the picker, so failing to record the workspace shouldn't keep it from opening
+        let _ = History::record_opened_workspace(workspace_path);
+    }
+    let create_session = !tmux_has_session(&tmux_name);
+    if create_session {
+        let workspace_definition = get_workspace_definition(workspace_type, config);
+        // the layout is picked first so its name can be passed to the session's shell
+        let layout = get_layout_for_workspace(workspace_path, workspace_type, config, args, tui)?;
+        let mut env = twm_context_env(workspace_path, layout.as_ref(), config).to_vec();
+        env.extend(load_workspace_dotenv(workspace_path, config, tui)?);
+        if config.dry_run {
+            tui.suspend()?;
+        }
+        create_tmux_session(
+            &tmux_name,
             workspace_type,
+            workspace_path,
+            workspace_definition,
+            initial_window_name(workspace_definition, layout.as_ref(), config),
             config,
-            cli_layout.as_deref(),
-            local_config.as_ref(),
+            &env,
         )?;
-        if let Some(layout_commands) = commands {
-            send_commands_to_session(&tmux_name.name, &layout_commands)?;
+        apply_layout_and_command(
+            &format!("={}:", tmux_name.name),
+            workspace_path,
+            layout,
+            config,
+            args,
+        )?;
+    } else if args.command.is_some() {
+        apply_layout_and_command(
+            &format!("={}:", tmux_name.name),
+            workspace_path,
+            None,
+            config,
+            args,
+        )?;
+    }
+    if !args.dont_attach {
+        match create_session {
+            true => attach_to_new_tmux_session(&tmux_name.name, config)?,
+            false => attach_to_tmux_session(&tmux_name.name, config)?,
         }
     }
+    Ok(())
+}
+
+/// Opens the workspace in a new window of the existing session `session_name` instead of in its own session.
+///
+/// The window gets the `TWM_*` environment variables of the workspace, but the session keeps its own, so it isn't
+/// treated as a session for the workspace.
+pub fn open_workspace_into_session(
+    session_name: &str,
+    workspace_path: &str,
+    workspace_type: Option<&str>,
+    config: &TwmGlobal,
+    args: &Arguments,
+    tui: &mut Tui,
+) -> Result<()> {
+    if !tmux_has_session_exact(session_name) {
+        bail!("No tmux session named `{session_name}` exists");
+    }
+    if config.sort_by == SortBy::Frecency && !config.dry_run {
+        let _ = History::record_opened_workspace(workspace_path);
+    }
+    let workspace_definition = get_workspace_definition(workspace_type, config);
+    let layout = get_layout_for_workspace(workspace_path, workspace_type, config, args, tui)?;
+    if config.dry_run {
+        tui.suspend()?;
+    }
+    let target = format!("={session_name}:");
+    let twm_env = twm_session_env(workspace_path, workspace_type, session_name, config);
+    let context_env = twm_context_env(workspace_path, layout.as_ref(), config);
+    let mut new_window_args = vec![
+        "new-window",
+        "-d",
+        "-P",
+        "-F",
+        "#{window_id}",
+        "-t",
+        &target,
+        "-c",
+        workspace_path,
+    ];
+    for var in twm_env.iter().chain(&context_env) {
+        new_window_args.extend(["-e", var]);
+    }
+    if let Some(window_name) = initial_window_name(workspace_definition, layout.as_ref(), config) {
+        new_window_args.extend(["-n", window_name]);
+    }
+    new_window_args.extend(workspace_definition.and_then(|d| d.initial_command.as_deref()));
+    let output = run_tmux_change(&new_window_args, config.dry_run).with_context(|| {
+        format!("Failed to open {workspace_path} in a new window of tmux session {session_name}")
+    })?;
+    let window_id = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    let window_id = apply_layout_and_command(&window_id, workspace_path, layout, config, args)?;
     if !args.dont_attach {
-        attach_to_tmux_session(&tmux_name.name)?;
+        run_tmux_change(&["select-window", "-t", &window_id], config.dry_run)?;
+        attach_to_tmux_session(session_name, config)?;
     }
     Ok(())
 }
 
-pub fn open_workspace_in_group(group_session_name: &str, args: &Arguments) -> Result<()> {
+pub fn open_workspace_in_group(
+    group_session_name: &str,
+    config: &TwmGlobal,
+    args: &Arguments,
+) -> Result<()> {
     let tmux_name = match &args.name {
         Some(name) => SessionName::from(name.as_str()),
-        None => get_group_session_name(group_session_name)?,
+        None => get_group_session_name(group_session_name, config, tmux_has_session)?,
     };
-    create_tmux_session_in_group(group_session_name, &tmux_name)?;
+    create_tmux_session_in_group(group_session_name, &tmux_name, config.dry_run)?;
     if !args.dont_attach {
-        attach_to_tmux_session(&tmux_name.name)?;
+        attach_to_new_tmux_session(&tmux_name.name, config)?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    use crate::config::RawTwmGlobal;
+    use crate::test_utils::{config_from_yaml, TestDir};
+
+    /// Simulates a tmux server with the given sessions. A `None` root means the session isn't a twm session.
+    fn fake_sessions(
+        sessions: &[(&str, Option<&str>)],
+    ) -> impl Fn(&SessionName) -> Option<Result<String>> {
+        let sessions: HashMap<String, Option<String>> = sessions
+            .iter()
+            .map(|(name, root)| (name.to_string(), root.map(str::to_string)))
+            .collect();
+        move |name| {
+            sessions.get(name.as_str()).map(|root| match root {
+                Some(root) => Ok(root.clone()),
+                None => bail!("not a twm session"),
+            })
+        }
+    }
+
+    fn fake_output(code: i32, stderr: &str) -> std::io::Result<Output> {
+        use std::os::unix::process::ExitStatusExt;
+        Ok(Output {
+            // wait statuses store the exit code in the second byte
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+        })
+    }
+
+    #[test]
+    fn test_kill_hooks() {
+        let dir = TestDir::new("kill-hooks");
+        let log = dir.join("log");
+        let config = config_from_yaml(&format!(
+            r#"
+pre_kill: 'echo "pre $TWM_SESSION $TWM_ROOT $PWD $WIP" >> {log}; test "$TWM_SESSION" != blocked'
+post_kill: 'echo "post $TWM_SESSION" >> {log}'
+"#,
+            log = log.display()
+        ));
+        let showenv_output = format!("TWM=1\nTWM_ROOT={}\nWIP=yes\n", dir.display());
+        let mut killed = Vec::new();
+
+        kill_session_with_hooks("foo", &showenv_output, false, &config, || {
+            killed.push("foo");
+            Ok(())
+        })
+        .unwrap();
+        let error = kill_session_with_hooks("blocked", &showenv_output, false, &config, || {
+            killed.push("blocked");
+            Ok(())
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("--force"));
+        assert_eq!(killed, ["foo"]);
+        kill_session_with_hooks("blocked", &showenv_output, true, &config, || {
+            killed.push("blocked");
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(killed, ["foo", "blocked"]);
+
+        let dir = dir.display();
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            format!("pre foo {dir} {dir} yes\npost foo\npre blocked {dir} {dir} yes\npre blocked {dir} {dir} yes\npost blocked\n")
+        );
+    }
+
+    #[test]
+    fn test_detach_others_attach_command() {
+        let config = config_from_yaml("");
+        assert_eq!(
+            outside_tmux_attach_command("foo", false, &config),
+            "tmux attach -t foo"
+        );
+        assert_eq!(
+            outside_tmux_attach_command("foo", true, &config),
+            "tmux attach -d -t foo"
+        );
+        // custom commands are left alone, the other clients are detached separately
+        let config = config_from_yaml("attach_command_template: kitty tmux attach -t {session}");
+        assert_eq!(
+            outside_tmux_attach_command("foo", true, &config),
+            "kitty tmux attach -t foo"
+        );
+    }
+
+    #[test]
+    fn test_kill_failures_are_collected() {
+        let sessions = ["foo", "bar", "baz"].map(String::from);
+        let mut attempted = Vec::new();
+        let error = kill_each(&sessions, |session_name| {
+            attempted.push(session_name.to_owned());
+            match session_name {
+                "foo" => Ok(()),
+                _ => bail!("can't find session"),
+            }
+        })
+        .unwrap_err();
+        assert_eq!(attempted, sessions);
+        assert_eq!(
+            error.to_string(),
+            "Failed to kill 2 of the selected sessions:\nbar: can't find session\nbaz: can't find session"
+        );
+        assert!(kill_each(&sessions, |_| Ok(())).is_ok());
+    }
+
+    #[test]
+    fn test_server_retry_recovers_after_server_starts() {
+        let mut calls = Vec::new();
+        let mut new_session_attempts = 0;
+        let mut run = |args: &[&str]| {
+            calls.push(args[0].to_string());
+            match args[0] {
+                "new-session" if new_session_attempts < 2 => {
+                    new_session_attempts += 1;
+                    fake_output(1, "no server running on /tmp/tmux-1000/default")
+                }
+                _ => fake_output(0, ""),
+            }
+        };
+        let result = run_tmux_command_with_server_retry(&["new-session"], &mut run, Duration::ZERO);
+        assert!(result.is_ok());
+        assert_eq!(
+            calls,
+            [
+                "new-session",
+                "start-server",
+                "new-session",
+                "start-server",
+                "new-session"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_server_retry_gives_up() {
+        let mut calls = 0;
+        let mut run = |_: &[&str]| {
+            calls += 1;
+            fake_output(1, "no server running on /tmp/tmux-1000/default")
+        };
+        let result = run_tmux_command_with_server_retry(&["new-session"], &mut run, Duration::ZERO);
+        assert!(result.is_err());
+        // the initial attempt plus a server start and attempt for each retry
+        assert_eq!(calls, 1 + 2 * SERVER_START_RETRIES);
+    }
+
+    #[test]
+    fn test_server_retry_does_not_retry_other_errors() {
+        let mut calls = 0;
+        let mut run = |_: &[&str]| {
+            calls += 1;
+            fake_output(1, "duplicate session: foo")
+        };
+        let result = run_tmux_command_with_server_retry(&["new-session"], &mut run, Duration::ZERO);
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_session_name_ignores_trailing_slash() {
+        assert_eq!(SessionName::new("/a/b/foo/", 2).as_str(), "b/foo");
+        assert_eq!(
+            SessionName::new("/a/b/foo//", 1).as_str(),
+            SessionName::new("/a/b/foo", 1).as_str()
+        );
+    }
+
+    #[test]
+    fn test_expand_name_placeholders() {
+        let path = "/home/vinny/projects/twm.rs/";
+        assert_eq!(
+            expand_name_placeholders("review-{basename}", path, Some("rust")),
+            "review-twm.rs"
+        );
+        assert_eq!(
+            expand_name_placeholders("{parent}/{basename}:{type}", path, None),
+            "projects/twm.rs:"
+        );
+        assert_eq!(
+            expand_name_placeholders("{type}-{basename}", "/", Some("rust")),
+            "rust-"
+        );
+        assert_eq!(expand_name_placeholders("jimbob", path, None), "jimbob");
+        // placeholders are expanded before the name is sanitized
+        assert_eq!(
+            SessionName::from(expand_name_placeholders("{basename}", path, None).as_str()).as_str(),
+            "twm_rs"
+        );
+    }
+
+    #[test]
+    fn test_next_session_in_group() {
+        let session = |name: &str, group: &str| TmuxSession {
+            name: name.to_owned(),
+            activity: 0,
+            created: 0,
+            group: group.to_owned(),
+        };
+        let sessions = [
+            session("foo", "foo"),
+            session("foo-1", "foo"),
+            session("other", ""),
+            session("foo-2", "foo"),
+        ];
+        assert_eq!(
+            next_session_in_group(&sessions, "foo", Some("foo")),
+            Some("foo-1")
+        );
+        assert_eq!(
+            next_session_in_group(&sessions, "foo", Some("foo-1")),
+            Some("foo-2")
+        );
+        assert_eq!(
+            next_session_in_group(&sessions, "foo", Some("foo-2")),
+            Some("foo")
+        );
+        assert_eq!(
+            next_session_in_group(&sessions, "foo", Some("other")),
+            Some("foo")
+        );
+        assert_eq!(next_session_in_group(&sessions, "foo", None), Some("foo"));
+        assert_eq!(next_session_in_group(&sessions, "bar", Some("foo")), None);
+    }
+
+    #[test]
+    fn test_group_name_template() {
+        let existing = |name: &SessionName| ["foo-1", "grp_foo_1"].contains(&name.as_str());
+        let config = config_from_yaml("session_name_path_components: 1");
+        let name = get_group_session_name("foo", &config, existing).unwrap();
+        assert_eq!(name.as_str(), "foo-2");
+
+        let config = config_from_yaml("group_name_template: grp.{base}.{n}");
+        let name = get_group_session_name("foo", &config, existing).unwrap();
+        assert_eq!(name.as_str(), "grp_foo_2");
+
+        let raw_config = RawTwmGlobal::from_str("group_name_template: grp-{base}").unwrap();
+        assert!(TwmGlobal::try_from(raw_config).is_err());
+    }
+
+    #[test]
+    fn test_no_server_is_empty_session_list() {
+        let args = ["list-sessions"];
+        let output = fake_output(1, "no server running on /tmp/tmux-1000/default");
+        assert!(parse_tmux_sessions(&args, output).unwrap().is_empty());
+        let output = fake_output(1, "error connecting to /tmp/tmux-1000/default");
+        assert!(parse_tmux_sessions(&args, output).unwrap().is_empty());
+        let output = fake_output(1, "unknown option -- z");
+        assert!(parse_tmux_sessions(&args, output).is_err());
+    }
+
+    #[test]
+    fn test_session_info_from_showenv() {
+        let config = config_from_yaml("");
+        let env = twm_session_env("/a/foo", None, "foo", &config).join("\n");
+        assert_eq!(
+            SessionInfo::from_showenv("foo".into(), &env, &config),
+            SessionInfo {
+                name: "foo".into(),
+                twm: true,
+                root: Some("/a/foo".into()),
+                workspace_type: None,
+            }
+        );
+        let info = SessionInfo::from_showenv("bar".into(), "-TWM\nHOME=/root", &config);
+        assert!(!info.twm && info.root.is_none());
+    }
+
+    #[test]
+    fn test_env_prefix_round_trip() {
+        let config = config_from_yaml("env_prefix: MY_TWM");
+        let env = twm_session_env("/a/foo", Some("rust"), "foo", &config);
+        assert_eq!(env[0], "MY_TWM=1");
+        let showenv_output = format!("TWM_ROOT=/other\n{}\n", env.join("\n"));
+        assert_eq!(
+            parse_twm_root(&showenv_output, &config).as_deref(),
+            Some("/a/foo")
+        );
+        let default_config = config_from_yaml("");
+        assert_eq!(parse_twm_root(&env.join("\n"), &default_config), None);
+        assert!(TwmGlobal::try_from(RawTwmGlobal::from_str("env_prefix: 1TWM").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_context_env() {
+        let config = config_from_yaml("env_prefix: MY_TWM");
+        let layout = ResolvedLayout::from_layout_names(
+            &["rust".to_owned(), "debugger".to_owned()],
+            &config.layouts,
+        );
+        let env = twm_context_env("/a/twm.rs/", Some(&layout), &config);
+        assert!(env[0].starts_with("MY_TWM_PREV_SESSION="));
+        assert_eq!(env[1], "MY_TWM_WORKSPACE_BASENAME=twm.rs");
+        assert_eq!(env[2], "MY_TWM_LAYOUT_NAME=rust,debugger");
+        let env = twm_context_env("/", None, &config);
+        assert_eq!(env[1], "MY_TWM_WORKSPACE_BASENAME=");
+        assert_eq!(env[2], "MY_TWM_LAYOUT_NAME=");
+    }
+
+    #[test]
+    fn test_format_tmux_command() {
+        assert_eq!(
+            format_tmux_command(&["send-keys", "-t", "=foo:.1", "echo it's $HOME", "C-m"]),
+            r"tmux send-keys -t =foo:.1 'echo it'\''s $HOME' C-m"
+        );
+        assert_eq!(
+            format_tmux_command(&["new-window", "-c", "#{pane_current_path}", "-n", ""]),
+            "tmux new-window -c '#{pane_current_path}' -n ''"
+        );
+    }
+
+    #[test]
+    fn test_initial_window_name() {
+        let mut definition = WorkspaceDefinition {
+            name: "rust".into(),
+            conditions: vec![],
+            default_layout: None,
+            initial_command: None,
+            window_name: None,
+        };
+        let layout = ResolvedLayout {
+            name: "dev".into(),
+            ..ResolvedLayout::default()
+        };
+        let mut config = config_from_yaml("{}");
+        // windows are only named after layouts when asked to
+        assert_eq!(
+            initial_window_name(Some(&definition), Some(&layout), &config),
+            None
+        );
+        config.name_window_after_layout = true;
+        assert_eq!(initial_window_name(None, None, &config), None);
+        assert_eq!(
+            initial_window_name(Some(&definition), Some(&layout), &config),
+            Some("dev")
+        );
+        // layouts that aren't defined have no name to use
+        let undefined = ResolvedLayout::default();
+        assert_eq!(
+            initial_window_name(Some(&definition), Some(&undefined), &config),
+            None
+        );
+        definition.window_name = Some("code".into());
+        assert_eq!(
+            initial_window_name(Some(&definition), Some(&layout), &config),
+            Some("code")
+        );
+        config.name_window_after_layout = false;
+        assert_eq!(
+            initial_window_name(Some(&definition), Some(&layout), &config),
+            Some("code")
+        );
+    }
+
+    #[test]
+    fn test_parse_dotenv() {
+        let (vars, warnings) = parse_dotenv(
+            "# comment\n\nFOO=bar\nexport BAZ = \"a b\"\nQUX='x=y'\nnot a var\n1X=2\nEMPTY=\n",
+        );
+        assert_eq!(vars, ["FOO=bar", "BAZ=a b", "QUX=x=y", "EMPTY="]);
+        assert_eq!(warnings.len(), 2);
+        let (vars, warnings) = parse_dotenv(&"A=1\n".repeat(MAX_DOTENV_LINES + 1));
+        assert_eq!(vars.len(), MAX_DOTENV_LINES);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_is_backgrounded() {
+        assert!(is_backgrounded("npm run dev &"));
+        assert!(is_backgrounded("sleep 1&  "));
+        assert!(!is_backgrounded("cargo build && cargo run"));
+        assert!(!is_backgrounded("source .venv/bin/activate"));
+    }
+
+    #[test]
+    fn test_pane_target() {
+        assert_eq!(pane_target("=foo:", 1), "=foo:.1");
+        assert_eq!(pane_target("@3", 0), "@3.0");
+    }
+
+    #[test]
+    fn test_sort_sessions() {
+        let mut output = fake_output(0, "").unwrap();
+        output.stdout = b"300:100::bar\n100:300:foo:foo\n200:200::baz\tqux\n".to_vec();
+        let sessions = parse_tmux_sessions(&["list-sessions"], Ok(output)).unwrap();
+        let sorted = |sort: SessionSort| {
+            let mut sessions = sessions.clone();
+            sort_tmux_sessions(&mut sessions, sort);
+            sessions.into_iter().map(|s| s.name).collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(SessionSort::Name), ["bar", "baz\tqux", "foo"]);
+        assert_eq!(sorted(SessionSort::Activity), ["bar", "baz\tqux", "foo"]);
+        assert_eq!(sorted(SessionSort::Created), ["foo", "baz\tqux", "bar"]);
+    }
+
+    #[test]
+    fn test_path_components_strategy() {
+        let config = config_from_yaml("session_name_path_components: 1");
+        let lookup = fake_sessions(&[
+            ("foo", Some("/a/foo")),
+            ("b/foo", Some("/a/b/foo")),
+            ("bar", None),
+        ]);
+        let name = get_session_name("/a/foo", None, &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "foo");
+        let name = get_session_name("/c/b/foo", None, &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "c/b/foo");
+        let name = get_session_name("/x/y/bar", None, &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "x/y/bar");
+    }
+
+    #[test]
+    fn test_session_name_type_suffix() {
+        let mut config = config_from_yaml("session_name_path_components: 1\nsession_name_include_type: true\nsession_name_type_separator: .");
+        let lookup = fake_sessions(&[("foo_rust", Some("/a/foo")), ("bar_rust", None)]);
+        // the separator is sanitized along with the rest of the name
+        let name = get_session_name("/a/foo", Some("rust"), &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "foo_rust");
+        let name = get_session_name("/b/foo", Some("rust"), &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "b/foo_rust");
+        let name = get_session_name("/b/foo", None, &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "foo");
+        config.collision_strategy = CollisionStrategy::NumericSuffix;
+        let name = get_session_name("/x/bar", Some("rust"), &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "bar-2_rust");
+    }
+
+    #[test]
+    fn test_session_name_template() {
+        let config = config_from_yaml("session_name_template: \"{parent}:{basename}{type}\"");
+        let lookup = fake_sessions(&[("a_foo", Some("/a/foo")), ("b_foo", Some("/x/b/foo"))]);
+        let name = get_session_name("/a/foo", None, &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "a_foo");
+        // a template name can't grow by path components even with the default strategy
+        let name = get_session_name("/y/b/foo", None, &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "b_foo-2");
+        let name = get_session_name("/c/bar.rs", Some("-rust"), &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "c_bar_rs-rust");
+        // an empty name falls back to the path
+        let config = config_from_yaml("session_name_template: \"{type}\"");
+        let name = get_session_name("/a/bar", None, &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "a/bar");
+    }
+
+    #[test]
+    fn test_numeric_suffix_strategy() {
+        let config =
+            config_from_yaml("session_name_path_components: 1\ncollision_strategy: numeric_suffix");
+        let lookup = fake_sessions(&[
+            ("foo", Some("/a/foo")),
+            ("foo-2", Some("/b/foo")),
+            ("bar", None),
+        ]);
+        let name = get_session_name("/a/foo", None, &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "foo");
+        let name = get_session_name("/b/foo", None, &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "foo-2");
+        let name = get_session_name("/c/foo", None, &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "foo-3");
+        let name = get_session_name("/x/y/bar", None, &config, &lookup).unwrap();
+        assert_eq!(name.as_str(), "bar-2");
+    }
+}
@@ -53,6 +53,16 @@ impl EventHandler {
         Self { receiver }
     }
 
+    /// Creates a handler that only yields `events`, after which `next` fails.
+    #[cfg(test)]
+    pub fn from_events(events: Vec<Event>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        for event in events {
+            sender.send(event).expect("The receiver is still alive");
+        }
+        Self { receiver }
+    }
+
     pub fn next(&self) -> Result<Event> {
         self.receiver.recv().map_err(Into::into)
     }
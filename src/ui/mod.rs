@@ -1,7 +1,11 @@
 mod event;
 mod picker;
+mod preview;
 mod tui;
 
 pub use event::EventHandler;
-pub use picker::{Picker, PickerSelection};
+pub use picker::{
+    compare_matches, ModifiedSelectionAction, Picker, PickerItem, PickerSelection, PickerValue,
+    SelectionAborted,
+};
 pub use tui::Tui;
@@ -1,51 +1,307 @@
 use anyhow::Result;
 use crossterm::event::{KeyEvent, KeyModifiers};
 
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
 use crossterm::event::KeyCode;
 use nucleo::{
     pattern::{CaseMatching, Normalization},
-    Injector, Nucleo,
+    Injector, Matcher, Nucleo, Snapshot,
 };
 use ratatui::{
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Style, Stylize},
     text::{Line, Span},
     widgets::{
-        block::Position, Block, HighlightSpacing, List, ListDirection, ListItem, ListState,
-        Paragraph,
+        block::{Position, Title},
+        Block, Borders, Clear, HighlightSpacing, List, ListDirection, ListItem, ListState,
+        Paragraph, Wrap,
     },
     Frame,
 };
 
+use crate::config::{PickerLayout, SortBy, Tiebreak, TwmGlobal};
+
 use super::event::Event;
+use super::preview::Preview;
 use super::tui::Tui;
 
-pub enum PickerSelection {
-    Selection(String),
-    ModifiedSelection(String),
+/// Orders two matched items, with their scores for the query, by the first criterion in `tiebreak` that tells them
+/// apart.
+pub fn compare_matches<T>(
+    (a, score_a): (&PickerItem<T>, u32),
+    (b, score_b): (&PickerItem<T>, u32),
+    tiebreak: &[Tiebreak],
+) -> std::cmp::Ordering {
+    tiebreak
+        .iter()
+        .map(|tiebreak| match tiebreak {
+            Tiebreak::Score => score_b.cmp(&score_a),
+            Tiebreak::Length => a.text.chars().count().cmp(&b.text.chars().count()),
+            Tiebreak::Path => a.text.cmp(&b.text),
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+/// An item that can be selected in the picker, returning `value` when selected.
+#[derive(Debug, Clone)]
+pub struct PickerItem<T = String> {
+    pub text: String,
+    /// The group the item is listed under when grouping is enabled. Items without a group are listed without a header.
+    pub group: Option<String>,
+    /// Label displayed after the text, formatted as `  [label]`. Empty if the item has no label.
+    label: String,
+    /// Tags displayed after the text, formatted as ` #tag1 #tag2`. Empty if the item has no tags.
+    tags: String,
+    /// Whether the item is one of the configured favorites, which are displayed in bold.
+    favorite: bool,
+    /// How much the item was used, listing it before items used less while nothing is typed with `sort_by: frecency`.
+    rank: u32,
+    pub value: T,
+}
+
+impl PickerItem {
+    /// Creates an item whose value is its text.
+    pub fn new(text: String, group: Option<String>) -> Self {
+        Self::with_value(text.clone(), group, text)
+    }
+}
+
+impl<T: PickerValue> PickerItem<T> {
+    /// Creates an item that displays `text` but returns `value` when selected.
+    pub fn with_value(text: String, group: Option<String>, value: T) -> Self {
+        Self {
+            text,
+            group,
+            label: String::new(),
+            tags: String::new(),
+            favorite: false,
+            rank: 0,
+            value,
+        }
+    }
+
+    /// Adds a label that is searchable along with the item's text, e.g. the workspace type.
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = format!("  [{label}]");
+        self
+    }
+
+    /// Adds tags that are searchable along with the item's text.
+    pub fn with_tags(mut self, tags: &[String]) -> Self {
+        self.tags = tags.iter().map(|tag| format!(" #{tag}")).collect();
+        self
+    }
+
+    /// Marks the item as a favorite, which is displayed in bold and listed first while nothing is typed.
+    pub fn with_favorite(mut self, favorite: bool) -> Self {
+        self.favorite = favorite;
+        self
+    }
+
+    /// Sets how much the item was used, see `rank`.
+    pub fn with_rank(mut self, rank: u32) -> Self {
+        self.rank = rank;
+        self
+    }
+
+    /// The text queries are matched against: the item's text followed by its label and tags.
+    ///
+    /// Only the best matching part of the combined text is scored, so the label and tags never lower the score of a
+    /// query that matches the text itself, they just let queries match them too.
+    pub fn match_text(&self) -> Cow<'_, str> {
+        if self.label.is_empty() && self.tags.is_empty() {
+            Cow::Borrowed(&self.text)
+        } else {
+            Cow::Owned(format!("{}{}{}", self.text, self.label, self.tags))
+        }
+    }
+
+    /// Pushes the item into the picker, matching on its `match_text`.
+    pub fn inject(self, injector: &Injector<PickerItem<T>>) {
+        injector.push(self, |item, dst| dst[0] = item.match_text().as_ref().into());
+    }
+
+    fn to_list_item(&self, indices: Option<&mut Vec<u32>>, marked: bool) -> ListItem<'_> {
+        let text_style = match self.favorite {
+            true => Style::default().bold(),
+            false => Style::default(),
+        };
+        let mut line = match indices {
+            None => Line::from(vec![
+                Span::styled(&self.text, text_style),
+                Span::from(&self.label).dark_gray(),
+                Span::from(&self.tags).dark_gray(),
+            ]),
+            Some(indices) => {
+                let mut line = highlight_line(&self.text, indices, text_style);
+                // indices of matches in the label and tags continue on from the end of the text
+                let mut offset = self.text.chars().count() as u32;
+                for annotation in [&self.label, &self.tags] {
+                    if annotation.is_empty() {
+                        continue;
+                    }
+                    let mut annotation_indices: Vec<u32> = indices
+                        .iter()
+                        .filter_map(|&i| i.checked_sub(offset))
+                        .collect();
+                    line.spans.extend(
+                        highlight_line(
+                            annotation,
+                            &mut annotation_indices,
+                            Style::default().fg(Color::DarkGray),
+                        )
+                        .spans,
+                    );
+                    offset += annotation.chars().count() as u32;
+                }
+                line
+            }
+        };
+        if marked {
+            line.spans
+                .insert(0, Span::from("+ ").fg(Color::LightGreen).bold());
+        }
+        ListItem::new(line)
+    }
+}
+
+/// A row displayed in the picker when grouping is enabled.
+enum PickerRow {
+    /// Index of a matched item.
+    Item(u32),
+    Header(String),
+    /// Header of a collapsed group, along with how many matched items are hidden under it. Unlike other headers, it
+    /// can be highlighted so the group can be expanded again.
+    Collapsed(String, usize),
+}
+
+/// Values that items in the picker can hold. The matcher works on another thread, so they have to be shareable.
+pub trait PickerValue: Clone + Send + Sync + 'static {}
+
+impl<T: Clone + Send + Sync + 'static> PickerValue for T {}
+
+/// What the picker should do after a modified selection (e.g. Ctrl+Enter) was made.
+pub enum ModifiedSelectionAction<T = String> {
+    /// Exit the picker, returning the modified selection.
+    Exit,
+    /// The selection was already handled, so keep the picker open with its items replaced by the given list.
+    StayOpen(Vec<PickerItem<T>>),
+}
+
+pub enum PickerSelection<T = String> {
+    Selection(T),
+    ModifiedSelection(T),
     None,
 }
 
-pub struct Picker {
-    matcher: Nucleo<String>,
+/// Error returned when the user closes a picker without selecting anything, describing what wasn't selected.
+///
+/// This is a deliberate abort rather than a failure, so it's reported with its own exit code and no error message.
+#[derive(Debug)]
+pub struct SelectionAborted(pub &'static str);
+
+impl std::fmt::Display for SelectionAborted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No {} selected", self.0)
+    }
+}
+
+impl std::error::Error for SelectionAborted {}
+
+/// Keybindings listed in the help overlay.
+const HELP_KEYBINDINGS: &[(&str, &str)] = &[
+    ("Enter", "select the highlighted item"),
+    ("Ctrl/Shift/Alt+Enter", "modified selection"),
+    ("Up/Down, Ctrl+P/N", "move the highlight"),
+    ("Left/Right, Ctrl+B/F", "move the cursor"),
+    ("Ctrl+Y", "copy the highlighted item"),
+    ("Esc, Ctrl+C/D/Z", "close without selecting"),
+    ("?", "toggle this help"),
+];
+
+/// Keybinding listed in the help overlay of pickers that allow selecting several items.
+const MULTI_SELECT_KEYBINDING: (&str, &str) = (
+    "Tab",
+    "mark the highlighted item, Enter then selects every marked item",
+);
+
+/// Keybinding listed in the help overlay of pickers that group their items.
+const GROUP_KEYBINDING: (&str, &str) = (
+    "Ctrl+T",
+    "collapse or expand the highlighted group, Enter also expands it",
+);
+
+/// Keybinding listed in the help overlay of pickers with a preview pane.
+const PREVIEW_KEYBINDING: (&str, &str) = ("Ctrl+/", "toggle the preview pane");
+
+pub struct Picker<T: PickerValue = String> {
+    matcher: Nucleo<PickerItem<T>>,
     selection: ListState,
     filter: String,
     cursor_pos: u16,
-    pub injector: Injector<String>,
+    pub injector: Injector<PickerItem<T>>,
     prompt: String,
     should_exit: bool,
+    highlight_matches: bool,
+    highlighter: Matcher,
+    auto_select_single: Option<Arc<AtomicBool>>,
+    has_input: bool,
+    grouped: bool,
+    layout: PickerLayout,
+    tiebreak: Vec<Tiebreak>,
+    /// How items are ordered while nothing is typed.
+    sort_by: SortBy,
+    /// Indices of the matched items in the order they're displayed, only set when `tiebreak` orders them differently
+    /// than the matcher already does. Otherwise items are displayed in the order they were matched.
+    order: Option<Vec<u32>>,
+    /// Rows currently displayed, only set when grouping is enabled. Otherwise each row is simply the matched item
+    /// with the same index.
+    rows: Option<Vec<PickerRow>>,
+    /// Groups whose items are hidden under their header.
+    collapsed: HashSet<String>,
+    /// Message shown next to the match count until the next key press.
+    status: Option<String>,
+    /// Set once the items were truncated, which is shown next to the match count.
+    truncated: Option<Arc<AtomicBool>>,
+    /// What selecting an item does, shown at the top of the help overlay.
+    description: Option<String>,
+    show_help: bool,
+    /// Whether items can be marked with Tab to select several at once.
+    multi_select: bool,
+    /// Text and value of the marked items, in the order they were marked.
+    marked: Vec<(String, T)>,
+    /// Preview of the highlighted item shown next to the list, if the picker has one.
+    preview: Option<Preview>,
 }
 
 impl Picker {
-    pub fn new(list: &[String], prompt: String) -> Self {
+    pub fn new(list: &[String], prompt: String, config: &TwmGlobal) -> Self {
+        Self::with_items(
+            list.iter()
+                .map(|str| PickerItem::new(str.to_owned(), None))
+                .collect(),
+            prompt,
+            config,
+        )
+    }
+}
+
+impl<T: PickerValue> Picker<T> {
+    /// Creates a picker over items that can display something other than the value they return.
+    pub fn with_items(items: Vec<PickerItem<T>>, prompt: String, config: &TwmGlobal) -> Self {
         let matcher = Nucleo::new(nucleo::Config::DEFAULT, Arc::new(request_redraw), None, 1);
 
         let injector = matcher.injector();
 
-        for str in list {
-            injector.push(str.to_owned(), |_, dst| dst[0] = str.to_owned().into());
+        for item in items {
+            item.inject(&injector);
         }
 
         Picker {
@@ -56,41 +312,183 @@ impl Picker {
             cursor_pos: 0,
             prompt,
             should_exit: false,
+            highlight_matches: config.highlight_matches,
+            highlighter: Matcher::new(nucleo::Config::DEFAULT),
+            auto_select_single: None,
+            has_input: false,
+            grouped: config.grouped || config.group_by_search_path,
+            layout: config.picker_layout,
+            tiebreak: config.tiebreak.clone(),
+            sort_by: SortBy::None,
+            order: None,
+            rows: None,
+            collapsed: HashSet::new(),
+            status: None,
+            truncated: None,
+            description: None,
+            show_help: false,
+            multi_select: false,
+            marked: Vec::new(),
+            preview: None,
         }
     }
 
-    pub fn get_selection(&mut self, tui: &mut Tui) -> Result<PickerSelection> {
+    /// Describes what selecting an item does in the help overlay toggled with `?`.
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_owned());
+        self
+    }
+
+    /// Orders the items by `sort_by` while nothing is typed, rather than in the order they were added.
+    pub fn with_sort_by(mut self, sort_by: SortBy) -> Self {
+        self.sort_by = sort_by;
+        self
+    }
+
+    /// Lets items be marked with Tab, so several can be selected at once. See `take_marked`.
+    pub fn with_multi_select(mut self) -> Self {
+        self.multi_select = true;
+        self
+    }
+
+    /// Adds a preview pane next to the list showing the output of `command` for the highlighted item, with `{}` in
+    /// place of the item. The pane starts out hidden unless `visible` is set and can be toggled with Ctrl+/.
+    pub fn with_preview(mut self, command: &str, visible: bool) -> Self {
+        self.preview = Some(Preview::new(command, visible));
+        self
+    }
+
+    /// Takes the values of the items marked before the selection was made, in the order they were marked.
+    ///
+    /// When anything is marked, the marked items are what was selected rather than the highlighted one.
+    pub fn take_marked(&mut self) -> Vec<T> {
+        std::mem::take(&mut self.marked)
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    /// Replaces all items in the picker with the given list.
+    pub fn set_items(&mut self, items: Vec<PickerItem<T>>) {
+        self.matcher.restart(true);
+        self.injector = self.matcher.injector();
+        for item in items {
+            item.inject(&self.injector);
+        }
+    }
+
+    /// Sets the initial search query, as if the user had typed it.
+    pub fn set_filter(&mut self, filter: &str) {
+        let prev_filter = std::mem::replace(&mut self.filter, filter.to_owned());
+        self.cursor_pos = self.filter.chars().count() as u16;
+        self.update_matcher_pattern(&prev_filter);
+    }
+
+    /// Show that not every item was added to the picker once `truncated` is set.
+    pub fn show_truncation(&mut self, truncated: Arc<AtomicBool>) {
+        self.truncated = Some(truncated);
+    }
+
+    /// Automatically select the only item in the picker once `injection_done` is set, as long as the user
+    /// hasn't typed anything yet.
+    pub fn auto_select_single(&mut self, injection_done: Arc<AtomicBool>) {
+        self.auto_select_single = Some(injection_done);
+    }
+
+    pub fn get_selection(&mut self, tui: &mut Tui) -> Result<PickerSelection<T>> {
+        self.get_selection_with_action(tui, |_| Ok(ModifiedSelectionAction::Exit))
+    }
+
+    /// Like `get_selection`, but runs `action` on modified selections to decide whether the picker should exit.
+    pub fn get_selection_with_action(
+        &mut self,
+        tui: &mut Tui,
+        mut action: impl FnMut(&T) -> Result<ModifiedSelectionAction<T>>,
+    ) -> Result<PickerSelection<T>> {
+        let result = self.run(tui, &mut action);
+        if result.is_err() {
+            // don't leave the terminal in raw mode on the alternate screen when bailing out of the picker.
+            // the original error is more useful than any error from restoring the terminal
+            let _ = tui.suspend();
+        }
+        result
+    }
+
+    fn run(
+        &mut self,
+        tui: &mut Tui,
+        action: &mut impl FnMut(&T) -> Result<ModifiedSelectionAction<T>>,
+    ) -> Result<PickerSelection<T>> {
         let mut selection = PickerSelection::None;
         while !self.should_exit {
             tui.draw(self)?;
-            selection = match tui.events.next()? {
-                Event::Tick => PickerSelection::None,
-                Event::Key(key_event) => self.update(key_event),
+            selection = match tui.next_event()? {
+                // only check on ticks so any keys pressed before the tick are handled first
+                Event::Tick => match self.get_auto_selection() {
+                    Some(s) => {
+                        self.should_exit = true;
+                        PickerSelection::Selection(s)
+                    }
+                    None => PickerSelection::None,
+                },
+                Event::Key(key_event) => {
+                    self.has_input = true;
+                    self.status = None;
+                    if key_event.code == KeyCode::Char('y')
+                        && key_event.modifiers.contains(KeyModifiers::CONTROL)
+                    {
+                        self.copy_selected_text(tui)?;
+                        continue;
+                    }
+                    match self.update(key_event) {
+                        PickerSelection::ModifiedSelection(s) => match action(&s)? {
+                            ModifiedSelectionAction::Exit => {
+                                self.should_exit = true;
+                                PickerSelection::ModifiedSelection(s)
+                            }
+                            ModifiedSelectionAction::StayOpen(items) => {
+                                self.set_items(items);
+                                PickerSelection::None
+                            }
+                        },
+                        selection => selection,
+                    }
+                }
             };
         }
         Ok(selection)
     }
 
-    fn update(&mut self, key_event: KeyEvent) -> PickerSelection {
+    fn update(&mut self, key_event: KeyEvent) -> PickerSelection<T> {
+        // the help overlay covers the list, so keys other than the ones closing it are ignored while it's open
+        if self.show_help {
+            if matches!(key_event.code, KeyCode::Esc | KeyCode::Char('?')) {
+                self.show_help = false;
+            }
+            return PickerSelection::None;
+        }
         match key_event.code {
             KeyCode::Esc => self.should_exit = true,
+            KeyCode::Enter if self.selected_collapsed_group().is_some() => self.toggle_group(),
             KeyCode::Enter => {
-                if let Some(selection) = self.get_selected_text() {
-                    self.should_exit = true;
+                if let Some(selection) = self.selected_item().map(|item| item.value.clone()) {
+                    // whether we exit after a modified selection is up to the caller
                     if key_event.modifiers.contains(KeyModifiers::CONTROL)
                         || key_event.modifiers.contains(KeyModifiers::SHIFT)
                         || key_event.modifiers.contains(KeyModifiers::ALT)
                     {
                         return PickerSelection::ModifiedSelection(selection);
                     } else {
+                        self.should_exit = true;
                         return PickerSelection::Selection(selection);
                     }
                 }
             }
+            KeyCode::Tab if self.multi_select => self.toggle_mark(),
             KeyCode::Backspace => self.backspace(),
             KeyCode::Delete => self.delete(),
-            KeyCode::Up => self.move_cursor_up(),
-            KeyCode::Down => self.move_cursor_down(),
+            KeyCode::Up => self.move_highlight_up(),
+            KeyCode::Down => self.move_highlight_down(),
             KeyCode::Left => self.move_cursor_left(),
             KeyCode::Right => self.move_cursor_right(),
             _ => {
@@ -98,12 +496,17 @@ impl Picker {
                     if key_event.modifiers.contains(KeyModifiers::CONTROL) {
                         match c {
                             'c' | 'd' | 'z' => self.should_exit = true,
-                            'p' => self.move_cursor_up(),
-                            'n' => self.move_cursor_down(),
+                            'p' => self.move_highlight_up(),
+                            'n' => self.move_highlight_down(),
                             'b' | 'h' => self.move_cursor_left(),
                             'f' | 'l' => self.move_cursor_right(),
+                            // most terminals send Ctrl+/ as Ctrl+_, which is read as Ctrl+7
+                            '/' | '7' => self.toggle_preview(),
+                            't' if self.grouped => self.toggle_group(),
                             _ => {}
                         }
+                    } else if c == '?' {
+                        self.show_help = true;
                     } else {
                         self.update_filter(c)
                     }
@@ -114,92 +517,446 @@ impl Picker {
     }
 
     pub fn render(&mut self, frame: &mut Frame) {
-        self.matcher.tick(10);
+        let status = self.matcher.tick(10);
+        // the snapshot can also change when ticking elsewhere, which would leave the order out of date
+        if status.changed
+            || self.order.as_ref().is_none_or(|order| {
+                order.len() != self.matcher.snapshot().matched_item_count() as usize
+            })
+        {
+            self.sort_matches();
+        }
+        let list_constraint = Constraint::Length(frame.size().height - 1);
+        let prompt_constraint = Constraint::Length(1);
+        let (list_area, prompt_area, list_direction, count_position) = match self.layout {
+            PickerLayout::Bottom => {
+                let layout = Layout::new(Direction::Vertical, [list_constraint, prompt_constraint])
+                    .split(frame.size());
+                (
+                    layout[0],
+                    layout[1],
+                    ListDirection::BottomToTop,
+                    Position::Bottom,
+                )
+            }
+            PickerLayout::Top => {
+                let layout = Layout::new(Direction::Vertical, [prompt_constraint, list_constraint])
+                    .split(frame.size());
+                (
+                    layout[1],
+                    layout[0],
+                    ListDirection::TopToBottom,
+                    Position::Top,
+                )
+            }
+        };
+
+        // the help overlay covers the preview pane as well
+        let help_area = list_area;
+        let (list_area, preview_area) = match &self.preview {
+            Some(preview) if preview.visible => {
+                let layout = Layout::new(
+                    Direction::Horizontal,
+                    [Constraint::Percentage(50), Constraint::Percentage(50)],
+                )
+                .split(list_area);
+                (layout[0], Some(layout[1]))
+            }
+            _ => (list_area, None),
+        };
+
+        self.regroup();
         let snapshot = self.matcher.snapshot();
-        let matches = snapshot
-            .matched_items(..snapshot.matched_item_count())
-            .map(|item| ListItem::new(item.data.as_str()));
-
-        if let Some(selected) = self.selection.selected() {
-            if snapshot.matched_item_count() == 0 {
-                self.selection.select(None);
-            } else if selected > snapshot.matched_item_count() as usize {
-                self.selection
-                    .select(Some(snapshot.matched_item_count() as usize - 1));
+        let row_count = self.row_count();
+
+        match self.selection.selected() {
+            _ if row_count == 0 => self.selection.select(None),
+            Some(selected) if selected < row_count && !self.is_header(selected) => {}
+            // if the selected row disappeared or we otherwise ended up on a header, move down to the nearest item.
+            // the first row is a header when headers are drawn above their items, so then move up instead
+            Some(selected) => {
+                let row = std::cmp::min(selected, row_count);
+                self.selection.select(
+                    self.next_item_row_down(row)
+                        .or_else(|| self.next_item_row_up(row)),
+                )
             }
-        } else if snapshot.matched_item_count() > 0 {
-            self.selection.select(Some(0));
+            None => self
+                .selection
+                .select((0..row_count).find(|&row| !self.is_header(row))),
         }
 
+        // only items that can actually be on screen are worth computing match indices for
+        let visible_start = self.selection.offset();
+        let visible_end = visible_start + list_area.height as usize;
+        let pattern = self.matcher.pattern.column_pattern(0);
+        let highlighter = &mut self.highlighter;
+        let highlight_matches = self.highlight_matches;
+        let rows = &self.rows;
+        let order = &self.order;
+        let marked = &self.marked;
+        let is_marked = |item: &PickerItem<T>| marked.iter().any(|(text, _)| *text == item.text);
+        let mut indices = Vec::new();
+        let matches = (0..row_count).map(|row| {
+            let index = match rows.as_ref().map(|rows| &rows[row]) {
+                Some(PickerRow::Header(group)) => {
+                    return ListItem::new(Span::from(group.as_str()).dark_gray().bold());
+                }
+                Some(PickerRow::Collapsed(group, count)) => {
+                    return ListItem::new(Line::from(vec![
+                        Span::from(group.as_str()).dark_gray().bold(),
+                        Span::from(format!(" ({count} hidden)")).dark_gray(),
+                    ]));
+                }
+                Some(PickerRow::Item(index)) => *index,
+                None => matched_index(order.as_deref(), row),
+            };
+            let item = snapshot
+                .get_matched_item(index)
+                .expect("Row indices are always in bounds");
+            if !highlight_matches || row < visible_start || row >= visible_end {
+                return item.data.to_list_item(None, is_marked(item.data));
+            }
+            indices.clear();
+            pattern.indices(item.matcher_columns[0].slice(..), highlighter, &mut indices);
+            item.data
+                .to_list_item(Some(&mut indices), is_marked(item.data))
+        });
+
+        let mut match_count = format!(
+            "{}/{}",
+            snapshot.matched_item_count(),
+            snapshot.item_count()
+        );
+        if !self.marked.is_empty() {
+            match_count.push_str(&format!(" ({} marked)", self.marked.len()));
+        }
+        if self
+            .truncated
+            .as_ref()
+            .is_some_and(|truncated| truncated.load(Ordering::Acquire))
+        {
+            match_count.push_str(" (truncated)");
+        }
         let table = List::new(matches)
-            .direction(ListDirection::BottomToTop)
+            .direction(list_direction)
             .highlight_spacing(HighlightSpacing::Always)
             .highlight_symbol("> ")
             .highlight_style(Style::default().fg(Color::LightBlue))
             .block(
-                Block::default().title_position(Position::Bottom).title(
-                    Span::from(format!(
-                        "{}/{}",
-                        snapshot.matched_item_count(),
-                        snapshot.item_count()
-                    ))
+                Block::default().title_position(count_position).title(
+                    Span::from(match &self.status {
+                        Some(status) => format!("{match_count} - {status}"),
+                        None => match_count,
+                    })
                     .gray(),
                 ),
             );
 
-        let layout = Layout::new(
-            Direction::Vertical,
-            [
-                Constraint::Length(frame.size().height - 1),
-                Constraint::Length(1),
-            ],
-        )
-        .split(frame.size());
-
-        frame.render_stateful_widget(table, layout[0], &mut self.selection);
+        frame.render_stateful_widget(table, list_area, &mut self.selection);
 
         let prompt = Span::from(&self.prompt).fg(Color::LightBlue).bold();
         let input_text = Span::raw(&self.filter);
         let input_line = Line::from(vec![prompt, input_text]);
         let input = Paragraph::new(vec![input_line]);
-        frame.render_widget(input, layout[1]);
+        frame.render_widget(input, prompt_area);
         frame.set_cursor(
-            layout[1].x + self.cursor_pos + self.prompt.len() as u16,
-            layout[1].y,
+            prompt_area.x + self.cursor_pos + self.prompt.chars().count() as u16,
+            prompt_area.y,
         );
+
+        if let Some(area) = preview_area {
+            self.render_preview(frame, area);
+        }
+
+        if self.show_help {
+            self.render_help(frame, help_area);
+        }
+    }
+
+    /// Draws the preview of the highlighted item in `area`, starting the preview command if it hasn't run yet.
+    fn render_preview(&mut self, frame: &mut Frame, area: Rect) {
+        let highlighted = self.selected_item().map(|item| item.text.clone());
+        let Some(preview) = &mut self.preview else {
+            return;
+        };
+        let text = match highlighted {
+            Some(path) => {
+                preview.request(&path);
+                preview.output(&path).unwrap_or_else(|| "Loading...".into())
+            }
+            None => String::new(),
+        };
+        let block = Block::default()
+            .borders(Borders::LEFT)
+            .title(Span::from(" Preview ").gray());
+        frame.render_widget(Paragraph::new(text).block(block), area);
     }
 
-    fn get_selected_text(&self) -> Option<String> {
-        if let Some(index) = self.selection.selected() {
-            return self
-                .matcher
-                .snapshot()
-                .get_matched_item(index as u32)
-                .map(|item| item.data.to_owned());
+    /// Draws the help overlay centered over `area`.
+    fn render_help(&self, frame: &mut Frame, area: Rect) {
+        let keybindings = HELP_KEYBINDINGS
+            .iter()
+            .chain(self.multi_select.then_some(&MULTI_SELECT_KEYBINDING))
+            .chain(self.grouped.then_some(&GROUP_KEYBINDING))
+            .chain(self.preview.is_some().then_some(&PREVIEW_KEYBINDING));
+        let key_width = keybindings
+            .clone()
+            .map(|(key, _)| key.len())
+            .max()
+            .unwrap_or_default();
+        let mut lines = Vec::new();
+        if let Some(description) = &self.description {
+            lines.push(Line::from(description.as_str()));
+            lines.push(Line::default());
         }
+        lines.extend(keybindings.map(|(key, action)| {
+            Line::from(vec![
+                Span::from(format!("{key:<key_width$}  ")).fg(Color::LightBlue),
+                Span::raw(*action),
+            ])
+        }));
+        let width = std::cmp::min(area.width, 60);
+        // the description can wrap onto more lines than it takes up unwrapped, so leave room for that
+        let height = std::cmp::min(area.height, lines.len() as u16 + 4);
+        let help_area = Rect::new(
+            area.x + (area.width - width) / 2,
+            area.y + (area.height - height) / 2,
+            width,
+            height,
+        );
+        let help = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" Help ")
+                .title(
+                    Title::from(Span::from(" ? or Esc to close ").dark_gray())
+                        .position(Position::Bottom),
+                ),
+        );
+        frame.render_widget(Clear, help_area);
+        frame.render_widget(help, help_area);
+    }
 
-        None
+    /// Orders the matched items by `tiebreak`, unless the matcher's own order already matches it.
+    fn sort_matches(&mut self) {
+        let sort_unfiltered = self.filter.is_empty() && self.sort_by != SortBy::None;
+        // the matcher sorts by score, then by length
+        if self.tiebreak == [Tiebreak::Score, Tiebreak::Length] && !sort_unfiltered {
+            self.order = None;
+            return;
+        }
+        let snapshot = self.matcher.snapshot();
+        let pattern = self.matcher.pattern.column_pattern(0);
+        let highlighter = &mut self.highlighter;
+        let mut matches: Vec<(u32, u32, &PickerItem<T>)> = snapshot
+            .matched_items(..)
+            .enumerate()
+            .map(|(index, item)| {
+                let score = pattern
+                    .score(item.matcher_columns[0].slice(..), highlighter)
+                    .unwrap_or_default();
+                (index as u32, score, item.data)
+            })
+            .collect();
+        // favorites are injected first, so without a query the matcher lists them first in the order they're listed
+        let favorites_first = self.filter.is_empty();
+        // the sort is stable, so matches tied on every criterion keep the matcher's order
+        matches.sort_by(|(index_a, score_a, a), (index_b, score_b, b)| {
+            if favorites_first && (a.favorite || b.favorite) {
+                return b.favorite.cmp(&a.favorite).then(index_a.cmp(index_b));
+            }
+            if sort_unfiltered {
+                match self.sort_by {
+                    SortBy::Frecency => return b.rank.cmp(&a.rank).then(index_a.cmp(index_b)),
+                    SortBy::Path => return a.text.cmp(&b.text),
+                    SortBy::None => {}
+                }
+            }
+            compare_matches((a, *score_a), (b, *score_b), &self.tiebreak)
+        });
+        self.order = Some(matches.into_iter().map(|(index, _, _)| index).collect());
     }
 
-    fn move_cursor_up(&mut self) {
-        let item_count = self.matcher.snapshot().matched_item_count() as usize;
-        if item_count == 0 {
+    /// Lays out the rows again when grouping is enabled, e.g. after the matches or the collapsed groups changed.
+    fn regroup(&mut self) {
+        let snapshot = self.matcher.snapshot();
+        let (order, layout, collapsed) = (self.order.as_deref(), self.layout, &self.collapsed);
+        self.rows = self
+            .grouped
+            .then(|| group_rows(snapshot, order, layout, collapsed));
+    }
+
+    /// Gets the group of the highlighted row if it's a collapsed group.
+    fn selected_collapsed_group(&self) -> Option<&str> {
+        match self.rows.as_ref()?.get(self.selection.selected()?)? {
+            PickerRow::Collapsed(group, _) => Some(group),
+            _ => None,
+        }
+    }
+
+    /// Collapses the group of the highlighted item, or expands the highlighted group if it's collapsed. The highlight
+    /// stays on the group, moving to its header or its best match.
+    fn toggle_group(&mut self) {
+        let group = match self.selected_collapsed_group() {
+            Some(group) => group.to_owned(),
+            None => match self.selected_item().and_then(|item| item.group.clone()) {
+                Some(group) => group,
+                None => return,
+            },
+        };
+        if !self.collapsed.remove(&group) {
+            self.collapsed.insert(group.clone());
+        }
+        self.regroup();
+        let snapshot = self.matcher.snapshot();
+        let row = self.rows.iter().flatten().position(|row| match row {
+            PickerRow::Collapsed(g, _) => *g == group,
+            PickerRow::Item(index) => snapshot
+                .get_matched_item(*index)
+                .is_some_and(|item| item.data.group.as_ref() == Some(&group)),
+            PickerRow::Header(_) => false,
+        });
+        if row.is_some() {
+            self.selection.select(row);
+        }
+    }
+
+    fn toggle_preview(&mut self) {
+        if let Some(preview) = &mut self.preview {
+            preview.visible = !preview.visible;
+        }
+    }
+
+    /// Marks the highlighted item, or unmarks it if it's already marked.
+    fn toggle_mark(&mut self) {
+        let Some(item) = self.selected_item() else {
             return;
+        };
+        let (text, value) = (item.text.clone(), item.value.clone());
+        match self.marked.iter().position(|(marked, _)| *marked == text) {
+            Some(index) => {
+                self.marked.remove(index);
+            }
+            None => self.marked.push((text, value)),
         }
+    }
 
-        let max = item_count - 1;
+    /// Copies the highlighted item to the clipboard without exiting the picker.
+    fn copy_selected_text(&mut self, tui: &mut Tui) -> Result<()> {
+        if let Some(text) = self.selected_item().map(|item| item.text.clone()) {
+            tui.copy_to_clipboard(&text)?;
+            self.status = Some(format!("Copied {text}"));
+        }
+        Ok(())
+    }
+
+    fn get_auto_selection(&mut self) -> Option<T> {
+        let injection_done = self.auto_select_single.as_ref()?;
+        if self.has_input {
+            self.auto_select_single = None;
+            return None;
+        }
+        // check the flag before ticking so the snapshot is guaranteed to contain every injected item
+        if !injection_done.load(Ordering::Acquire) {
+            return None;
+        }
+        let status = self.matcher.tick(10);
+        if status.running {
+            return None;
+        }
+        // no more items are coming, so whatever we decide now is final
+        self.auto_select_single = None;
+        let snapshot = self.matcher.snapshot();
+        if snapshot.item_count() != 1 {
+            return None;
+        }
+        snapshot
+            .get_matched_item(0)
+            .map(|item| item.data.value.clone())
+    }
+
+    fn selected_item(&self) -> Option<&PickerItem<T>> {
+        let index = self.item_index(self.selection.selected()?)?;
+        self.matcher
+            .snapshot()
+            .get_matched_item(index)
+            .map(|item| item.data)
+    }
+
+    fn row_count(&self) -> usize {
+        match &self.rows {
+            Some(rows) => rows.len(),
+            None => self.matcher.snapshot().matched_item_count() as usize,
+        }
+    }
+
+    /// Gets the index of the matched item displayed in the given row, if the row is an item.
+    fn item_index(&self, row: usize) -> Option<u32> {
+        match &self.rows {
+            Some(rows) => match rows.get(row)? {
+                PickerRow::Item(index) => Some(*index),
+                PickerRow::Header(_) | PickerRow::Collapsed(..) => None,
+            },
+            None => Some(matched_index(self.order.as_deref(), row)),
+        }
+    }
+
+    fn is_header(&self, row: usize) -> bool {
+        matches!(
+            self.rows.as_ref().and_then(|rows| rows.get(row)),
+            Some(PickerRow::Header(_))
+        )
+    }
+
+    /// Finds the nearest row below `row` that holds an item.
+    fn next_item_row_down(&self, row: usize) -> Option<usize> {
+        (0..row).rev().find(|&r| !self.is_header(r))
+    }
+
+    /// Finds the nearest row above `row` that holds an item.
+    fn next_item_row_up(&self, row: usize) -> Option<usize> {
+        (row + 1..self.row_count()).find(|&r| !self.is_header(r))
+    }
+
+    /// Moves the highlight to the item displayed above the current one, which depends on the direction of the list.
+    fn move_highlight_up(&mut self) {
+        match self.layout {
+            PickerLayout::Bottom => self.move_cursor_up(),
+            PickerLayout::Top => self.move_cursor_down(),
+        }
+    }
+
+    /// Moves the highlight to the item displayed below the current one, which depends on the direction of the list.
+    fn move_highlight_down(&mut self) {
+        match self.layout {
+            PickerLayout::Bottom => self.move_cursor_down(),
+            PickerLayout::Top => self.move_cursor_up(),
+        }
+    }
+
+    fn move_cursor_up(&mut self) {
+        if self.row_count() == 0 {
+            return;
+        }
 
         match self.selection.selected() {
-            Some(i) if i >= max => {}
-            Some(i) => self.selection.select(Some(i + 1)),
+            Some(i) => {
+                if let Some(row) = self.next_item_row_up(i) {
+                    self.selection.select(Some(row));
+                }
+            }
             None => self.selection.select(Some(0)),
         }
     }
 
     fn move_cursor_down(&mut self) {
         match self.selection.selected() {
-            Some(0) => {}
-            Some(i) => self.selection.select(Some(i - 1)),
+            Some(i) => {
+                if let Some(row) = self.next_item_row_down(i) {
+                    self.selection.select(Some(row));
+                }
+            }
             None => self.selection.select(Some(0)),
         }
     }
@@ -211,18 +968,25 @@ impl Picker {
     }
 
     fn move_cursor_right(&mut self) {
-        if self.cursor_pos < self.filter.len() as u16 {
+        if (self.cursor_pos as usize) < self.filter.chars().count() {
             self.cursor_pos += 1;
         }
     }
 
+    /// Byte index in the filter of the character at the cursor, since the cursor position counts characters.
+    fn cursor_byte_index(&self) -> usize {
+        self.filter
+            .char_indices()
+            .nth(self.cursor_pos as usize)
+            .map_or(self.filter.len(), |(i, _)| i)
+    }
     fn update_filter(&mut self, c: char) {
-        if self.filter.len() == u16::MAX as usize {
+        if self.filter.chars().count() == u16::MAX as usize {
             return;
         }
 
         let prev_filter = self.filter.clone();
-        self.filter.insert(self.cursor_pos as usize, c);
+        self.filter.insert(self.cursor_byte_index(), c);
         self.cursor_pos += 1;
 
         self.update_matcher_pattern(&prev_filter);
@@ -234,9 +998,8 @@ impl Picker {
         }
 
         let prev_filter = self.filter.clone();
-        self.filter.remove(self.cursor_pos as usize - 1);
-
         self.cursor_pos -= 1;
+        self.filter.remove(self.cursor_byte_index());
 
         if self.filter != prev_filter {
             self.update_matcher_pattern(&prev_filter);
@@ -244,12 +1007,12 @@ impl Picker {
     }
 
     fn delete(&mut self) {
-        if (self.cursor_pos as usize) == self.filter.len() {
+        if (self.cursor_pos as usize) == self.filter.chars().count() {
             return;
         }
 
         let prev_filter = self.filter.clone();
-        self.filter.remove(self.cursor_pos as usize);
+        self.filter.remove(self.cursor_byte_index());
 
         if self.filter != prev_filter {
             self.update_matcher_pattern(&prev_filter);
@@ -267,4 +1030,320 @@ impl Picker {
     }
 }
 
+/// Lays out the matched items in groups, ordered by the best match in each group, with a header for each group. The
+/// items of groups in `collapsed` are left out, leaving just their header.
+///
+/// Headers are displayed above their items, so when the list is drawn bottom to top each group's header comes after
+/// its items.
+fn group_rows<T: PickerValue>(
+    snapshot: &Snapshot<PickerItem<T>>,
+    order: Option<&[u32]>,
+    layout: PickerLayout,
+    collapsed: &HashSet<String>,
+) -> Vec<PickerRow> {
+    let mut groups: Vec<(Option<&str>, Vec<u32>)> = Vec::new();
+    for position in 0..snapshot.matched_item_count() as usize {
+        let index = matched_index(order, position);
+        let item = snapshot
+            .get_matched_item(index)
+            .expect("Matched indices are always in bounds");
+        let group = item.data.group.as_deref();
+        match groups.iter_mut().find(|(g, _)| *g == group) {
+            Some((_, items)) => items.push(index),
+            None => groups.push((group, vec![index])),
+        }
+    }
+    groups
+        .into_iter()
+        .flat_map(|(group, items)| {
+            if let Some(group) = group.filter(|g| collapsed.contains(*g)) {
+                return vec![PickerRow::Collapsed(group.to_owned(), items.len())];
+            }
+            let header = group.map(|g| PickerRow::Header(g.to_owned()));
+            let items = items.into_iter().map(PickerRow::Item);
+            match layout {
+                PickerLayout::Bottom => items.chain(header).collect::<Vec<_>>(),
+                PickerLayout::Top => header.into_iter().chain(items).collect(),
+            }
+        })
+        .collect()
+}
+
+/// Gets the index of the matched item displayed at `position` in the list of matches.
+fn matched_index(order: Option<&[u32]>, position: usize) -> u32 {
+    match order {
+        Some(order) => order[position],
+        None => position as u32,
+    }
+}
+
+/// Builds a line with the characters at the given match indices highlighted.
+fn highlight_line<'a>(text: &'a str, indices: &mut Vec<u32>, style: Style) -> Line<'a> {
+    indices.sort_unstable();
+    indices.dedup();
+    let mut spans = Vec::new();
+    let mut matched = indices.iter().peekable();
+    let mut span_start = 0;
+    let mut span_is_match = false;
+    for (char_idx, (byte_idx, _)) in text.char_indices().enumerate() {
+        let is_match = matched.next_if(|&&i| i as usize == char_idx).is_some();
+        if is_match != span_is_match {
+            spans.push(styled_span(
+                &text[span_start..byte_idx],
+                span_is_match,
+                style,
+            ));
+            span_start = byte_idx;
+            span_is_match = is_match;
+        }
+    }
+    spans.push(styled_span(&text[span_start..], span_is_match, style));
+    Line::from(spans)
+}
+
+fn styled_span(text: &str, is_match: bool, style: Style) -> Span<'_> {
+    if is_match {
+        Span::styled(text, style).fg(Color::Yellow).bold()
+    } else {
+        Span::styled(text, style)
+    }
+}
+
 fn request_redraw() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::config_from_yaml;
+
+    fn picker() -> Picker {
+        let config = config_from_yaml("");
+        Picker::new(&[], "> ".into(), &config)
+    }
+
+    #[test]
+    fn test_selection_returns_value() {
+        let config = config_from_yaml("");
+        let items = vec![
+            PickerItem::with_value("one".into(), None, 1),
+            PickerItem::with_value("two".into(), None, 2),
+        ];
+        let mut picker = Picker::with_items(items, "> ".into(), &config);
+        picker.set_filter("tw");
+        while picker.matcher.tick(10).running {}
+        picker.selection.select(Some(0));
+        let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        assert!(matches!(
+            picker.update(enter),
+            PickerSelection::Selection(2)
+        ));
+    }
+
+    #[test]
+    fn test_errors_restore_the_terminal() {
+        let config = config_from_yaml("");
+        // a failing modified selection action, e.g. tmux failing to create a session
+        let modified_enter = Event::Key(KeyEvent::new(KeyCode::Enter, KeyModifiers::ALT));
+        let mut tui = Tui::headless(vec![Event::Tick, modified_enter]);
+        let mut picker = Picker::new(&["/code/foo".into()], "> ".into(), &config);
+        let Err(error) =
+            picker.get_selection_with_action(&mut tui, |_| anyhow::bail!("tmux exited with 1"))
+        else {
+            panic!("The action's error wasn't returned");
+        };
+        assert_eq!(error.to_string(), "tmux exited with 1");
+        assert!(!tui.is_entered());
+
+        // the terminal's events running out
+        let mut tui = Tui::headless(vec![]);
+        let Err(error) = picker.get_selection(&mut tui) else {
+            panic!("The event error wasn't returned");
+        };
+        assert_eq!(error.to_string(), "receiving on a closed channel");
+        assert!(!tui.is_entered());
+    }
+
+    #[test]
+    fn test_label_is_matched_but_not_selected() {
+        let config = config_from_yaml("");
+        let items = vec![
+            PickerItem::new("/code/foo".into(), None).with_label("rust"),
+            PickerItem::new("/code/bar".into(), None).with_label("python"),
+        ];
+        let mut picker = Picker::with_items(items, "> ".into(), &config);
+        picker.set_filter("rust");
+        while picker.matcher.tick(10).running {}
+        assert_eq!(picker.row_count(), 1);
+        picker.selection.select(Some(0));
+        let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        assert!(matches!(
+            picker.update(enter),
+            PickerSelection::Selection(path) if path == "/code/foo"
+        ));
+    }
+
+    #[test]
+    fn test_groups_collapse_and_expand() {
+        let config = config_from_yaml("group_by_search_path: true\npicker_layout: top");
+        let items = vec![
+            PickerItem::new("/a/one".into(), Some("/a".into())),
+            PickerItem::new("/a/two".into(), Some("/a".into())),
+            PickerItem::new("/b/three".into(), Some("/b".into())),
+        ];
+        let mut picker = Picker::with_items(items, "> ".into(), &config);
+        while picker.matcher.tick(10).running {}
+        picker.regroup();
+        assert_eq!(picker.row_count(), 5);
+        picker.selection.select(Some(2));
+        let ctrl_t = KeyEvent::new(KeyCode::Char('t'), KeyModifiers::CONTROL);
+        picker.update(ctrl_t);
+        assert_eq!(picker.row_count(), 3);
+        assert_eq!(picker.selected_collapsed_group(), Some("/a"));
+        // enter expands the group rather than selecting anything
+        let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        assert!(matches!(picker.update(enter), PickerSelection::None));
+        assert_eq!(picker.row_count(), 5);
+        assert_eq!(picker.selected_item().unwrap().text, "/a/one");
+    }
+
+    #[test]
+    fn test_marked_items_are_taken_in_marking_order() {
+        let config = config_from_yaml("");
+        let items = ["one", "two", "three"].map(String::from);
+        let mut picker = Picker::new(&items, "> ".into(), &config).with_multi_select();
+        while picker.matcher.tick(10).running {}
+        let tab = KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE);
+        for row in [2, 0, 1] {
+            picker.selection.select(Some(row));
+            picker.update(tab);
+        }
+        // marking again unmarks
+        picker.update(tab);
+        assert_eq!(picker.take_marked(), ["three", "one"]);
+        assert!(picker.take_marked().is_empty());
+    }
+
+    #[test]
+    fn test_help_toggle_ignores_other_keys() {
+        let mut picker = picker();
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        picker.update(key('?'));
+        assert!(picker.show_help);
+        picker.update(key('a'));
+        assert_eq!(picker.filter, "");
+        picker.update(key('?'));
+        assert!(!picker.show_help);
+        picker.update(key('?'));
+        picker.update(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        assert!(!picker.show_help);
+        assert!(!picker.should_exit);
+    }
+
+    #[test]
+    fn test_top_layout_moves_highlight_by_screen_direction() {
+        let config = config_from_yaml("picker_layout: top");
+        let items = ["one", "two", "three"].map(String::from);
+        let mut picker = Picker::new(&items, "> ".into(), &config);
+        while picker.matcher.tick(10).running {}
+        picker.selection.select(Some(1));
+        picker.update(KeyEvent::new(KeyCode::Down, KeyModifiers::NONE));
+        assert_eq!(picker.selection.selected(), Some(2));
+        picker.update(KeyEvent::new(KeyCode::Up, KeyModifiers::NONE));
+        picker.update(KeyEvent::new(KeyCode::Up, KeyModifiers::NONE));
+        assert_eq!(picker.selection.selected(), Some(0));
+    }
+
+    #[test]
+    fn test_tiebreak_orders_matches() {
+        let items = ["zeta", "alpha", "beta-longer"].map(String::from);
+        let displayed = |tiebreak: &str| {
+            let config = config_from_yaml(&format!("tiebreak: {tiebreak}"));
+            let mut picker = Picker::new(&items, "> ".into(), &config);
+            while picker.matcher.tick(10).running {}
+            picker.sort_matches();
+            (0..picker.row_count())
+                .map(|row| {
+                    picker.selection.select(Some(row));
+                    picker.selected_item().unwrap().text.clone()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(displayed("[path]"), ["alpha", "beta-longer", "zeta"]);
+        assert_eq!(
+            displayed("[length, path]"),
+            ["zeta", "alpha", "beta-longer"]
+        );
+        // without a query every score is the same
+        assert_eq!(displayed("[score, path]"), ["alpha", "beta-longer", "zeta"]);
+    }
+
+    #[test]
+    fn test_sort_by_only_orders_unfiltered_matches() {
+        let config = config_from_yaml("");
+        let items = vec![
+            PickerItem::new("/b/rarely".into(), None).with_rank(1),
+            PickerItem::new("/c/never".into(), None),
+            PickerItem::new("/a/often".into(), None).with_rank(20),
+            PickerItem::new("/d/fav".into(), None).with_favorite(true),
+        ];
+        let mut picker =
+            Picker::with_items(items, "> ".into(), &config).with_sort_by(SortBy::Frecency);
+        let displayed = |picker: &mut Picker| {
+            while picker.matcher.tick(10).running {}
+            picker.sort_matches();
+            (0..picker.row_count())
+                .map(|row| {
+                    picker.selection.select(Some(row));
+                    picker.selected_item().unwrap().text.clone()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            displayed(&mut picker),
+            ["/d/fav", "/a/often", "/b/rarely", "/c/never"]
+        );
+        picker.sort_by = SortBy::Path;
+        assert_eq!(
+            displayed(&mut picker),
+            ["/d/fav", "/a/often", "/b/rarely", "/c/never"]
+        );
+        // favorites are injected first in practice, so the order they were added in is kept as is
+        picker.sort_by = SortBy::None;
+        assert_eq!(
+            displayed(&mut picker),
+            ["/b/rarely", "/c/never", "/a/often", "/d/fav"]
+        );
+        // once something is typed, the matcher's order wins
+        picker.sort_by = SortBy::Frecency;
+        picker.set_filter("r");
+        assert_eq!(displayed(&mut picker)[0], "/b/rarely");
+    }
+
+    #[test]
+    fn test_multibyte_filter_editing() {
+        let mut picker = picker();
+        for c in "caféx".chars() {
+            picker.update_filter(c);
+        }
+        picker.backspace();
+        assert_eq!(picker.filter, "café");
+        picker.backspace();
+        assert_eq!(picker.filter, "caf");
+        for c in "éé".chars() {
+            picker.update_filter(c);
+        }
+        picker.move_cursor_left();
+        picker.move_cursor_left();
+        picker.update_filter('ü');
+        assert_eq!(picker.filter, "cafüéé");
+        picker.delete();
+        assert_eq!(picker.filter, "cafüé");
+        picker.move_cursor_right();
+        picker.move_cursor_right();
+        assert_eq!(picker.cursor_pos, 5);
+        picker.set_filter("ñandú");
+        assert_eq!(picker.cursor_pos, 5);
+        picker.backspace();
+        assert_eq!(picker.filter, "ñand");
+    }
+}
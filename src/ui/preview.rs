@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Placeholder in `preview_command` replaced with the path of the highlighted item.
+pub const PREVIEW_PATH_PLACEHOLDER: &str = "{}";
+
+/// Maximum number of lines of preview command output kept per item.
+const MAX_PREVIEW_LINES: usize = 500;
+
+/// How long the preview command can run before it's killed, so a command that hangs doesn't keep every later preview
+/// waiting.
+const PREVIEW_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often to check whether the preview command has exited.
+const PREVIEW_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Preview of the highlighted item in the picker, produced by running a command on a background thread so slow
+/// commands don't hold up scrolling.
+pub struct Preview {
+    pub visible: bool,
+    /// Item the preview was last requested for, so it's only sent to the worker once while it stays highlighted.
+    last_requested: Option<String>,
+    /// Items to run the command for, sent to the worker thread. The worker stops when this is dropped.
+    requests: Sender<String>,
+    /// Output of the finished commands, by item.
+    outputs: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl Preview {
+    pub fn new(command: &str, visible: bool) -> Self {
+        let (requests, pending) = channel();
+        let outputs: Arc<Mutex<HashMap<String, String>>> = Arc::default();
+        let worker_outputs = outputs.clone();
+        let command = command.to_owned();
+        std::thread::spawn(move || run_preview_worker(&command, &pending, &worker_outputs));
+        Self {
+            visible,
+            last_requested: None,
+            requests,
+            outputs,
+        }
+    }
+
+    /// Asks the worker to run the preview command for `path`, unless it already did.
+    pub fn request(&mut self, path: &str) {
+        if self.last_requested.as_deref() == Some(path) || self.output(path).is_some() {
+            return;
+        }
+        self.last_requested = Some(path.to_owned());
+        // the worker only stops once the preview is dropped
+        let _ = self.requests.send(path.to_owned());
+    }
+
+    /// Gets the output of the preview command for `path`, or `None` if it hasn't run yet.
+    pub fn output(&self, path: &str) -> Option<String> {
+        self.outputs
+            .lock()
+            .expect("Preview lock is never poisoned")
+            .get(path)
+            .cloned()
+    }
+}
+
+/// Runs the preview command for the requested items one at a time, until the preview is dropped.
+///
+/// Items requested while a command was running were highlighted since and most likely scrolled past, so only the
+/// latest one is run.
+fn run_preview_worker(
+    template: &str,
+    pending: &Receiver<String>,
+    outputs: &Mutex<HashMap<String, String>>,
+) {
+    while let Ok(mut path) = pending.recv() {
+        while let Ok(newer) = pending.try_recv() {
+            path = newer;
+        }
+        let output = run_preview_command(&preview_command(template, &path), &path, PREVIEW_TIMEOUT);
+        outputs
+            .lock()
+            .expect("Preview lock is never poisoned")
+            .insert(path, output);
+    }
+}
+
+/// Replaces the placeholder in `template` with `path`, quoted so the shell passes it on as a single argument.
+fn preview_command(template: &str, path: &str) -> String {
+    let quoted = format!("'{}'", path.replace('\'', r"'\''"));
+    template.replace(PREVIEW_PATH_PLACEHOLDER, &quoted)
+}
+
+/// Runs `command` in `path` if it's a directory, returning its output or why it couldn't be run. The command is
+/// killed, along with any processes it started, if it runs for longer than `timeout`.
+fn run_preview_command(command: &str, path: &str, timeout: Duration) -> String {
+    let shell = std::env::var("SHELL").unwrap_or("sh".to_string());
+    let mut process = Command::new(shell);
+    process
+        .args(["-c", command])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // a group of its own, so the processes it starts can be killed with it
+        .process_group(0);
+    if Path::new(path).is_dir() {
+        process.current_dir(path);
+    }
+    let mut child = match process.spawn() {
+        Ok(child) => child,
+        Err(e) => return format!("Failed to run preview command `{command}`: {e}"),
+    };
+    // read while the command runs, otherwise it blocks once it fills the pipe
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(PREVIEW_POLL_INTERVAL),
+            _ => {
+                kill_process_group(&mut child);
+                return format!(
+                    "Preview command `{command}` was killed after running for {:?}",
+                    timeout
+                );
+            }
+        }
+    }
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    stdout
+        .lines()
+        .chain(stderr.lines())
+        .take(MAX_PREVIEW_LINES)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+        String::from_utf8_lossy(&output).into_owned()
+    })
+}
+
+/// Kills `child` and every process in its process group.
+fn kill_process_group(child: &mut Child) {
+    // SAFETY: `kill` doesn't touch any memory, and the child is the leader of its own process group
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_command_quotes_path() {
+        assert_eq!(
+            preview_command("ls -A {}", "/home/vinny/it's here"),
+            r"ls -A '/home/vinny/it'\''s here'"
+        );
+        assert_eq!(preview_command("git log -5", "/a"), "git log -5");
+    }
+
+    #[test]
+    fn test_preview_runs_in_background() {
+        let mut preview = Preview::new("printf '%s' {}", true);
+        preview.request("/nonexistent/it's");
+        let start = Instant::now();
+        let output = loop {
+            if let Some(output) = preview.output("/nonexistent/it's") {
+                break output;
+            }
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(output, "/nonexistent/it's");
+        assert_eq!(preview.output("/other"), None);
+    }
+
+    #[test]
+    fn test_preview_only_runs_latest_request() {
+        let mut preview = Preview::new("sleep 0.2; printf '%s' {}", true);
+        preview.request("/a");
+        preview.request("/b");
+        preview.request("/c");
+        let start = Instant::now();
+        while preview.output("/c").is_none() {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(preview.output("/b"), None);
+    }
+
+    #[test]
+    fn test_preview_command_is_killed_after_timeout() {
+        let start = Instant::now();
+        let output =
+            run_preview_command("sleep 10 | cat", "/nonexistent", Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(output.contains("was killed"), "{output}");
+    }
+}
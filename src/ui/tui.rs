@@ -1,4 +1,5 @@
-use std::{io, panic};
+use std::io::{self, IsTerminal, Write};
+use std::panic;
 
 use anyhow::Result;
 use crossterm::{
@@ -9,59 +10,212 @@ use ratatui::backend::CrosstermBackend;
 use ratatui::Terminal;
 use std::time::Duration;
 
-use crate::ui::picker::Picker;
+use crate::config::TwmGlobal;
+use crate::ui::picker::{Picker, PickerValue};
 
+use super::event::Event;
 use super::EventHandler;
-pub type CrosstermTerminal = ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stderr>>;
+pub type CrosstermTerminal = ratatui::Terminal<ratatui::backend::CrosstermBackend<Box<dyn Write>>>;
 
+/// Setting this environment variable to `1` makes any attempt to show the picker fail, for scripts and CI.
+const NO_TUI_ENV_VAR: &str = "TWM_NO_TUI";
+
+/// The terminal UI used for pickers.
+///
+/// The terminal isn't touched until something is actually drawn, so flows that never end up showing a picker
+/// (e.g. `twm -p <PATH>`) work without an interactive terminal.
+///
+/// The TUI can be suspended and entered again, e.g. to print something between two pickers. The event handler and the
+/// panic hook are only set up the first time, so there's only ever one thread reading the terminal's events.
 pub struct Tui {
-    terminal: CrosstermTerminal,
-    pub events: EventHandler,
+    terminal: Option<CrosstermTerminal>,
+    events: Option<EventHandler>,
+    mouse_capture: bool,
+    open_terminal: fn(bool) -> Result<CrosstermTerminal>,
+    panic_hook_installed: bool,
 }
 
 impl Tui {
-    pub fn start() -> Result<Self> {
-        let backend = CrosstermBackend::new(std::io::stderr());
-        let terminal = Terminal::new(backend)?;
-        let events = EventHandler::new(Duration::from_millis(15));
-        let mut tui = Self::new(terminal, events);
-        tui.enter()?;
-        Ok(tui)
+    pub fn new(config: &TwmGlobal) -> Self {
+        Self {
+            terminal: None,
+            events: None,
+            mouse_capture: config.mouse_capture,
+            open_terminal: Self::open_terminal,
+            panic_hook_installed: false,
+        }
     }
 
-    pub fn new(terminal: CrosstermTerminal, events: EventHandler) -> Self {
-        Self { terminal, events }
+    fn enter(&mut self) -> Result<&mut CrosstermTerminal> {
+        if let Some(ref mut terminal) = self.terminal {
+            return Ok(terminal);
+        }
+
+        let terminal = (self.open_terminal)(self.mouse_capture)?;
+        if !self.panic_hook_installed {
+            let panic_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |panic| {
+                Self::reset(&mut io::stderr()).expect("Failed to reset the terminal");
+                panic_hook(panic);
+            }));
+            self.panic_hook_installed = true;
+        }
+        self.events
+            .get_or_insert_with(|| EventHandler::new(Duration::from_millis(15)));
+        Ok(self.terminal.insert(terminal))
     }
 
-    pub fn enter(&mut self) -> Result<()> {
+    /// Puts the terminal in raw mode on the alternate screen, and returns it cleared for drawing the picker.
+    fn open_terminal(mouse_capture: bool) -> Result<CrosstermTerminal> {
+        if std::env::var(NO_TUI_ENV_VAR).is_ok_and(|v| v == "1") {
+            anyhow::bail!(
+                "twm needs to show a picker, but the TUI is disabled by {NO_TUI_ENV_VAR}=1. Use `-p/--path` to open a workspace without the picker."
+            );
+        }
+        if !io::stderr().is_terminal() {
+            anyhow::bail!(
+                "twm needs to show a picker, but stderr is not a terminal. Use `-p/--path` to open a workspace without the picker."
+            );
+        }
+
+        let backend = CrosstermBackend::new(Box::new(io::stderr()) as Box<dyn Write>);
+        let mut terminal = Terminal::new(backend)?;
+
         terminal::enable_raw_mode()?;
-        crossterm::execute!(io::stderr(), EnterAlternateScreen, EnableMouseCapture)?;
+        crossterm::execute!(io::stderr(), EnterAlternateScreen)?;
+        if mouse_capture {
+            crossterm::execute!(io::stderr(), EnableMouseCapture)?;
+        }
 
-        let panic_hook = panic::take_hook();
-        panic::set_hook(Box::new(move |panic| {
-            Self::reset().expect("Failed to reset the terminal");
-            panic_hook(panic);
-        }));
+        terminal.hide_cursor()?;
+        terminal.clear()?;
+        Ok(terminal)
+    }
 
-        self.terminal.hide_cursor()?;
-        self.terminal.clear()?;
+    /// Leaves raw mode and the alternate screen drawn to with `writer`.
+    fn reset(writer: &mut impl Write) -> Result<()> {
+        terminal::disable_raw_mode()?;
+        crossterm::execute!(writer, LeaveAlternateScreen, DisableMouseCapture)?;
         Ok(())
     }
 
-    fn reset() -> Result<()> {
-        terminal::disable_raw_mode()?;
-        crossterm::execute!(io::stderr(), LeaveAlternateScreen, DisableMouseCapture)?;
+    /// Creates a TUI that's already entered, drawing to nowhere and reading `events` instead of the terminal's. The
+    /// events run out after the last one, like when the terminal goes away.
+    #[cfg(test)]
+    pub fn headless(events: Vec<Event>) -> Self {
+        let open_terminal = |_| {
+            let backend = CrosstermBackend::new(Box::new(io::sink()) as Box<dyn Write>);
+            let viewport = ratatui::Viewport::Fixed(ratatui::layout::Rect::new(0, 0, 80, 24));
+            Ok(Terminal::with_options(
+                backend,
+                ratatui::TerminalOptions { viewport },
+            )?)
+        };
+        Self {
+            terminal: Some(open_terminal(false).expect("Failed to create a headless terminal")),
+            events: Some(EventHandler::from_events(events)),
+            mouse_capture: false,
+            open_terminal,
+            // there's no real terminal to restore
+            panic_hook_installed: true,
+        }
+    }
+
+    /// Whether the TUI currently has the terminal in raw mode on the alternate screen.
+    #[cfg(test)]
+    pub fn is_entered(&self) -> bool {
+        self.terminal.is_some()
+    }
+
+    /// Leaves raw mode and the alternate screen, so output printed next stays visible in the terminal. Drawing a
+    /// picker afterwards enters the TUI again.
+    pub fn suspend(&mut self) -> Result<()> {
+        if let Some(mut terminal) = self.terminal.take() {
+            Self::reset(terminal.backend_mut())?;
+            terminal.show_cursor()?;
+        }
         Ok(())
     }
 
-    pub fn exit(&mut self) -> Result<()> {
-        Self::reset()?;
-        self.terminal.show_cursor()?;
+    pub fn draw<T: PickerValue>(&mut self, picker: &mut Picker<T>) -> Result<()> {
+        self.enter()?.draw(|frame| picker.render(frame))?;
         Ok(())
     }
 
-    pub fn draw(&mut self, picker: &mut Picker) -> Result<()> {
-        self.terminal.draw(|frame| picker.render(frame))?;
+    /// Copies `text` to the system clipboard with an OSC 52 escape sequence.
+    ///
+    /// This works over SSH, but only in terminals that support OSC 52. Inside tmux, `set-clipboard` has to be
+    /// enabled for the sequence to reach the terminal.
+    pub fn copy_to_clipboard(&mut self, text: &str) -> Result<()> {
+        self.enter()?;
+        let mut stderr = io::stderr();
+        write!(stderr, "\x1b]52;c;{}\x07", base64_encode(text.as_bytes()))?;
+        stderr.flush()?;
         Ok(())
     }
+
+    pub fn next_event(&mut self) -> Result<Event> {
+        self.enter()?;
+        self.events
+            .as_ref()
+            .expect("Events are set up when entering the TUI")
+            .next()
+    }
+}
+
+/// Encodes `bytes` as standard padded base64, as expected by OSC 52.
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+impl Drop for Tui {
+    /// Restores the terminal if the TUI is dropped without being suspended, e.g. when an error is returned early.
+    fn drop(&mut self) {
+        let _ = self.suspend();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyEvent};
+
+    #[test]
+    fn test_reentering_keeps_the_events() {
+        let key = |c| Event::Key(KeyEvent::from(KeyCode::Char(c)));
+        let mut tui = Tui::headless(vec![key('a'), key('b')]);
+        assert!(matches!(tui.next_event().unwrap(), Event::Key(e) if e.code == KeyCode::Char('a')));
+        tui.suspend().unwrap();
+        assert!(!tui.is_entered());
+        // a second event handler would race the first one for the terminal's keys
+        assert!(matches!(tui.next_event().unwrap(), Event::Key(e) if e.code == KeyCode::Char('b')));
+        assert!(tui.is_entered());
+        assert!(tui.next_event().is_err());
+    }
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"/home/vinny"), "L2hvbWUvdmlubnk=");
+    }
 }
@@ -1,20 +1,42 @@
 use enum_dispatch::enum_dispatch;
-use std::path::Path;
+use regex::{Regex, RegexSet};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct WorkspaceDefinition {
     pub name: String,
     pub conditions: Vec<WorkspaceConditionEnum>,
     pub default_layout: Option<String>,
+    pub initial_command: Option<String>,
+    pub window_name: Option<String>,
 }
 
 #[enum_dispatch]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "condition")]
 pub enum WorkspaceConditionEnum {
+    #[serde(rename = "has_any_file")]
     HasAnyFileCondition,
+    #[serde(rename = "has_all_files")]
     HasAllFilesCondition,
+    #[serde(rename = "missing_any_file")]
     MissingAnyFileCondition,
+    #[serde(rename = "missing_all_files")]
     MissingAllFilesCondition,
+    #[serde(rename = "git_branch")]
+    GitBranchCondition,
+    #[serde(rename = "max_entries")]
+    MaxEntriesCondition,
+    #[serde(rename = "has_any_file_matching")]
+    RegexCondition,
+    #[serde(rename = "script")]
+    ScriptCondition,
+    #[serde(rename = "has_command_succeed")]
+    ExecCondition,
+    #[serde(rename = "none")]
     NullCondition,
 }
 
@@ -23,7 +45,7 @@ pub trait WorkspaceCondition {
     fn meets_condition(&self, path: &Path) -> bool;
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct HasAnyFileCondition {
     pub files: Vec<String>,
 }
@@ -39,7 +61,7 @@ impl WorkspaceCondition for HasAnyFileCondition {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct HasAllFilesCondition {
     pub files: Vec<String>,
 }
@@ -55,7 +77,7 @@ impl WorkspaceCondition for HasAllFilesCondition {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct MissingAnyFileCondition {
     pub files: Vec<String>,
 }
@@ -71,7 +93,7 @@ impl WorkspaceCondition for MissingAnyFileCondition {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct MissingAllFilesCondition {
     pub files: Vec<String>,
 }
@@ -87,9 +109,208 @@ impl WorkspaceCondition for MissingAllFilesCondition {
     }
 }
 
+/// A condition that matches git repositories whose currently checked out branch matches `pattern`.
+///
+/// The branch is read straight out of `.git/HEAD` rather than by shelling out to git, since this
+/// gets checked for every directory visited during the search.
+#[derive(Debug, Clone, Serialize)]
+pub struct GitBranchCondition {
+    pub pattern: String,
+    #[serde(skip)]
+    regex: Regex,
+}
+
+impl GitBranchCondition {
+    pub fn new(pattern: String) -> Result<Self, regex::Error> {
+        let regex = Regex::new(&pattern)?;
+        Ok(Self { pattern, regex })
+    }
+}
+
+impl PartialEq for GitBranchCondition {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Eq for GitBranchCondition {}
+
+impl WorkspaceCondition for GitBranchCondition {
+    fn meets_condition(&self, path: &Path) -> bool {
+        match get_git_branch(path) {
+            Some(branch) => self.regex.is_match(&branch),
+            None => false,
+        }
+    }
+}
+
+/// Finds the git directory for a repository at `path`.
+///
+/// `.git` is usually a directory, but for worktrees and submodules it's a file containing `gitdir: <path>`.
+fn find_git_dir(path: &Path) -> Option<PathBuf> {
+    let dot_git = path.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    let contents = std::fs::read_to_string(&dot_git).ok()?;
+    let git_dir = contents.lines().next()?.strip_prefix("gitdir:")?.trim();
+    Some(path.join(git_dir))
+}
+
+/// Gets the name of the branch currently checked out in the git repository at `path`.
+///
+/// Returns `None` if `path` isn't a git repository or `HEAD` is detached.
+pub fn get_git_branch(path: &Path) -> Option<String> {
+    let head = std::fs::read_to_string(find_git_dir(path)?.join("HEAD")).ok()?;
+    parse_git_head(&head).map(str::to_owned)
+}
+
+fn parse_git_head(head: &str) -> Option<&str> {
+    head.trim().strip_prefix("ref: refs/heads/")
+}
+
+/// Gets when the workspace at `path` was last modified, going by its directory and its git `HEAD` if it's a git
+/// repository.
+///
+/// This is only an approximation since files further inside the workspace aren't checked, but walking the whole
+/// workspace for every match would be far too slow.
+pub fn get_last_modified(path: &Path) -> Option<SystemTime> {
+    let dir_modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let head_modified = find_git_dir(path)
+        .and_then(|git_dir| std::fs::metadata(git_dir.join("HEAD")).ok())
+        .and_then(|m| m.modified().ok());
+    dir_modified.max(head_modified)
+}
+
+/// A condition that matches directories with at most `max` immediate entries.
+///
+/// Only the directory's direct children are counted, so this is a single cheap `read_dir`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MaxEntriesCondition {
+    pub max: usize,
+}
+
+impl WorkspaceCondition for MaxEntriesCondition {
+    fn meets_condition(&self, path: &Path) -> bool {
+        match std::fs::read_dir(path) {
+            // no need to count past the limit
+            Ok(entries) => entries.take(self.max + 1).count() <= self.max,
+            Err(_) => false,
+        }
+    }
+}
+
+/// A condition that matches directories containing at least one entry whose name matches any of `patterns`.
+///
+/// The patterns are compiled into a single set up front, since this gets checked for every directory visited during
+/// the search.
+#[derive(Debug, Clone, Serialize)]
+pub struct RegexCondition {
+    pub patterns: Vec<String>,
+    #[serde(skip)]
+    regexes: RegexSet,
+}
+
+impl RegexCondition {
+    pub fn new(patterns: Vec<String>) -> Result<Self, regex::Error> {
+        let regexes = RegexSet::new(&patterns)?;
+        Ok(Self { patterns, regexes })
+    }
+}
+
+impl PartialEq for RegexCondition {
+    fn eq(&self, other: &Self) -> bool {
+        self.patterns == other.patterns
+    }
+}
+
+impl Eq for RegexCondition {}
+
+impl WorkspaceCondition for RegexCondition {
+    fn meets_condition(&self, path: &Path) -> bool {
+        let Ok(entries) = std::fs::read_dir(path) else {
+            return false;
+        };
+        entries.flatten().any(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| self.regexes.is_match(name))
+        })
+    }
+}
+
+/// How long a script or command run by a condition can run before it is killed and the directory is considered not to
+/// match.
+const CONDITION_PROCESS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often to check whether a script or command run by a condition has exited.
+const CONDITION_PROCESS_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Runs `command` in the directory `path`, returning whether it exited successfully within
+/// `CONDITION_PROCESS_TIMEOUT`.
+fn condition_process_succeeds(command: &mut Command, path: &Path) -> bool {
+    let Ok(mut child) = command
+        .current_dir(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        return false;
+    };
+    let deadline = Instant::now() + CONDITION_PROCESS_TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return status.success(),
+            Ok(None) if Instant::now() < deadline => {
+                std::thread::sleep(CONDITION_PROCESS_POLL_INTERVAL)
+            }
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return false;
+            }
+        }
+    }
+}
+
+/// Runs a user script in the directory, which matches if the script exits successfully.
+///
+/// This starts a process for every directory checked, so it is always checked after the other conditions of a
+/// workspace definition to run it as rarely as possible.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScriptCondition {
+    pub path: String,
+}
+
+impl WorkspaceCondition for ScriptCondition {
+    fn meets_condition(&self, path: &Path) -> bool {
+        condition_process_succeeds(Command::new(&self.path).arg(path), path)
+    }
+}
+
+/// Runs shell commands in the directory, which matches if every command exits successfully.
+///
+/// Like `ScriptCondition`, this starts processes for every directory checked, so it is checked after the other
+/// conditions of a workspace definition.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExecCondition {
+    pub commands: Vec<String>,
+}
+
+impl WorkspaceCondition for ExecCondition {
+    fn meets_condition(&self, path: &Path) -> bool {
+        let shell = std::env::var("SHELL").unwrap_or("sh".to_string());
+        self.commands.iter().all(|command| {
+            condition_process_succeeds(Command::new(&shell).args(["-c", command]), path)
+        })
+    }
+}
+
 /// A condition that always returns true, used as a default condition if no others
 /// are specified.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct NullCondition {}
 
 impl WorkspaceCondition for NullCondition {
@@ -103,15 +324,99 @@ pub fn path_meets_workspace_conditions(path: &Path, conditions: &[WorkspaceCondi
     conditions.iter().all(|c| c.meets_condition(path))
 }
 
+#[inline(always)]
+pub fn get_workspace_definition_for_path<'a>(
+    path: &Path,
+    workspace_definitions: &'a [WorkspaceDefinition],
+) -> Option<&'a WorkspaceDefinition> {
+    workspace_definitions
+        .iter()
+        .find(|d| path_meets_workspace_conditions(path, &d.conditions))
+}
+
 #[inline(always)]
 pub fn get_workspace_type_for_path<'a>(
     path: &Path,
     workspace_definitions: &'a [WorkspaceDefinition],
 ) -> Option<&'a str> {
-    for workspace_definition in workspace_definitions {
-        if path_meets_workspace_conditions(path, &workspace_definition.conditions) {
-            return Some(&workspace_definition.name);
-        }
+    get_workspace_definition_for_path(path, workspace_definitions).map(|d| d.name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestDir;
+
+    #[test]
+    fn test_parse_git_head() {
+        assert_eq!(parse_git_head("ref: refs/heads/main\n"), Some("main"));
+        assert_eq!(
+            parse_git_head("ref: refs/heads/release/1.0\n"),
+            Some("release/1.0")
+        );
+        assert_eq!(
+            parse_git_head("284cf3a5b0c1a4e7e1f4c2a9d3b6e8f0a1b2c3d4\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_get_last_modified() {
+        let dir = std::env::temp_dir();
+        assert!(get_last_modified(&dir).is_some());
+        assert!(get_last_modified(Path::new("/definitely/not/a/dir")).is_none());
+    }
+
+    #[test]
+    fn test_git_branch_condition_non_git_dir() {
+        let condition = GitBranchCondition::new(".*".into()).unwrap();
+        assert!(!condition.meets_condition(Path::new("/")));
+    }
+
+    #[test]
+    fn test_regex_condition() {
+        let dir = TestDir::new("regex-condition");
+        std::fs::write(dir.join("app.sln"), "").unwrap();
+        let matches = |patterns: &[&str]| {
+            RegexCondition::new(patterns.iter().map(|p| p.to_string()).collect())
+                .unwrap()
+                .meets_condition(&dir)
+        };
+        assert!(matches(&[r"\.sln$"]));
+        assert!(matches(&[r"^Cargo\.", r"^app\."]));
+        assert!(!matches(&[r"^Cargo\."]));
+        assert!(!matches(&[]));
+        assert!(RegexCondition::new(vec!["(".into()]).is_err());
+    }
+
+    #[test]
+    fn test_script_condition() {
+        let dir = std::env::temp_dir();
+        let matches = |script: &str| {
+            ScriptCondition {
+                path: script.into(),
+            }
+            .meets_condition(&dir)
+        };
+        assert!(matches("true"));
+        assert!(!matches("false"));
+        assert!(!matches("/definitely/not/a/script"));
+    }
+
+    #[test]
+    fn test_exec_condition() {
+        let dir = TestDir::new("exec-condition");
+        std::fs::write(dir.join("marker"), "").unwrap();
+        let matches = |commands: &[&str]| {
+            ExecCondition {
+                commands: commands.iter().map(|c| c.to_string()).collect(),
+            }
+            .meets_condition(&dir)
+        };
+        assert!(matches(&["test -f marker"]));
+        assert!(matches(&["true", "test -f marker && exit 0"]));
+        assert!(!matches(&["true", "test -f missing"]));
+        // commands running past the timeout are killed
+        assert!(!matches(&["sleep 5"]));
     }
-    None
 }